@@ -6,17 +6,29 @@ use axum::http::{Request, Response, StatusCode};
 use axum::body::Body;
 use tower::{Layer, Service};
 
-use shared::tokens::jwt::{AccessTokenPayload, TokenEncoder};
+use shared::tokens::{jwt::{AccessTokenPayload, ServiceTokenPayload, TokenEncoder}, keys};
 use tracing::info;
 
+use crate::revocation::RevocationStore;
+
 #[derive(Clone, Default)]
-pub struct AuthAccessLayer {pass_unauthorized: bool}
+pub struct AuthAccessLayer {pass_unauthorized: bool, revocation: Option<RevocationStore>, service_audience: Option<String>}
 impl AuthAccessLayer {
     pub fn allow_guests() -> Self {
-        Self {pass_unauthorized: true}
+        Self {pass_unauthorized: true, revocation: None, service_audience: None}
     }
     pub fn only_authorized() -> Self {
-        Self {pass_unauthorized: false}
+        Self {pass_unauthorized: false, revocation: None, service_audience: None}
+    }
+    /// Also accepts service tokens issued for `audience`, handlers get [`ServiceTokenPayload`] instead of [`AccessTokenPayload`]
+    pub fn accept_services(mut self, audience: impl Into<String>) -> Self {
+        self.service_audience = Some(audience.into());
+        self
+    }
+    /// Rejects revoked tokens (treated as missing ones)
+    pub fn with_revocation(mut self, revocation: RevocationStore) -> Self {
+        self.revocation = Some(revocation);
+        self
     }
 }
 
@@ -27,6 +39,8 @@ impl<S> Layer<S> for AuthAccessLayer {
     fn layer(&self, inner: S) -> Self::Service {
         AuthAccessService {
             pass_unauthorized: self.pass_unauthorized.clone(),
+            revocation: self.revocation.clone(),
+            service_audience: self.service_audience.clone(),
             service: inner
         }
     }
@@ -34,7 +48,9 @@ impl<S> Layer<S> for AuthAccessLayer {
 
 pub struct AuthAccessService<S> {
     service: S,
-    pass_unauthorized: bool
+    pass_unauthorized: bool,
+    revocation: Option<RevocationStore>,
+    service_audience: Option<String>,
 }
 
 impl<S> Clone for AuthAccessService<S>
@@ -44,6 +60,8 @@ where
     fn clone(&self) -> Self {
         Self {
             pass_unauthorized: self.pass_unauthorized.clone(),
+            revocation: self.revocation.clone(),
+            service_audience: self.service_audience.clone(),
             service: self.service.clone(),
         }
     }
@@ -84,28 +102,49 @@ where
                     .map(|(_, v)| v)
             });
         let token_value = auth_header.or(query_token);
-        let token : Option<AccessTokenPayload> = if let Some(token_value) = token_value {
-            // if let Some(token) = header_value.strip_prefix("Bearer ") {
-                TokenEncoder::decode_access(token_value.to_string())
-            // } else {None}
-        } else {None};
-        if let Some(decoded_token) = token {
-            info!("Auth passed. User: {}", decoded_token.user);
-            req.extensions_mut().insert(decoded_token);
-            let fut = self.service.call(req);
-            Box::pin(fut)
-        } else {
-            if self.pass_unauthorized {
+        let service_audience = self.service_audience.clone();
+        let pass_unauthorized = self.pass_unauthorized;
+        let revocation = self.revocation.clone();
+        // the ready service has to handle this request, the clone waits for the next one
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
+        Box::pin(async move {
+            if let Some(token_value) = &token_value {
+                // signed with a key rotated in after the last keyring load
+                keys::refresh_for(token_value).await;
+            }
+            let token : Option<AccessTokenPayload> = token_value.as_ref().and_then(|t| TokenEncoder::decode_access(t.to_string()));
+            let service_token : Option<ServiceTokenPayload> = match (&token, &token_value, &service_audience) {
+                (None, Some(token_value), Some(audience)) => TokenEncoder::decode_service(token_value, audience),
+                _ => None,
+            };
+            let token = match (token, &revocation) {
+                (Some(token), Some(revocation)) if revocation.is_revoked(&token).await => {
+                    info!("Token of {} is revoked", token.user);
+                    None
+                }
+                (token, _) => token,
+            };
+            if let Some(decoded_token) = token {
+                info!("Auth passed. User: {}", decoded_token.user);
+                req.extensions_mut().insert(decoded_token);
+                return inner.call(req).await
+            }
+            if let Some(service_token) = service_token {
+                info!("Auth passed. Service: {}", service_token.sub);
+                req.extensions_mut().insert(service_token);
+                return inner.call(req).await
+            }
+            if pass_unauthorized {
                 info!("Auth failed, passing unauthorized as a guest.");
-                let fut = self.service.call(req);
-                return Box::pin(fut)
+                return inner.call(req).await
             }
             info!("Auth failed!");
-            Box::pin(async move {Ok(Response::builder()
+            Ok(Response::builder()
                     .status(StatusCode::UNAUTHORIZED)
                     .body(Body::from("Unauthorized"))
-                    .unwrap())})
-        }
+                    .unwrap())
+        })
     }
 }
 
@@ -2,6 +2,7 @@ use shared::env_config;
 
 pub mod auth;
 pub mod logging;
+pub mod revocation;
 pub mod rustperms;
 
 pub use uuid;
@@ -17,5 +18,12 @@ env_config!(
     ".cfg" => CFG = EnvCfg{
         REDIS_REFRESH_TOKEN_LIFETIME : u64 = 30 * 24 * 60 * 60, // 30 days
         REDIS_MAX_LIVE_SESSIONS : usize = 5,
+        // 0 disables local permission decision cache
+        PERMISSION_CACHE_TTL_MS : u64 = 0,
+        PERMISSION_CACHE_CAPACITY : usize = 10_000,
+        // max body size buffered for {body.*} permission placeholders
+        PERMISSION_BODY_LIMIT : usize = 1024 * 1024,
+        REVOCATION_CACHE_TTL_SECS : u64 = 60,
+        REVOCATION_CACHE_CAPACITY : usize = 100_000,
     }
 );
\ No newline at end of file
@@ -2,11 +2,11 @@ use std::fmt::Display;
 
 use axum::{
     body::Body,
-    http::{HeaderMap, Request},
+    http::{HeaderMap, HeaderValue, Request},
     middleware::Next,
     response::Response,
 };
-use shared::utils::{hash::hash_fingerprint, header::{get_user_agent, get_user_fingerprint, get_user_ip}};
+use shared::utils::{hash::hash_fingerprint, header::{get_user_agent, get_user_fingerprint, get_user_ip}, request_id::{request_id_or_new, with_request_id, REQUEST_ID_HEADER}, telemetry::continue_trace};
 use tracing::{info, Instrument, Span};
 
 #[derive(Clone)]
@@ -44,6 +44,24 @@ pub async fn logging_middleware(mut req: Request<Body>, next: Next) -> Response
     response
 }
 
+/// Runs the request in a span with its `request_id`, taken from `X-Request-Id` or generated.
+/// The id is passed on to the handlers (header and task local) and returned in the response,
+/// the span continues the trace of the caller if it sent `traceparent`.
+pub async fn request_id_middleware(make_span: impl FnOnce(&str) -> Span, mut req: Request<Body>, next: Next) -> Response {
+    let id = request_id_or_new(req.headers().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+    let header = HeaderValue::from_str(&id).ok();
+    if let Some(header) = &header {
+        req.headers_mut().insert(REQUEST_ID_HEADER, header.clone());
+    }
+    let span = make_span(&id);
+    continue_trace(&span, |name| req.headers().get(name).and_then(|v| v.to_str().ok()));
+    let mut response = with_request_id(id, next.run(req)).instrument(span).await;
+    if let Some(header) = header {
+        response.headers_mut().insert(REQUEST_ID_HEADER, header);
+    }
+    response
+}
+
 #[macro_export]
 macro_rules! make_unique_span {
     ($name:ident) => {
@@ -60,9 +78,8 @@ macro_rules! make_unique_span {
 macro_rules! layer_with_unique_span {
     ($prefix:expr) => {
         async |req: axum::extract::Request<axum::body::Body>, next: axum::middleware::Next| -> axum::response::Response {
-            $crate::make_unique_span!($prefix, span);
-            let response = $crate::tracing::Instrument::instrument(next.run(req), span).await;
-            response
+            let make_span = |id: &str| $crate::tracing::info_span!($prefix, "request_id" = %id);
+            $crate::logging::request_id_middleware(make_span, req, next).await
         }
     };
     () => {
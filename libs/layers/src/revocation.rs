@@ -0,0 +1,145 @@
+use std::{collections::HashMap, hash::Hash, sync::{Arc, RwLock}, time::{Duration, Instant}};
+
+use futures::StreamExt;
+use message_broker::revocation::types::RevocationEvent;
+use redis_utils::{redis::RedisConn, redis_revocation::RedisRevocation};
+use shared::{tokens::jwt::AccessTokenPayload, uuid::Uuid};
+use tracing::{error, info, warn};
+
+use crate::CFG;
+
+#[derive(Debug)]
+struct TtlMap<K, V> {
+    entries: HashMap<K, (V, Instant)>,
+}
+
+impl<K: Hash + Eq, V: Copy> TtlMap<K, V> {
+    fn get(&self, key: &K, ttl: Duration) -> Option<V> {
+        self.entries.get(key).filter(|(_, at)| at.elapsed() < ttl).map(|(v, _)| *v)
+    }
+
+    fn insert(&mut self, key: K, value: V, ttl: Duration) {
+        if self.entries.len() >= CFG.REVOCATION_CACHE_CAPACITY {
+            self.entries.retain(|_, (_, at)| at.elapsed() < ttl);
+            if self.entries.len() >= CFG.REVOCATION_CACHE_CAPACITY {
+                self.entries.clear();
+            }
+        }
+        self.entries.insert(key, (value, Instant::now()));
+    }
+}
+
+/// Access token revocation checks for [`crate::auth::AuthAccessLayer`]
+///
+/// Watermarks and revoked sessions are read from redis and cached locally,
+/// revocations published by auth are applied to the cache immediately.
+#[derive(Clone)]
+pub struct RevocationStore {
+    redis: RedisConn,
+    users: Arc<RwLock<TtlMap<Uuid, Option<i64>>>>,
+    sessions: Arc<RwLock<TtlMap<Uuid, bool>>>,
+    ttl: Duration,
+}
+
+impl RevocationStore {
+    /// Uses `redis_utils` redis and keeps the cache subscribed to `TOKEN_REVOCATION_NATS_EVENT`
+    pub async fn connect() -> anyhow::Result<Self> {
+        let env = &message_broker::ENV;
+        let client = async_nats::connect(format!("nats://{}:{}", env.NATS_URL, env.NATS_PORT)).await?;
+        let store = Self {
+            redis: RedisConn::default().await,
+            users: Arc::new(RwLock::new(TtlMap{entries: HashMap::new()})),
+            sessions: Arc::new(RwLock::new(TtlMap{entries: HashMap::new()})),
+            ttl: Duration::from_secs(CFG.REVOCATION_CACHE_TTL_SECS),
+        };
+        let listener = store.clone();
+        tokio::spawn(async move {
+            loop {
+                match client.subscribe(env.TOKEN_REVOCATION_NATS_EVENT.clone()).await {
+                    Ok(subscriber) => listener.listen(subscriber).await,
+                    Err(e) => error!("Can't subscribe to token revocations: {e}"),
+                }
+                // revocations may be missed while resubscribing
+                listener.clear();
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+        Ok(store)
+    }
+
+    async fn listen(&self, mut subscriber: async_nats::Subscriber) {
+        info!("Revocation store subscribed to token revocations");
+        while let Some(message) = subscriber.next().await {
+            match RevocationEvent::decode(&message.payload) {
+                Ok(RevocationEvent::User{user, before}) => if let Ok(user) = Uuid::parse_str(&user) {
+                    self.set_user(user, Some(before));
+                }
+                Ok(RevocationEvent::Session{sid}) => if let Ok(sid) = Uuid::parse_str(&sid) {
+                    self.set_session(sid, true);
+                }
+                Err(e) => {
+                    warn!("Can't decode revocation event, dropping revocation cache: {e}");
+                    self.clear();
+                }
+            }
+        }
+        warn!("Token revocation subscription ended");
+    }
+
+    fn clear(&self) {
+        if let Ok(mut users) = self.users.write() {users.entries.clear()}
+        if let Ok(mut sessions) = self.sessions.write() {sessions.entries.clear()}
+    }
+
+    fn set_user(&self, user: Uuid, before: Option<i64>) {
+        if let Ok(mut users) = self.users.write() {
+            // watermarks only move forward
+            let before = before.max(users.get(&user, self.ttl).flatten());
+            users.insert(user, before, self.ttl);
+        }
+    }
+
+    fn set_session(&self, sid: Uuid, revoked: bool) {
+        if let Ok(mut sessions) = self.sessions.write() {
+            let revoked = revoked || sessions.get(&sid, self.ttl).unwrap_or(false);
+            sessions.insert(sid, revoked, self.ttl);
+        }
+    }
+
+    /// Tokens issued before the user's watermark or bound to a revoked session are revoked.
+    /// Redis errors let the token through, it expires soon anyway.
+    pub async fn is_revoked(&self, token: &AccessTokenPayload) -> bool {
+        let cached = self.users.read().ok().and_then(|users| users.get(&token.user, self.ttl));
+        let before = match cached {
+            Some(before) => before,
+            None => match self.redis.get_user_revoked_before(&token.user).await {
+                Ok(before) => {
+                    self.set_user(token.user, before);
+                    before
+                }
+                Err(e) => {
+                    error!("Can't read revocation watermark: {e}");
+                    None
+                }
+            },
+        };
+        if before.is_some_and(|before| token.iat < before) {
+            return true;
+        }
+        let Some(sid) = token.sid else {return false};
+        let cached = self.sessions.read().ok().and_then(|sessions| sessions.get(&sid, self.ttl));
+        match cached {
+            Some(revoked) => revoked,
+            None => match self.redis.is_session_revoked(&sid).await {
+                Ok(revoked) => {
+                    self.set_session(sid, revoked);
+                    revoked
+                }
+                Err(e) => {
+                    error!("Can't read session revocation: {e}");
+                    false
+                }
+            },
+        }
+    }
+}
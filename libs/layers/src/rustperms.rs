@@ -1,9 +1,9 @@
 use axum::{body::Body, extract::{FromRequestParts, Path}, http::request::Parts, RequestPartsExt};
 use once_cell::sync::Lazy;
 use regex::Regex;
-use rustperms_nodes::proto::{rustperms_replica_proto_client::RustpermsReplicaProtoClient, CheckPermReply, CheckPermRequest};
-use tracing::{error, info};
-use std::{collections::HashMap};
+use rustperms_nodes::{proto::{CheckPermsReply, CheckPermsRequest}, ReplicaClient};
+use tracing::{error, info, warn};
+use std::{collections::HashMap, fmt::Display, time::Duration};
 use std::task::{Context, Poll};
 use std::pin::Pin;
 use std::future::Future;
@@ -13,6 +13,17 @@ use shared::utils::IntoKey;
 
 use shared::{tokens::jwt::AccessTokenPayload};
 
+use crate::CFG;
+
+pub mod cache;
+pub mod expr;
+pub mod filter;
+pub mod handle;
+pub mod sources;
+use cache::PermissionCache;
+use expr::PermissionExpr;
+use sources::{CompleteError, PlaceholderSources};
+
 // Why we can't directly get a Path<Vec<(String, String)>> in middleware?
 // .layer(from_extractor::<ExtractPath>()))
 // At least path_extractor -> extensions -> middleware_layer works: 
@@ -24,37 +35,74 @@ pub enum PermissionKind {
 }
 
 impl PermissionKind {
-    pub fn try_complete(self, kvs: Option<&ExtractedPathKV>, user_id: &str) -> Option<String> {
+    pub fn parse(permission: &str) -> anyhow::Result<Self> {
+        if permission.contains('*') {
+            anyhow::bail!("Can't use wildcard in permission definition `{}`", permission);
+        }
+        let c = REGEX.captures_iter(permission)
+            .filter_map(|m| m.get(1).map(|v| (format!("{{{}}}", v.as_str()), v.as_str().to_string())))
+            .collect::<Vec<(String, String)>>();
+        if permission.matches('{').count() != c.len() || permission.matches('}').count() != c.len() {
+            anyhow::bail!("Malformed placeholder in permission `{}`", permission);
+        }
+        Ok(if !c.is_empty() {
+            Self::Pattern { incomplete: permission.to_string(), replace: c }
+        } else {
+            Self::NoPat { permission: permission.to_string() }
+        })
+    }
+
+    pub fn try_complete(&self, sources: &PlaceholderSources) -> Result<String, CompleteError> {
         match self {
-            Self::NoPat { permission } => Some(permission),
+            Self::NoPat { permission } => Ok(permission.clone()),
             Self::Pattern { incomplete, replace } => {
                 let mut permission = incomplete.clone();
-                if user_id != "" {
-                    permission = permission.replace("{from_access}", user_id);
-                }
-                info!("Extracted: {:?}", kvs);
-                if let Some(ExtractedPathKV(kvs)) = kvs {
-                    for (in_brackets, without_brackets) in replace {
-                        if let Some(v) = kvs.get(&without_brackets) {
-                            permission = permission.replace(&in_brackets, v);
-                        }
-                    }
-                }
-                if permission.is_complete() {Some(permission)} else {
-                    error!("Can't complete permission! Pattern: {} ~> {}", incomplete, permission);
-                    None
+                for (in_brackets, without_brackets) in replace {
+                    permission = permission.replace(in_brackets, &sources.get(without_brackets)?);
                 }
+                Ok(permission)
             }
         }
     }
+
+    pub fn needs_body(&self) -> bool {
+        match self {
+            Self::NoPat { .. } => false,
+            Self::Pattern { replace, .. } => replace.iter().any(|(_, name)| name.starts_with("body.")),
+        }
+    }
 }
 
 
+/// What the layer does when the replica can't answer
+#[derive(Clone, Copy, Debug, Default)]
+pub enum FailureMode {
+    /// Reject with 503, so an outage doesn't look like a logout
+    #[default]
+    Closed,
+    /// Reuse cached decisions expired less than `grace` ago, otherwise reject with 503
+    Stale{grace: Duration},
+    /// Let the request through, only for explicitly safe read routes
+    Open,
+}
+
+impl Display for FailureMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => write!(f, "fail-closed"),
+            Self::Stale{grace} => write!(f, "stale within {}s", grace.as_secs()),
+            Self::Open => write!(f, "fail-open"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct PermissionMiddlewareBundle {
-    pub permission: PermissionKind,
-    pub rustperms_client: RustpermsReplicaProtoClient<tonic::transport::Channel>,
+    pub permission: PermissionExpr,
+    pub rustperms_client: ReplicaClient,
     pub on_fail: StatusCode,
+    pub cache: Option<PermissionCache>,
+    pub failure: FailureMode,
 }
 
 pub trait CompletePerm {
@@ -68,17 +116,9 @@ impl CompletePerm for String {
 }
 
 impl PermissionMiddlewareBundle {
-    pub async fn new(permission: String, rustperms_client: RustpermsReplicaProtoClient<tonic::transport::Channel>, on_fail: StatusCode) -> anyhow::Result<Self> {
-        permission.contains("*").then(|| panic!("Can't use wildcard in permission definition"));
-        let c = REGEX.captures_iter(&permission)
-            .filter_map(|m| m.get(1).map(|v| (format!("{{{}}}", v.as_str()), v.as_str().to_string())))
-            .collect::<Vec<(String, String)>>();
-        let permission = if !c.is_empty() {
-            PermissionKind::Pattern { incomplete: permission, replace: c }
-        } else {
-            PermissionKind::NoPat { permission }
-        };
-        Ok(Self{permission, on_fail, rustperms_client})
+    pub async fn new(permission: String, rustperms_client: ReplicaClient, on_fail: StatusCode) -> anyhow::Result<Self> {
+        let permission = PermissionExpr::parse(&permission)?;
+        Ok(Self{permission, on_fail, rustperms_client, cache: None, failure: FailureMode::default()})
     }
 }
 
@@ -87,10 +127,10 @@ impl PermissionMiddlewareBundle {
 #[derive(Clone, Debug)]
 pub struct PermissionAccessLayer(PermissionMiddlewareBundle);
 
-const REGEX : Lazy<Regex> = Lazy::new(||Regex::new(r"(?:\{)([^\{\}]+)(?:\})").expect("Can't parse permission pattern regex!"));
+static REGEX : Lazy<Regex> = Lazy::new(||Regex::new(r"(?:\{)([^\{\}]+)(?:\})").expect("Can't parse permission pattern regex!"));
 
 impl PermissionAccessLayer {
-    pub async fn new(permission: String, rustperms_client: RustpermsReplicaProtoClient<tonic::transport::Channel>, on_fail: StatusCode) -> anyhow::Result<Self> {
+    pub async fn new(permission: String, rustperms_client: ReplicaClient, on_fail: StatusCode) -> anyhow::Result<Self> {
         info!("Creating permission layer for {}", permission);
         Ok(Self(PermissionMiddlewareBundle::new(permission, rustperms_client, on_fail).await?))
     }
@@ -99,6 +139,23 @@ impl PermissionAccessLayer {
         self.0.on_fail = StatusCode::NOT_FOUND;
         self
     }
+
+    pub fn cached(mut self, cache: Option<PermissionCache>) -> Self {
+        self.0.cache = cache;
+        self
+    }
+
+    pub fn on_failure(mut self, failure: FailureMode) -> Self {
+        if let FailureMode::Stale{..} = failure && self.0.cache.is_none() {
+            warn!("Stale failure mode without permission cache behaves as fail-closed");
+        }
+        self.0.failure = failure;
+        self
+    }
+
+    pub fn failure_mode(&self) -> FailureMode {
+        self.0.failure
+    }
 }
 
 impl<S> Layer<S> for PermissionAccessLayer {
@@ -128,12 +185,11 @@ where
     }
 }
 
-impl<S, ReqBody> Service<Request<ReqBody>> for PermissionAccessService<S>
+impl<S> Service<Request<Body>> for PermissionAccessService<S>
 where
-    S: Service<Request<ReqBody>, Response = Response<Body>> + Clone + Send + 'static,
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
     S::Future: Send,
-    <S as Service<axum::http::Request<ReqBody>>>::Error: Send,
-    ReqBody: Send + 'static,
+    <S as Service<axum::http::Request<Body>>>::Error: Send,
 {
     type Response = S::Response;
     type Error = S::Error;
@@ -143,43 +199,122 @@ where
         self.service.poll_ready(cx)
     }
 
-    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
         let user_uid = if let Some(token) = req.extensions().get::<AccessTokenPayload>() {
             token.user.into_key()
         } else {
             "".to_string()
         };
 
+        // the ready service has to handle this request, the clone waits for the next one
+        let clone = self.service.clone();
+        let mut inner = std::mem::replace(&mut self.service, clone);
         let mut client = self.perm_bundle.rustperms_client.clone();
-        let on_fail = Ok(Response::builder().status(self.perm_bundle.on_fail).body(Body::empty()).unwrap());
-        let kvs = req.extensions().get::<ExtractedPathKV>();
-        let permission = self.perm_bundle.permission.clone().try_complete(kvs, &user_uid);
-        let Some(permission) = permission else {return Box::pin(async {on_fail})};
-        let next = self.service.call(req);
+        let status = self.perm_bundle.on_fail;
+        let on_fail = move || Ok(Response::builder().status(status).body(Body::empty()).unwrap());
+        let unavailable = || Ok(Response::builder().status(StatusCode::SERVICE_UNAVAILABLE).body(Body::empty()).unwrap());
+        let bad_request = || Ok(Response::builder().status(StatusCode::BAD_REQUEST).body(Body::empty()).unwrap());
+        let failure = self.perm_bundle.failure;
+        let permission = self.perm_bundle.permission.clone();
+        let cache = self.perm_bundle.cache.clone();
         Box::pin(async move { 
-            info!("Starting {} check for {}", permission, if user_uid == "" {"\"guest\""} else {&user_uid});
-            let reply = client.check_perm(
-                CheckPermRequest{user_uid, permission, unset_policy: false}
-            ).await;
-            match reply {
-                Ok(response) => {
-                    let CheckPermReply{result: check_result} = response.into_inner();
-                    info!("Perm check result: {}!", check_result);
-                    if !check_result {
-                        on_fail
-                    } else {
-                        next.await
-                    }
-                }
+            let (req, body) = if permission.needs_body() {
+                let (parts, body) = req.into_parts();
+                let Ok(bytes) = axum::body::to_bytes(body, CFG.PERMISSION_BODY_LIMIT).await else {
+                    warn!("Can't buffer request body for permission placeholders");
+                    return bad_request();
+                };
+                let json = serde_json::from_slice(&bytes).ok();
+                (Request::from_parts(parts, Body::from(bytes)), json)
+            } else {
+                (req, None)
+            };
+            let expr = {
+                let mut sources = PlaceholderSources::new(&user_uid, req.extensions().get::<ExtractedPathKV>(), req.uri().query(), req.headers());
+                sources.body = body;
+                permission.try_complete(&sources)
+            };
+            let expr = match expr {
+                Ok(expr) => expr,
+                Err(CompleteError::Unauthorized) => return on_fail(),
                 Err(e) => {
-                    error!("Can't call check perm from middleware!: {e}");
-                    on_fail
+                    warn!("Can't complete permission placeholders: {:?}", e);
+                    return bad_request();
+                }
+            };
+            let next = inner.call(req);
+            let results = match check_batch(&mut client, cache.as_ref(), &user_uid, &expr.permissions).await {
+                Ok(results) => results,
+                Err(known) => match failure {
+                    FailureMode::Open => {
+                        warn!("Replica unavailable, letting request through ({failure})");
+                        return next.await;
+                    }
+                    FailureMode::Stale{grace} => {
+                        let stale = known.into_iter().zip(&expr.permissions)
+                            .map(|(known, p)| known.or_else(|| cache.as_ref()?.get_within(&user_uid, p, grace)))
+                            .collect::<Option<Vec<bool>>>();
+                        let Some(stale) = stale else {return unavailable()};
+                        warn!("Replica unavailable, using stale decisions ({failure})");
+                        stale
+                    }
+                    FailureMode::Closed => return unavailable(),
                 }
+            };
+            let check_result = expr.root.eval(&results);
+            info!("Perm check result: {}!", check_result);
+            if !check_result {
+                on_fail()
+            } else {
+                next.await
             }
         })
     }
 }
 
+/// Checks permissions with one replica call, decisions found in `cache` aren't requested again.
+/// When replica can't answer, returns decisions known from cache.
+pub(crate) async fn check_batch(
+    client: &mut ReplicaClient,
+    cache: Option<&PermissionCache>,
+    user_uid: &String,
+    permissions: &[String],
+) -> Result<Vec<bool>, Vec<Option<bool>>> {
+    let mut results: Vec<Option<bool>> = permissions.iter()
+        .map(|p| cache.and_then(|c| c.get(user_uid, p)))
+        .collect();
+    let missing: Vec<usize> = (0..results.len()).filter(|i| results[*i].is_none()).collect();
+    if !missing.is_empty() {
+        let to_check: Vec<String> = missing.iter().map(|i| permissions[*i].clone()).collect();
+        info!("Starting {:?} check for {}", to_check, if user_uid.is_empty() {"\"guest\""} else {user_uid});
+        let generation = cache.map(|c| c.generation());
+        let reply = client.check_perms(
+            CheckPermsRequest{user_uid: user_uid.clone(), permissions: to_check, unset_policy: false}
+        ).await;
+        let checked = match reply {
+            Ok(response) => {
+                let CheckPermsReply{results: checked} = response.into_inner();
+                if checked.len() != missing.len() {
+                    error!("Replica answered {} decisions for {} permissions", checked.len(), missing.len());
+                    return Err(results);
+                }
+                checked
+            }
+            Err(e) => {
+                error!("Can't call check perms from middleware!: {e}");
+                return Err(results);
+            }
+        };
+        for (i, allowed) in missing.into_iter().zip(checked) {
+            results[i] = Some(allowed);
+            if let (Some(cache), Some(generation)) = (cache, generation) {
+                cache.insert(user_uid.clone(), permissions[i].clone(), allowed, generation);
+            }
+        }
+    }
+    Ok(results.into_iter().map(|r| r.unwrap_or(false)).collect())
+}
+
 #[derive(Clone, Debug)]
 pub struct ExtractedPathKV(pub HashMap<String, String>);
 
@@ -217,21 +352,50 @@ where
 ///     .layer(p.build("vesper.edit.{from_access}").await?)
 /// 
 /// ```
+/// Query parameters, headers and JSON body fields are available as `{query.<name>}`, `{header.<name>}`
+/// and `{body.<field>}`, see [`PlaceholderSources`]. A missing value rejects the request with 400.
+///
+/// Permissions can be combined with `|`, `&`, `!` and parentheses, see [`PermissionExpr`]:
+/// ```ignore
+/// route("/room/{id}", <handler>)
+///     .layer(p.build("calls.room.owner.{id} | calls.room.moderator.{id}").await?)
+/// ```
 /// ## DON'T CHAIN IT LIKE THAT:
 /// ```ignore
 /// route("/user/{id}", get(<handler>).layer(perm).post(<handler>).layer(perm2))
 /// ```
 pub struct PermissionMiddlewareBuilder {
     // permission: String, rustperms_client: RustpermsReplicaProtoClient<tonic::transport::Channel>, on_fail: StatusCode
-    pub rustperms_client: RustpermsReplicaProtoClient<tonic::transport::Channel>,
+    pub rustperms_client: ReplicaClient,
+    pub cache: Option<PermissionCache>,
+    /// Default for built layers, routes can override it with [`PermissionAccessLayer::on_failure`]
+    pub failure: FailureMode,
 }
 
 impl PermissionMiddlewareBuilder {
-    pub fn new(rustperms_client: RustpermsReplicaProtoClient<tonic::transport::Channel>) -> Self {
-        Self {rustperms_client}
+    pub fn new(rustperms_client: ReplicaClient) -> Self {
+        Self {rustperms_client, cache: None, failure: FailureMode::default()}
+    }
+
+    /// Shares replica client and cache with handler extractors like [`handle::Perms`] and [`filter::FieldFilter`]
+    pub fn handle_layer(&self) -> axum::Extension<handle::PermissionHandle> {
+        axum::Extension(handle::PermissionHandle{rustperms_client: self.rustperms_client.clone(), cache: self.cache.clone()})
+    }
+
+    pub fn with_failure_mode(mut self, failure: FailureMode) -> Self {
+        self.failure = failure;
+        self
+    }
+
+    /// Shares one decision cache between all built layers, configured by `PERMISSION_CACHE_*` (disabled by default)
+    pub async fn with_cache(mut self) -> anyhow::Result<Self> {
+        if CFG.PERMISSION_CACHE_TTL_MS == 0 {return Ok(self)}
+        let ttl = std::time::Duration::from_millis(CFG.PERMISSION_CACHE_TTL_MS);
+        self.cache = Some(PermissionCache::connect(ttl, CFG.PERMISSION_CACHE_CAPACITY).await?);
+        Ok(self)
     }
 
     pub async fn build(&self, path: &str) -> anyhow::Result<PermissionAccessLayer> {
-        PermissionAccessLayer::new(path.to_string(), self.rustperms_client.clone(), StatusCode::UNAUTHORIZED).await
+        Ok(PermissionAccessLayer::new(path.to_string(), self.rustperms_client.clone(), StatusCode::UNAUTHORIZED).await?.cached(self.cache.clone()).on_failure(self.failure))
     }
 }
\ No newline at end of file
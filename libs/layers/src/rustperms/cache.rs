@@ -0,0 +1,119 @@
+use std::{collections::{HashMap, HashSet}, sync::{atomic::{AtomicU64, Ordering}, Arc, RwLock}, time::{Duration, Instant}};
+
+use futures::StreamExt;
+use rustperms::prelude::{RustpermsDelta, RustpermsOperation, UserUID};
+use tracing::{error, info, warn};
+
+type Entries = HashMap<(UserUID, String), (bool, Instant)>;
+
+/// Bounded TTL cache of permission decisions, invalidated by the rustperms delta stream
+#[derive(Clone, Debug)]
+pub struct PermissionCache {
+    entries: Arc<RwLock<Entries>>,
+    // bumped on every invalidation, so checks started before it don't store stale decisions
+    generation: Arc<AtomicU64>,
+    ttl: Duration,
+    capacity: usize,
+}
+
+impl PermissionCache {
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            entries: Arc::new(RwLock::new(HashMap::new())),
+            generation: Arc::new(AtomicU64::new(0)),
+            ttl,
+            capacity,
+        }
+    }
+
+    /// Creates cache and keeps it subscribed to `PERM_WRITE_NATS_EVENT`
+    pub async fn connect(ttl: Duration, capacity: usize) -> anyhow::Result<Self> {
+        let env = &rustperms_nodes::ENV;
+        let client = async_nats::connect(format!("nats://{}:{}", env.NATS_URL, env.NATS_PORT)).await?;
+        let cache = Self::new(ttl, capacity);
+        let listener = cache.clone();
+        tokio::spawn(async move {
+            loop {
+                match client.subscribe(env.PERM_WRITE_NATS_EVENT.clone()).await {
+                    Ok(subscriber) => listener.listen(subscriber).await,
+                    Err(e) => error!("Can't subscribe to rustperms deltas: {e}"),
+                }
+                // deltas may be missed while resubscribing
+                listener.clear();
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        });
+        Ok(cache)
+    }
+
+    async fn listen(&self, mut subscriber: async_nats::Subscriber) {
+        info!("Permission cache subscribed to rustperms deltas");
+        while let Some(message) = subscriber.next().await {
+            let delta = std::str::from_utf8(&message.payload).ok()
+                .and_then(|payload| RustpermsDelta::deserialize_from_string(payload).ok());
+            match delta {
+                Some(delta) => self.invalidate(delta),
+                None => {
+                    warn!("Can't decode rustperms delta, dropping whole permission cache");
+                    self.clear();
+                }
+            }
+        }
+        warn!("Rustperms delta subscription ended");
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    pub fn get(&self, user: &UserUID, permission: &str) -> Option<bool> {
+        self.get_within(user, permission, Duration::ZERO)
+    }
+
+    /// Same as [`Self::get`], but also accepts decisions expired less than `grace` ago
+    pub fn get_within(&self, user: &UserUID, permission: &str, grace: Duration) -> Option<bool> {
+        let entries = self.entries.read().ok()?;
+        let (allowed, at) = entries.get(&(user.clone(), permission.to_string()))?;
+        (at.elapsed() < self.ttl + grace).then_some(*allowed)
+    }
+
+    /// Stores decision unless cache was invalidated after `generation` was taken
+    pub fn insert(&self, user: UserUID, permission: String, allowed: bool, generation: u64) {
+        let Ok(mut entries) = self.entries.write() else {return};
+        if self.generation() != generation {return}
+        if entries.len() >= self.capacity {
+            entries.retain(|_, (_, at)| at.elapsed() < self.ttl);
+            if entries.len() >= self.capacity {
+                entries.clear();
+            }
+        }
+        entries.insert((user, permission), (allowed, Instant::now()));
+    }
+
+    pub fn clear(&self) {
+        let Ok(mut entries) = self.entries.write() else {return};
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        entries.clear();
+    }
+
+    /// Drops decisions of users touched by the delta, any group level change drops everything
+    pub fn invalidate(&self, delta: RustpermsDelta) {
+        let mut users: HashSet<UserUID> = HashSet::new();
+        for op in delta {
+            match op {
+                RustpermsOperation::UserCreate(u)
+                | RustpermsOperation::UserRemove(u)
+                | RustpermsOperation::UserUpdatePerms(u, _)
+                | RustpermsOperation::UserRemovePerms(u, _) => {users.insert(u);}
+                RustpermsOperation::GroupAddUsers(_, us)
+                | RustpermsOperation::GroupRemoveUsers(_, us)
+                | RustpermsOperation::GroupAddScopedUsers(_, _, us)
+                | RustpermsOperation::GroupRemoveScopedUsers(_, _, us) => users.extend(us),
+                _ => return self.clear(),
+            }
+        }
+        let Ok(mut entries) = self.entries.write() else {return};
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        entries.retain(|(u, _), _| !users.contains(u));
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{anyhow, bail};
+
+use super::{sources::{CompleteError, PlaceholderSources}, PermissionKind};
+
+/// Permission expression of a route
+///
+/// Syntax: `a | b` (any-of), `a & b` (all-of), `!a` (not) and parentheses,
+/// `!` binds tighter than `&`, `&` tighter than `|`. Each leaf is a permission pattern:
+/// ```ignore
+/// "calls.room.owner.{id} | calls.room.moderator.{id}"
+/// "calls.connect & !calls.banned.{from_access}"
+/// ```
+#[derive(Clone, Debug)]
+pub enum PermissionExpr {
+    Perm(PermissionKind),
+    All(Vec<PermissionExpr>),
+    Any(Vec<PermissionExpr>),
+    Not(Box<PermissionExpr>),
+}
+
+/// Expression with completed permissions, leaves are indexes of `CompletedExpr::permissions`
+#[derive(Clone, Debug)]
+pub enum CompletedNode {
+    Perm(usize),
+    All(Vec<CompletedNode>),
+    Any(Vec<CompletedNode>),
+    Not(Box<CompletedNode>),
+}
+
+#[derive(Clone, Debug)]
+pub struct CompletedExpr {
+    pub root: CompletedNode,
+    /// Deduplicated permissions to check in one batch
+    pub permissions: Vec<String>,
+}
+
+impl PermissionExpr {
+    pub fn parse(expression: &str) -> anyhow::Result<Self> {
+        let tokens = tokenize(expression)?;
+        let mut parser = Parser{tokens, pos: 0};
+        let expr = parser.any()?;
+        if let Some(token) = parser.tokens.get(parser.pos) {
+            bail!("Unexpected `{}` in permission expression `{}`", token, expression);
+        }
+        Ok(expr)
+    }
+
+    pub fn try_complete(&self, sources: &PlaceholderSources) -> Result<CompletedExpr, CompleteError> {
+        let mut permissions = vec![];
+        let root = self.complete_node(sources, &mut permissions)?;
+        Ok(CompletedExpr{root, permissions})
+    }
+
+    /// Whether any leaf uses `{body.*}` placeholders, so the request body has to be buffered
+    pub fn needs_body(&self) -> bool {
+        match self {
+            Self::Perm(kind) => kind.needs_body(),
+            Self::All(exprs) | Self::Any(exprs) => exprs.iter().any(|e| e.needs_body()),
+            Self::Not(expr) => expr.needs_body(),
+        }
+    }
+
+    fn complete_node(&self, sources: &PlaceholderSources, permissions: &mut Vec<String>) -> Result<CompletedNode, CompleteError> {
+        let complete_all = |exprs: &Vec<PermissionExpr>, permissions: &mut Vec<String>| exprs.iter()
+            .map(|e| e.complete_node(sources, permissions))
+            .collect::<Result<Vec<_>, _>>();
+        Ok(match self {
+            Self::Perm(kind) => {
+                let permission = kind.try_complete(sources)?;
+                let idx = permissions.iter().position(|p| p == &permission).unwrap_or_else(|| {
+                    permissions.push(permission);
+                    permissions.len() - 1
+                });
+                CompletedNode::Perm(idx)
+            }
+            Self::All(exprs) => CompletedNode::All(complete_all(exprs, permissions)?),
+            Self::Any(exprs) => CompletedNode::Any(complete_all(exprs, permissions)?),
+            Self::Not(expr) => CompletedNode::Not(Box::new(expr.complete_node(sources, permissions)?)),
+        })
+    }
+}
+
+impl CompletedNode {
+    /// `results[i]` is the decision for `CompletedExpr::permissions[i]`
+    pub fn eval(&self, results: &[bool]) -> bool {
+        match self {
+            Self::Perm(idx) => results.get(*idx).copied().unwrap_or(false),
+            Self::All(nodes) => nodes.iter().all(|n| n.eval(results)),
+            Self::Any(nodes) => nodes.iter().any(|n| n.eval(results)),
+            Self::Not(node) => !node.eval(results),
+        }
+    }
+}
+
+fn tokenize(expression: &str) -> anyhow::Result<Vec<String>> {
+    let mut tokens = vec![];
+    let mut current = String::new();
+    let mut in_placeholder = false;
+    for c in expression.chars() {
+        match c {
+            '{' if in_placeholder => bail!("Nested `{{` in permission expression `{}`", expression),
+            '}' if !in_placeholder => bail!("Unmatched `}}` in permission expression `{}`", expression),
+            '{' | '}' => {
+                in_placeholder = c == '{';
+                current.push(c);
+            }
+            _ if in_placeholder => current.push(c),
+            '&' | '|' | '!' | '(' | ')' => {
+                if !current.is_empty() {tokens.push(std::mem::take(&mut current))}
+                tokens.push(c.to_string());
+            }
+            c if c.is_whitespace() => if !current.is_empty() {tokens.push(std::mem::take(&mut current))},
+            c => current.push(c),
+        }
+    }
+    if in_placeholder {
+        bail!("Unclosed `{{` in permission expression `{}`", expression);
+    }
+    if !current.is_empty() {tokens.push(current)}
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.as_str())
+    }
+
+    fn any(&mut self) -> anyhow::Result<PermissionExpr> {
+        let mut exprs = vec![self.all()?];
+        while self.peek() == Some("|") {
+            self.pos += 1;
+            exprs.push(self.all()?);
+        }
+        Ok(if exprs.len() == 1 {exprs.remove(0)} else {PermissionExpr::Any(exprs)})
+    }
+
+    fn all(&mut self) -> anyhow::Result<PermissionExpr> {
+        let mut exprs = vec![self.unary()?];
+        while self.peek() == Some("&") {
+            self.pos += 1;
+            exprs.push(self.unary()?);
+        }
+        Ok(if exprs.len() == 1 {exprs.remove(0)} else {PermissionExpr::All(exprs)})
+    }
+
+    fn unary(&mut self) -> anyhow::Result<PermissionExpr> {
+        let token = self.peek().map(|t| t.to_string()).ok_or_else(|| anyhow!("Permission expression ended unexpectedly"))?;
+        self.pos += 1;
+        match token.as_str() {
+            "!" => Ok(PermissionExpr::Not(Box::new(self.unary()?))),
+            "(" => {
+                let expr = self.any()?;
+                if self.peek() != Some(")") {
+                    bail!("Expected `)` in permission expression");
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            "&" | "|" | ")" => bail!("Unexpected `{}` in permission expression", token),
+            _ => Ok(PermissionExpr::Perm(PermissionKind::parse(&token)?)),
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use axum::{extract::FromRequestParts, http::{request::Parts, StatusCode}};
+use serde::Serialize;
+use tracing::{error, warn};
+
+use super::handle::Perms;
+
+/// JSON fields of a response tagged with permissions required to see them
+#[derive(Clone, Debug, Default)]
+pub struct FieldPermissions(pub Vec<(String, String)>);
+
+impl FieldPermissions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn field(mut self, field: impl Into<String>, permission: impl Into<String>) -> Self {
+        self.0.push((field.into(), permission.into()));
+        self
+    }
+}
+
+/// Drops response fields the caller lacks permissions for
+/// ```ignore
+/// async fn handler(filter: FieldFilter) -> ... {
+///     let fields = FieldPermissions::new().field("status", "user.profile.restricted.<guid>.status");
+///     Ok(Json(filter.apply(&profile, fields).await?))
+/// }
+/// ```
+pub struct FieldFilter(Perms);
+
+impl<S> FromRequestParts<S> for FieldFilter
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Self(Perms::from_request_parts(parts, state).await?))
+    }
+}
+
+impl FieldFilter {
+    /// Serializes `value` and removes tagged top level fields with one batch check.
+    /// If replica can't answer, every tagged field is removed.
+    pub async fn apply<T: Serialize>(&self, value: &T, fields: FieldPermissions) -> Result<serde_json::Value, StatusCode> {
+        let mut json = serde_json::to_value(value).map_err(|e| {
+            error!("Can't serialize filtered response: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+        let FieldPermissions(fields) = fields;
+        if fields.is_empty() {return Ok(json)}
+        let Some(object) = json.as_object_mut() else {
+            warn!("Field filter applied to non-object response, returning it as is");
+            return Ok(json);
+        };
+        let mut permissions: Vec<String> = fields.iter().map(|(_, p)| p.clone()).collect();
+        permissions.sort();
+        permissions.dedup();
+        let results = self.0.can_all(&permissions).await;
+        for (field, permission) in fields {
+            let allowed = permissions.iter().position(|p| p == &permission).and_then(|i| results.get(i)).copied().unwrap_or(false);
+            if !allowed {
+                object.remove(&field);
+            }
+        }
+        Ok(json)
+    }
+}
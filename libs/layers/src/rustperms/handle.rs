@@ -0,0 +1,80 @@
+use std::{collections::HashMap, sync::{Arc, Mutex}};
+
+use axum::{extract::FromRequestParts, http::{request::Parts, StatusCode}, Extension};
+use rustperms_nodes::ReplicaClient;
+use shared::{tokens::jwt::AccessTokenPayload, utils::IntoKey};
+use tracing::error;
+
+use super::{cache::PermissionCache, check_batch};
+
+/// Replica client shared with handlers, put into request extensions by [`super::PermissionMiddlewareBuilder::handle_layer`]
+#[derive(Clone, Debug)]
+pub struct PermissionHandle {
+    pub rustperms_client: ReplicaClient,
+    pub cache: Option<PermissionCache>,
+}
+
+/// Lazy permission checks of the caller inside a handler, decisions are memoized for the request
+/// ```ignore
+/// async fn get_rooms(perms: Perms) -> ... {
+///     if perms.can("calls.view.hidden").await { ... }
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Perms {
+    handle: PermissionHandle,
+    user_uid: String,
+    memo: Arc<Mutex<HashMap<String, bool>>>,
+}
+
+impl<S> FromRequestParts<S> for Perms
+where
+    S: Send + Sync,
+{
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        // extractors of the same request share one memo
+        if let Some(perms) = parts.extensions.get::<Perms>() {
+            return Ok(perms.clone());
+        }
+        let Ok(Extension(handle)) = Extension::<PermissionHandle>::from_request_parts(parts, state).await else {
+            error!("PermissionHandle is missing, add PermissionMiddlewareBuilder::handle_layer to the router");
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        };
+        let user_uid = parts.extensions.get::<AccessTokenPayload>().map(|t| t.user.into_key()).unwrap_or_default();
+        let perms = Self{handle, user_uid, memo: Arc::default()};
+        parts.extensions.insert(perms.clone());
+        Ok(perms)
+    }
+}
+
+impl Perms {
+    /// Empty for guests
+    pub fn user_uid(&self) -> &str {
+        &self.user_uid
+    }
+
+    /// Denies if replica can't answer
+    pub async fn can(&self, permission: &str) -> bool {
+        self.can_all(&[permission.to_string()]).await[0]
+    }
+
+    /// Checks not yet memoized permissions with one replica call
+    pub async fn can_all(&self, permissions: &[String]) -> Vec<bool> {
+        let missing: Vec<String> = {
+            let Ok(memo) = self.memo.lock() else {return vec![false; permissions.len()]};
+            permissions.iter().filter(|p| !memo.contains_key(*p)).cloned().collect()
+        };
+        if !missing.is_empty() {
+            let mut client = self.handle.rustperms_client.clone();
+            // failed checks aren't memoized, so the next call retries them
+            if let Ok(results) = check_batch(&mut client, self.handle.cache.as_ref(), &self.user_uid, &missing).await
+                && let Ok(mut memo) = self.memo.lock() {
+                memo.extend(missing.into_iter().zip(results));
+            }
+        }
+        let Ok(memo) = self.memo.lock() else {return vec![false; permissions.len()]};
+        permissions.iter().map(|p| memo.get(p).copied().unwrap_or(false)).collect()
+    }
+}
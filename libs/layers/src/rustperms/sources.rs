@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use axum::http::HeaderMap;
+
+use super::ExtractedPathKV;
+
+/// Why a permission pattern couldn't be completed
+#[derive(Clone, Debug)]
+pub enum CompleteError {
+    /// `{from_access}` without access token
+    Unauthorized,
+    Missing(String),
+    /// Value would change the permission structure (contains `.`, `*` or brackets)
+    Invalid(String),
+}
+
+/// Values available for permission placeholders:
+/// - `{from_access}` - user from access token
+/// - `{query.<name>}` - query string parameter
+/// - `{header.<name>}` - request header
+/// - `{body.<field>}` - JSON body field, nested fields are separated by `.`
+/// - `{<name>}` - path parameter extracted by `ExtractPath`
+pub struct PlaceholderSources<'a> {
+    pub user_id: &'a str,
+    pub path: Option<&'a ExtractedPathKV>,
+    pub query: HashMap<String, String>,
+    pub headers: &'a HeaderMap,
+    pub body: Option<serde_json::Value>,
+}
+
+impl<'a> PlaceholderSources<'a> {
+    pub fn new(user_id: &'a str, path: Option<&'a ExtractedPathKV>, query: Option<&str>, headers: &'a HeaderMap) -> Self {
+        let query = query
+            .map(|q| form_urlencoded::parse(q.as_bytes()).into_owned().collect())
+            .unwrap_or_default();
+        Self {user_id, path, query, headers, body: None}
+    }
+
+    pub fn get(&self, placeholder: &str) -> Result<String, CompleteError> {
+        let value = if placeholder == "from_access" {
+            if self.user_id.is_empty() {return Err(CompleteError::Unauthorized)}
+            Some(self.user_id.to_string())
+        } else if let Some(name) = placeholder.strip_prefix("query.") {
+            self.query.get(name).cloned()
+        } else if let Some(name) = placeholder.strip_prefix("header.") {
+            self.headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string())
+        } else if let Some(field) = placeholder.strip_prefix("body.") {
+            self.body.as_ref()
+                .and_then(|body| field.split('.').try_fold(body, |v, key| v.get(key)))
+                .and_then(|v| match v {
+                    serde_json::Value::String(s) => Some(s.clone()),
+                    serde_json::Value::Number(n) => Some(n.to_string()),
+                    serde_json::Value::Bool(b) => Some(b.to_string()),
+                    _ => None,
+                })
+        } else {
+            self.path.and_then(|ExtractedPathKV(kvs)| kvs.get(placeholder).cloned())
+        };
+        let value = value.filter(|v| !v.is_empty()).ok_or_else(|| CompleteError::Missing(placeholder.to_string()))?;
+        if value.contains(['.', '*', '{', '}']) {
+            return Err(CompleteError::Invalid(placeholder.to_string()));
+        }
+        Ok(value)
+    }
+}
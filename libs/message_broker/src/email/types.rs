@@ -30,6 +30,20 @@ pub enum EmailKind {
     RefreshRulesUpdate {
         ip : String,
         user_agent : String,
+    },
+    /// Sent to the new address
+    EmailChangeCode {
+        code: String
+    },
+    /// Sent to the old address
+    EmailChangeRequested {
+        new_email: String,
+        cancel_link: String
+    },
+    /// An already rotated refresh token was presented, the session got ended
+    RefreshTokenReuse {
+        ip : String,
+        user_agent : String,
     }
 }
 
@@ -42,6 +56,9 @@ impl EmailKind {
             EmailKind::NewLogin { ip: _, user_agent: _ } => "NewLogin",
             EmailKind::SuspiciousRefresh { ip: _, user_agent: _ } => "SuspiciousRefresh",
             EmailKind::RefreshRulesUpdate { ip: _, user_agent: _ } => "RefreshRulesUpdate",
+            EmailKind::EmailChangeCode { code: _ } => "EmailChangeCode",
+            EmailKind::EmailChangeRequested { new_email: _, cancel_link: _ } => "EmailChangeRequested",
+            EmailKind::RefreshTokenReuse { ip: _, user_agent: _ } => "RefreshTokenReuse",
         }
     } 
 }
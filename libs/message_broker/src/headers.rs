@@ -0,0 +1,27 @@
+use async_nats::HeaderMap;
+use shared::utils::{request_id::{current_request_id, request_id_or_new, REQUEST_ID_HEADER}, telemetry::{continue_trace, trace_headers}};
+use tracing::Span;
+
+/// Headers carrying the id of the request handled by the current task and the trace context of the current span
+pub fn request_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(id) = current_request_id() {
+        headers.insert(REQUEST_ID_HEADER, id.as_str());
+    }
+    for (name, value) in trace_headers() {
+        headers.insert(name.as_str(), value.as_str());
+    }
+    headers
+}
+
+/// Request id of a received message, a new one if the publisher didn't set it
+pub fn message_request_id(message: &async_nats::Message) -> String {
+    let header = message.headers.as_ref().and_then(|h| h.get(REQUEST_ID_HEADER)).map(|v| v.as_str());
+    request_id_or_new(header)
+}
+
+/// Makes `span` (the consumer side) a child of the publisher's span
+pub fn continue_message_trace(span: &Span, message: &async_nats::Message) {
+    let Some(headers) = message.headers.as_ref() else {return};
+    continue_trace(span, |name| headers.get(name).map(|v| v.as_str()));
+}
@@ -2,11 +2,14 @@ use shared::env_config;
 
 pub mod publisher;
 pub mod email;
+pub mod revocation;
+pub mod headers;
 
 
 env_config!(
-    ".env" => ENV = Env {
+    ".env" => pub ENV = Env {
         NATS_URL : String,
         NATS_PORT : String,
+        TOKEN_REVOCATION_NATS_EVENT : String = "auth.revocation".to_string(),
     }
 );
\ No newline at end of file
@@ -1,10 +1,16 @@
 use anyhow::Result;
 use async_nats::jetstream::Context;
 
-use crate::ENV;
+use crate::{headers::request_headers, ENV};
 
 pub async fn build_publisher() -> Result<Context> {
     let nats_url = format!("nats://{}:{}", ENV.NATS_URL, ENV.NATS_PORT);
     let client = async_nats::connect(nats_url).await?;
     Ok(async_nats::jetstream::new(client))
 }
+
+/// Core subscribers (auth layers of every service) receive it, no stream is needed
+pub async fn publish_revocation(publisher: &Context, event: &crate::revocation::types::RevocationEvent) -> Result<()> {
+    publisher.publish_with_headers(ENV.TOKEN_REVOCATION_NATS_EVENT.clone(), request_headers(), event.encode()?.into()).await?;
+    Ok(())
+}
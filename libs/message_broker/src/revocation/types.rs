@@ -0,0 +1,28 @@
+use bincode::{Decode, Encode};
+
+
+
+/// Published by auth when access tokens have to stop working before their `exp`
+#[derive(Encode, Decode, Debug, Clone)]
+pub enum RevocationEvent {
+    /// Tokens of the user issued before `before` (unix seconds) are invalid
+    User {
+        user: String,
+        before: i64,
+    },
+    /// Tokens bound to the session (refresh token `rtid`) are invalid
+    Session {
+        sid: String,
+    },
+}
+
+impl RevocationEvent {
+    pub fn encode(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::encode_to_vec(self, bincode::config::standard())?)
+    }
+
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let (event, _) = bincode::decode_from_slice(bytes, bincode::config::standard())?;
+        Ok(event)
+    }
+}
@@ -0,0 +1,53 @@
+use rustperms::prelude::*;
+
+use crate::{groups::DEFAULT_GROUP, rule};
+
+// room roles are assigned as scoped memberships, so one group serves every room
+pub const ROOM_OWNER_GROUP : &str = "calls.room.owner";
+pub const ROOM_MODERATOR_GROUP : &str = "calls.room.moderator";
+
+rule!(ROOM_PERM, "calls.room");
+
+pub fn room_scope(room_guid: &str) -> PermissionPath {
+    room_perm(room_guid).into_perm()
+}
+
+pub fn init_default() -> Vec<RustpermsOperation> {
+    vec![
+        RustpermsOperation::GroupCreate { group_uid: ROOM_MODERATOR_GROUP.to_string(), weight: 20 },
+        RustpermsOperation::GroupCreate { group_uid: ROOM_OWNER_GROUP.to_string(), weight: 30 },
+        RustpermsOperation::GroupAddGroupsToInherit(ROOM_OWNER_GROUP.to_string(), vec![ROOM_MODERATOR_GROUP.to_string()]),
+    ]
+}
+
+pub fn fill_with_defaults() -> Vec<RustpermsOperation> {
+    vec![
+        // default group allows whole "calls", room management is granted by roles only
+        RustpermsOperation::GroupUpdatePerms(DEFAULT_GROUP.to_string(), vec![
+            (room_perm("*").into_perm(), false),
+        ]),
+        RustpermsOperation::GroupUpdatePerms(ROOM_MODERATOR_GROUP.to_string(), vec![
+            (room_perm_postfix("?", "kick").into_perm(), true),
+            (room_perm_postfix("?", "mute").into_perm(), true),
+        ]),
+        RustpermsOperation::GroupUpdatePerms(ROOM_OWNER_GROUP.to_string(), vec![
+            (room_perm_postfix("?", "delete").into_perm(), true),
+            (room_perm_postfix("?", "moderators").into_perm(), true),
+        ]),
+    ]
+}
+
+pub fn grant_room_owner(user: &UserUID, room_guid: &str) -> Vec<RustpermsOperation> {
+    vec![RustpermsOperation::GroupAddScopedUsers(ROOM_OWNER_GROUP.to_string(), room_scope(room_guid), vec![user.clone()])]
+}
+
+pub fn grant_room_moderator(user: &UserUID, room_guid: &str) -> Vec<RustpermsOperation> {
+    vec![RustpermsOperation::GroupAddScopedUsers(ROOM_MODERATOR_GROUP.to_string(), room_scope(room_guid), vec![user.clone()])]
+}
+
+pub fn revoke_room_roles(users: Vec<UserUID>, room_guid: &str) -> Vec<RustpermsOperation> {
+    vec![
+        RustpermsOperation::GroupRemoveScopedUsers(ROOM_OWNER_GROUP.to_string(), room_scope(room_guid), users.clone()),
+        RustpermsOperation::GroupRemoveScopedUsers(ROOM_MODERATOR_GROUP.to_string(), room_scope(room_guid), users),
+    ]
+}
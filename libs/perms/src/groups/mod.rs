@@ -1,5 +1,5 @@
 
-use rustperms::{api::util::{sharded_group_crate, sharded_group_update_perms}, prelude::*};
+use rustperms::prelude::*;
 use rustperms_nodes::proto::{rustperms_master_proto_client::RustpermsMasterProtoClient, WriteRequest};
 
 use crate::{rule, user::profile::profile_view_perm};
@@ -7,20 +7,21 @@ use crate::{rule, user::profile::profile_view_perm};
 pub const AUTHED_GROUP : &str = "authed";
 pub const DEFAULT_GROUP : &str = "default";
 
-pub const AUTHED_SHARDING : usize = 32;
-pub const GUEST_SHARDING : usize = 32;
-pub const DEFAULT_SHARDING : usize = 32;
+pub const AUTHED_SHARDING : u32 = 32;
+pub const GUEST_SHARDING : u32 = 32;
+pub const DEFAULT_SHARDING : u32 = 32;
 
+// sharding is handled by the engine, members and rules are routed to shards automatically
 pub fn init_default() -> Vec<RustpermsOperation> {
-    let mut ops = sharded_group_crate(AUTHED_GROUP.to_string(), 10, AUTHED_SHARDING);
-    ops.extend(sharded_group_crate(GUEST_GROUP.to_string(), 5, GUEST_SHARDING).into_iter());
-    ops.extend(sharded_group_crate(DEFAULT_GROUP.to_string(), 0, DEFAULT_SHARDING).into_iter());
-    ops.extend(
-        vec![
-            RustpermsOperation::GroupAddDependentGroups(DEFAULT_GROUP.to_string(), vec![AUTHED_GROUP.to_string(), GUEST_GROUP.to_string()]),
-        ]
-    );
-    ops
+    vec![
+        RustpermsOperation::GroupCreate { group_uid: AUTHED_GROUP.to_string(), weight: 10 },
+        RustpermsOperation::GroupCreate { group_uid: GUEST_GROUP.to_string(), weight: 5 },
+        RustpermsOperation::GroupCreate { group_uid: DEFAULT_GROUP.to_string(), weight: 0 },
+        RustpermsOperation::GroupReshard { group_uid: AUTHED_GROUP.to_string(), shards: AUTHED_SHARDING },
+        RustpermsOperation::GroupReshard { group_uid: GUEST_GROUP.to_string(), shards: GUEST_SHARDING },
+        RustpermsOperation::GroupReshard { group_uid: DEFAULT_GROUP.to_string(), shards: DEFAULT_SHARDING },
+        RustpermsOperation::GroupAddDependentGroups(DEFAULT_GROUP.to_string(), vec![AUTHED_GROUP.to_string(), GUEST_GROUP.to_string()]),
+    ]
 }
 
 rule!(UPLOAD_TO_STORE_PERM, "store.upload");
@@ -28,15 +29,14 @@ rule!(UPLOAD_TO_STORE_PERM, "store.upload");
 rule!(CALLS_PERM, "calls");
 
 pub fn fill_with_defaults() -> Vec<RustpermsOperation> {
-    let mut ops = sharded_group_update_perms(DEFAULT_GROUP.to_string(), DEFAULT_SHARDING, vec![
-        ("profile", (profile_view_perm("*").into_perm(), true)),
-        ("calls", (calls_perm("*").into_perm(), true)),
-        ("calls", (calls_perm("view.hidden").into_perm(), false)),
-    ]);
-    ops.extend(
-        sharded_group_update_perms(AUTHED_GROUP.to_string(), AUTHED_SHARDING, vec![
-            ("*", (upload_to_store_perm("*").into_perm(), true)),
-        ])
-    );
-    ops
+    vec![
+        RustpermsOperation::GroupUpdatePerms(DEFAULT_GROUP.to_string(), vec![
+            (profile_view_perm("*").into_perm(), true),
+            (calls_perm("*").into_perm(), true),
+            (calls_perm("view.hidden").into_perm(), false),
+        ]),
+        RustpermsOperation::GroupUpdatePerms(AUTHED_GROUP.to_string(), vec![
+            (upload_to_store_perm("*").into_perm(), true),
+        ]),
+    ]
 }
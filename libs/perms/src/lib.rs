@@ -1,5 +1,7 @@
 pub mod user;
 pub mod groups;
+pub mod calls;
+pub mod posts;
 
 #[macro_export]
 macro_rules! rule {
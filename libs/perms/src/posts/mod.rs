@@ -0,0 +1,48 @@
+use rustperms::prelude::*;
+
+use crate::rule;
+
+// post roles are assigned as scoped memberships, so one group serves every post
+pub const POST_OWNER_GROUP : &str = "posts.post.owner";
+pub const POST_EDITOR_GROUP : &str = "posts.post.editor";
+
+rule!(POST_PERM, "posts.post");
+
+pub fn post_scope(post_guid: &str) -> PermissionPath {
+    post_perm(post_guid).into_perm()
+}
+
+pub fn init_default() -> Vec<RustpermsOperation> {
+    vec![
+        RustpermsOperation::GroupCreate { group_uid: POST_EDITOR_GROUP.to_string(), weight: 20 },
+        RustpermsOperation::GroupCreate { group_uid: POST_OWNER_GROUP.to_string(), weight: 30 },
+        RustpermsOperation::GroupAddGroupsToInherit(POST_OWNER_GROUP.to_string(), vec![POST_EDITOR_GROUP.to_string()]),
+    ]
+}
+
+pub fn fill_with_defaults() -> Vec<RustpermsOperation> {
+    vec![
+        RustpermsOperation::GroupUpdatePerms(POST_EDITOR_GROUP.to_string(), vec![
+            (post_perm_postfix("?", "edit").into_perm(), true),
+        ]),
+        RustpermsOperation::GroupUpdatePerms(POST_OWNER_GROUP.to_string(), vec![
+            (post_perm_postfix("?", "delete").into_perm(), true),
+            (post_perm_postfix("?", "editors").into_perm(), true),
+        ]),
+    ]
+}
+
+pub fn grant_post_owner(user: &UserUID, post_guid: &str) -> Vec<RustpermsOperation> {
+    vec![RustpermsOperation::GroupAddScopedUsers(POST_OWNER_GROUP.to_string(), post_scope(post_guid), vec![user.clone()])]
+}
+
+pub fn grant_post_editor(user: &UserUID, post_guid: &str) -> Vec<RustpermsOperation> {
+    vec![RustpermsOperation::GroupAddScopedUsers(POST_EDITOR_GROUP.to_string(), post_scope(post_guid), vec![user.clone()])]
+}
+
+pub fn revoke_post_roles(users: Vec<UserUID>, post_guid: &str) -> Vec<RustpermsOperation> {
+    vec![
+        RustpermsOperation::GroupRemoveScopedUsers(POST_OWNER_GROUP.to_string(), post_scope(post_guid), users.clone()),
+        RustpermsOperation::GroupRemoveScopedUsers(POST_EDITOR_GROUP.to_string(), post_scope(post_guid), users),
+    ]
+}
@@ -2,7 +2,7 @@ use rustperms::prelude::{IntoPermPath, RustpermsOperation};
 use uuid::Uuid;
 use shared::utils::IntoKey;
 
-use crate::{groups::{DEFAULT_GROUP, AUTHED_GROUP}, rule, user::profile::{miniprofile_edit_perm_postfix, profile_edit_perm_postfix}};
+use crate::{groups::{DEFAULT_GROUP, AUTHED_GROUP}, rule, user::profile::{miniprofile_edit_perm_postfix, profile_edit_perm_postfix, profile_view_restricted_perm_postfix}};
 
 pub mod profile;
 
@@ -31,6 +31,7 @@ pub fn grant_default_for_user(guid: &Uuid) -> Vec<RustpermsOperation> {
     let p = vec![
         (profile_edit_perm_postfix(&key, "*").into_perm(), true),
         (miniprofile_edit_perm_postfix(&key, "*").into_perm(), true),
+        (profile_view_restricted_perm_postfix(&key, "*").into_perm(), true),
     ];
     vec![
         RustpermsOperation::UserUpdatePerms(key, p)
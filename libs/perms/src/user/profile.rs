@@ -1,4 +1,3 @@
-use rustperms::{api::util::sharded_group_update_perms, prelude::*};
 use uuid::Uuid;
 use crate::{groups::*, rule, user::IntoKey};
 
@@ -12,5 +11,7 @@ rule!(MINIPROFILE_EDIT_PERM, "user.miniprofile.edit");
 
 
 rule!(PROFILE_VIEW_PERM, "user.profile.view");
+// fields hidden by `restrict_visibility`, e.g. `user.profile.restricted.<guid>.status`
+rule!(PROFILE_VIEW_RESTRICTED_PERM, "user.profile.restricted");
 
 
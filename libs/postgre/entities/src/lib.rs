@@ -8,3 +8,4 @@ pub mod post;
 pub mod user_mini_profile;
 pub mod user_profile;
 pub mod user_data;
+pub mod webauthn_credential;
@@ -7,3 +7,4 @@ pub mod post;
 pub mod user_data;
 pub mod user_mini_profile;
 pub mod user_profile;
+pub mod webauthn_credential;
@@ -5,3 +5,4 @@ pub use super::post::Entity as Post;
 pub use super::user_data::Entity as UserData;
 pub use super::user_mini_profile::Entity as UserMiniProfile;
 pub use super::user_profile::Entity as UserProfile;
+pub use super::webauthn_credential::Entity as WebauthnCredential;
@@ -24,6 +24,10 @@ pub struct Model {
     pub allow_suspicious_refresh: bool,
     pub avatar: Option<String>,
     pub badges: Vec<i16>,
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    pub totp_recovery_codes: Vec<String>,
+    pub deletion_scheduled_at: Option<DateTime>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -34,6 +38,8 @@ pub enum Relation {
     UserMiniProfile,
     #[sea_orm(has_one = "super::user_profile::Entity")]
     UserProfile,
+    #[sea_orm(has_many = "super::webauthn_credential::Entity")]
+    WebauthnCredential,
 }
 
 impl Related<super::post::Entity> for Entity {
@@ -54,3 +60,9 @@ impl Related<super::user_profile::Entity> for Entity {
     }
 }
 
+impl Related<super::webauthn_credential::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::WebauthnCredential.def()
+    }
+}
+
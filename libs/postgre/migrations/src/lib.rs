@@ -11,6 +11,9 @@ impl MigratorTrait for Migrator {
             Box::new(m20250309_125602_user_data_update::Migration),
             // Box::new(m20250418_172130_perms::Migration),
             Box::new(m20250807_160804_user_profiles::Migration),
+            Box::new(m20251019_120000_two_factor::Migration),
+            Box::new(m20251020_120000_webauthn::Migration),
+            Box::new(m20251021_120000_account_deletion::Migration),
         ]
     }
 }
@@ -19,3 +22,6 @@ mod m20250306_130625_init;
 mod m20250309_125602_user_data_update;
 // mod m20250418_172130_perms;
 mod m20250807_160804_user_profiles;
+mod m20251019_120000_two_factor;
+mod m20251020_120000_webauthn;
+mod m20251021_120000_account_deletion;
@@ -0,0 +1,39 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter()
+                .table(UserData::Table)
+                // encrypted, set on enrolment and used only after confirmation
+                .add_column(string_null(UserData::TotpSecret).null())
+                .add_column(boolean(UserData::TotpEnabled).not_null().default(false))
+                // sha256 of unused recovery codes
+                .add_column(array(UserData::TotpRecoveryCodes, ColumnType::String(StringLen::None)).default(Vec::<String>::new()))
+            .to_owned()
+        ).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter()
+                .table(UserData::Table)
+                .drop_column(UserData::TotpSecret)
+                .drop_column(UserData::TotpEnabled)
+                .drop_column(UserData::TotpRecoveryCodes)
+            .to_owned()
+        ).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserData {
+    Table,
+    TotpSecret,
+    TotpEnabled,
+    TotpRecoveryCodes,
+}
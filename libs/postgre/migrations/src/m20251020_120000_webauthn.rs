@@ -0,0 +1,59 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+use crate::m20250306_130625_init::{UserData as UserDataTable};
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(Table::create()
+                .table(WebauthnCredential::Table)
+                .if_not_exists()
+                // base64url of the authenticator credential id
+                .col(string(WebauthnCredential::CredentialId).not_null().primary_key())
+                .col(uuid(WebauthnCredential::UserGUID).not_null())
+                .foreign_key(
+                    ForeignKey::create()
+                        .name("fk-webauthn_credential-user_guid")
+                        .from(WebauthnCredential::Table, WebauthnCredential::UserGUID)
+                        .to(UserDataTable::Table, UserDataTable::GUID)
+                        .on_delete(ForeignKeyAction::Cascade)
+                    )
+                // SEC1 encoded P-256 key
+                .col(binary(WebauthnCredential::PublicKey).not_null())
+                .col(big_integer(WebauthnCredential::SignCount).not_null().default(0))
+                .col(string(WebauthnCredential::Name).not_null())
+                .col(timestamp(WebauthnCredential::CreatedAt).extra("DEFAULT CURRENT_TIMESTAMP".to_string()))
+                .col(timestamp_null(WebauthnCredential::LastUsedAt).null())
+            .to_owned()
+        ).await?;
+        manager
+            .create_index(Index::create()
+                .name("idx-webauthn_credential-user_guid")
+                .table(WebauthnCredential::Table)
+                .col(WebauthnCredential::UserGUID)
+            .to_owned()
+        ).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(WebauthnCredential::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum WebauthnCredential {
+    Table,
+    CredentialId,
+    UserGUID,
+    PublicKey,
+    SignCount,
+    Name,
+    CreatedAt,
+    LastUsedAt,
+}
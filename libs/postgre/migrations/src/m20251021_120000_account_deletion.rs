@@ -0,0 +1,32 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter()
+                .table(UserData::Table)
+                // purge time of an account pending deletion
+                .add_column(timestamp_null(UserData::DeletionScheduledAt))
+            .to_owned()
+        ).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(Table::alter()
+                .table(UserData::Table)
+                .drop_column(UserData::DeletionScheduledAt)
+            .to_owned()
+        ).await
+    }
+}
+
+#[derive(DeriveIden)]
+enum UserData {
+    Table,
+    DeletionScheduledAt,
+}
@@ -4,6 +4,7 @@ pub mod redis;
 use shared::env_config;
 
 pub mod redis_tokens;
+pub mod redis_revocation;
 // pub mod redis_perms;
 
 env_config!(
@@ -16,5 +17,7 @@ env_config!(
     ".cfg" => CFG = EnvCfg{
         REDIS_REFRESH_TOKEN_LIFETIME : u64 = 30 * 24 * 60 * 60, // 30 days
         MAX_LIVE_SESSIONS : usize = 5,
+        // has to outlive access tokens
+        REDIS_REVOCATION_LIFETIME : u64 = 24 * 60 * 60, // 1 day
     }
 );
\ No newline at end of file
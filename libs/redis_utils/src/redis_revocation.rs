@@ -0,0 +1,51 @@
+use bb8_redis::redis::AsyncCommands;
+use uuid::Uuid;
+
+use anyhow::Result;
+
+use crate::{redis::RedisConn, CFG};
+
+
+
+const USER_REVOCATION_PREFIX : &str = "RVKU";
+const SESSION_REVOCATION_PREFIX : &str = "RVKS";
+
+fn user_to_key(user: &Uuid) -> String {
+    format!("{}::{}", USER_REVOCATION_PREFIX, user)
+}
+
+fn sid_to_key(sid: &Uuid) -> String {
+    format!("{}::{}", SESSION_REVOCATION_PREFIX, sid)
+}
+
+/// Access token revocation store, records only have to outlive access tokens
+pub trait RedisRevocation {
+    fn revoke_user_before(&self, user: &Uuid, before: i64) -> impl std::future::Future<Output = Result<()>> + Send;
+    fn revoke_session(&self, sid: &Uuid) -> impl std::future::Future<Output = Result<()>> + Send;
+    fn get_user_revoked_before(&self, user: &Uuid) -> impl std::future::Future<Output = Result<Option<i64>>> + Send;
+    fn is_session_revoked(&self, sid: &Uuid) -> impl std::future::Future<Output = Result<bool>> + Send;
+}
+
+impl RedisRevocation for RedisConn {
+    async fn revoke_user_before(&self, user: &Uuid, before: i64) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _: () = conn.set_ex(user_to_key(user), before, CFG.REDIS_REVOCATION_LIFETIME).await?;
+        Ok(())
+    }
+
+    async fn revoke_session(&self, sid: &Uuid) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _: () = conn.set_ex(sid_to_key(sid), 1, CFG.REDIS_REVOCATION_LIFETIME).await?;
+        Ok(())
+    }
+
+    async fn get_user_revoked_before(&self, user: &Uuid) -> Result<Option<i64>> {
+        let mut conn = self.pool.get().await?;
+        Ok(conn.get(user_to_key(user)).await?)
+    }
+
+    async fn is_session_revoked(&self, sid: &Uuid) -> Result<bool> {
+        let mut conn = self.pool.get().await?;
+        Ok(conn.exists(sid_to_key(sid)).await?)
+    }
+}
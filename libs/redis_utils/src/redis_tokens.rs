@@ -1,5 +1,5 @@
 use bb8::PooledConnection;
-use bb8_redis::{redis::{AsyncCommands, RedisError}, RedisConnectionManager};
+use bb8_redis::{redis::{AsyncCommands, ExistenceCheck, RedisError, SetExpiry, SetOptions}, RedisConnectionManager};
 use chrono::Utc;
 // use redis::{Client, FromRedisValue, RedisError, RedisResult};
 use tracing::info;
@@ -8,6 +8,7 @@ pub use redis::Commands;
 
 use shared::tokens::jwt::RefreshTokenRecord;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 
 use crate::{redis::RedisConn, CFG};
 
@@ -15,6 +16,7 @@ use crate::{redis::RedisConn, CFG};
 
 const REFRESH_TOKEN_PREFIX : &str = "RTID";
 const USER_TOKEN_PAIR_PREFIX : &str = "UTPP";
+const ROTATED_TOKEN_PREFIX : &str = "RTRT";
 // const CRFS_TOKEN_PREFIX : &'static str = "CRFS";
 //const TEMPORARY_USERDATA_TOKEN_PREFIX : &'static str = "TMPR";
 
@@ -26,6 +28,19 @@ fn user_to_key(user: &Uuid) -> String{
     format!("{}::{}", USER_TOKEN_PAIR_PREFIX, user)
 }
 
+fn rotated_to_key(rtid: &Uuid) -> String{
+    format!("{}::{}", ROTATED_TOKEN_PREFIX, rtid)
+}
+
+/// Left behind by a rotated refresh token, kept as long as the token could be presented
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RotatedRefresh {
+    pub user: Uuid,
+    pub session: Uuid,
+    pub email: String,
+    pub rotated_at: i64,
+}
+
 pub trait RedisTokens {
     fn set_refresh(&self, record: RefreshTokenRecord) -> impl std::future::Future<Output = Result<()>> + Send;
     fn get_refresh(&self, rtid: String) -> impl std::future::Future<Output = Result<Option<RefreshTokenRecord>>> + Send;
@@ -33,6 +48,11 @@ pub trait RedisTokens {
     fn rm_all_refresh(&self, user: &Uuid) -> impl std::future::Future<Output = Result<()>> + Send;
     fn pop_refresh(&self, rtid: &Uuid) -> impl std::future::Future<Output = Result<Option<RefreshTokenRecord>>> + Send;
     fn get_refresh_conn(&self, rtid: String, conn : &mut PooledConnection<'_, RedisConnectionManager>) -> impl std::future::Future<Output = Result<Option<RefreshTokenRecord>>> + Send;
+    /// Rewrites the email of every live session of `user`, expirations are kept
+    fn set_refresh_email(&self, user: &Uuid, email: &str) -> impl std::future::Future<Output = Result<()>> + Send;
+    /// Live records of `user`, records without a session id get one assigned
+    fn get_user_refresh(&self, user: &Uuid) -> impl std::future::Future<Output = Result<Vec<RefreshTokenRecord>>> + Send;
+    fn get_rotated_refresh(&self, rtid: &Uuid) -> impl std::future::Future<Output = Result<Option<RotatedRefresh>>> + Send;
 }
 
 
@@ -53,6 +73,10 @@ impl RedisTokens for RedisConn {
         }
         let _: () = conn.zadd(user_key.clone(), rtid_to_key(&record.rtid), now + CFG.REDIS_REFRESH_TOKEN_LIFETIME as i64).await?;
         let _: () = conn.set_ex(rtid_to_key(&record.rtid), serde_json::to_string(&record)?, CFG.REDIS_REFRESH_TOKEN_LIFETIME).await?;
+        if let Some(parent) = record.parent {
+            let rotated = RotatedRefresh {user: record.user, session: record.session, email: record.email.clone(), rotated_at: now};
+            let _: () = conn.set_ex(rotated_to_key(&parent), serde_json::to_string(&rotated)?, CFG.REDIS_REFRESH_TOKEN_LIFETIME).await?;
+        }
         Ok(())
     }
 
@@ -108,6 +132,41 @@ impl RedisTokens for RedisConn {
         Ok(None)
     }
 
+    async fn set_refresh_email(&self, user: &Uuid, email: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let keys: Vec<String> = conn.zrangebyscore(user_to_key(user), Utc::now().timestamp(), "+inf").await?;
+        for rtid_key in keys {
+            let Some(mut record) = self.get_refresh_conn(rtid_key.clone(), &mut conn).await? else {continue};
+            record.email = email.to_string();
+            let options = SetOptions::default().conditional_set(ExistenceCheck::XX).with_expiration(SetExpiry::KEEPTTL);
+            let _: Option<String> = conn.set_options(rtid_key, serde_json::to_string(&record)?, options).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_user_refresh(&self, user: &Uuid) -> Result<Vec<RefreshTokenRecord>> {
+        let mut conn = self.pool.get().await?;
+        let keys: Vec<String> = conn.zrangebyscore(user_to_key(user), Utc::now().timestamp(), "+inf").await?;
+        let mut records = Vec::with_capacity(keys.len());
+        for rtid_key in keys {
+            let Some(mut record) = self.get_refresh_conn(rtid_key.clone(), &mut conn).await? else {continue};
+            if record.session.is_nil() {
+                record.session = Uuid::new_v4();
+                let options = SetOptions::default().conditional_set(ExistenceCheck::XX).with_expiration(SetExpiry::KEEPTTL);
+                let _: Option<String> = conn.set_options(rtid_key, serde_json::to_string(&record)?, options).await?;
+            }
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    async fn get_rotated_refresh(&self, rtid: &Uuid) -> Result<Option<RotatedRefresh>> {
+        let mut conn = self.pool.get().await?;
+        let s : Option<String> = conn.get(rotated_to_key(rtid)).await?;
+        let Some(s) = s else {return Ok(None)};
+        Ok(Some(serde_json::from_str(&s)?))
+    }
+
     // pub async fn set_crfs(
     //     &self,
     //     crfs: &String,
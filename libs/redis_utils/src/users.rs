@@ -7,6 +7,14 @@ use crate::redis::RedisConn;
 
 pub const USER_GUIDS_HASH: &str = "USER_GUIDS";
 pub const USER_UIDS_HASH: &str = "USER_UIDS";
+/// Released uid to its former owner, kept while the uid is reserved
+pub const UID_REDIRECT_PREFIX: &str = "UID_REDIRECT";
+/// Profile caches of the user service, they embed the uid
+pub const PROFILE_CACHE_PREFIX: &str = "PROFILE:";
+
+fn redirect_key(uid: &str) -> String {
+    format!("{UID_REDIRECT_PREFIX}:{uid}")
+}
 
 pub trait RedisUsers {
     async fn get_user_guid(&self, user_uid: &str) -> Result<Option<Uuid>>;
@@ -15,6 +23,13 @@ pub trait RedisUsers {
     async fn add_user(&self, user_guid: &Uuid, user_uid: &str) -> Result<()>;
     async fn remove_user(&self, user_guid: &Uuid, user_uid: &str) -> Result<()>;
     async fn get_user_guids(&self) -> Result<Vec<Uuid>>;
+    /// Moves the user to `new_uid` in one transaction, `old_uid` keeps redirecting to the user for `reserve_for` seconds
+    async fn rename_user(&self, user_guid: &Uuid, old_uid: &str, new_uid: &str, reserve_for: u64) -> Result<()>;
+    async fn get_uid_redirect(&self, user_uid: &str) -> Result<Option<Uuid>>;
+    /// Removes the user from lookups, the uid stays reserved for `reserve_for` seconds by a redirect to nowhere
+    async fn hide_user(&self, user_guid: &Uuid, user_uid: &str, reserve_for: u64) -> Result<()>;
+    /// Reverts [`RedisUsers::hide_user`]
+    async fn unhide_user(&self, user_guid: &Uuid, user_uid: &str) -> Result<()>;
 }
 
 impl RedisUsers for RedisConn {
@@ -68,4 +83,57 @@ impl RedisUsers for RedisConn {
         let _: () = conn.hdel(USER_UIDS_HASH, user_uid).await?;
         Ok(())
     }
+
+    async fn rename_user(&self, user_guid: &Uuid, old_uid: &str, new_uid: &str, reserve_for: u64) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        info!("Renaming user {} {} -> {}", user_guid, old_uid, new_uid);
+        let guid = user_guid.simple().to_string();
+        let _: () = bb8_redis::redis::pipe()
+            .atomic()
+            .hset(USER_GUIDS_HASH, &guid, new_uid).ignore()
+            .hdel(USER_UIDS_HASH, old_uid).ignore()
+            .hset(USER_UIDS_HASH, new_uid, &guid).ignore()
+            // taking back an own reserved uid
+            .del(redirect_key(new_uid)).ignore()
+            .set_ex(redirect_key(old_uid), &guid, reserve_for).ignore()
+            .del(format!("{PROFILE_CACHE_PREFIX}{guid}")).ignore()
+            .query_async(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn hide_user(&self, user_guid: &Uuid, user_uid: &str, reserve_for: u64) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        info!("Hiding user {} {}", user_guid, user_uid);
+        let guid = user_guid.simple().to_string();
+        let _: () = bb8_redis::redis::pipe()
+            .atomic()
+            .hdel(USER_GUIDS_HASH, &guid).ignore()
+            .hdel(USER_UIDS_HASH, user_uid).ignore()
+            .set_ex(redirect_key(user_uid), &guid, reserve_for).ignore()
+            .del(format!("{PROFILE_CACHE_PREFIX}{guid}")).ignore()
+            .query_async(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn unhide_user(&self, user_guid: &Uuid, user_uid: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        info!("Unhiding user {} {}", user_guid, user_uid);
+        let guid = user_guid.simple().to_string();
+        let _: () = bb8_redis::redis::pipe()
+            .atomic()
+            .hset(USER_GUIDS_HASH, &guid, user_uid).ignore()
+            .hset(USER_UIDS_HASH, user_uid, &guid).ignore()
+            .del(redirect_key(user_uid)).ignore()
+            .query_async(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_uid_redirect(&self, user_uid: &str) -> Result<Option<Uuid>> {
+        let mut conn = self.pool.get().await?;
+        let guid_str: Option<String> = conn.get(redirect_key(user_uid)).await?;
+        Ok(guid_str.map(|g| g.parse()).transpose()?)
+    }
 }
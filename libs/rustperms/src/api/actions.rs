@@ -38,6 +38,8 @@ impl RustpermsDelta {
     pub fn push_many(&mut self, actions: Vec<impl Into<RustpermsOperation>>){
         self.ops.extend(actions.into_iter().map(|v|v.into()));
     }
+    pub fn len(&self) -> usize {self.ops.len()}
+    pub fn is_empty(&self) -> bool {self.ops.is_empty()}
     pub fn serialize_to_string(self) -> anyhow::Result<String> {
         let e = encode_to_vec(self.ops, bincode::config::standard())?;
         Ok(BASE64_URL_SAFE_NO_PAD.encode(e))
@@ -74,4 +76,13 @@ pub enum RustpermsOperation {
     GroupRemoveDependentGroups(GroupUID, Vec<GroupUID>),
     GroupAddUsers(GroupUID, Vec<UserUID>),
     GroupRemoveUsers(GroupUID, Vec<UserUID>),
+    /// Adds users to group scoped to resource prefix (e.g. `calls.room.<guid>`), group rules apply only under it
+    GroupAddScopedUsers(GroupUID, PermissionPath, Vec<UserUID>),
+    GroupRemoveScopedUsers(GroupUID, PermissionPath, Vec<UserUID>),
+
+    /// Declares group as sharded (or reshards it online). `shards: 0` merges everything back into the base group.
+    /// Routed by the manager into primitive operations, never stored as is.
+    GroupReshard{group_uid: GroupUID, shards: u32},
+    /// Primitive: only updates sharding metadata of the base group and its existing shards.
+    GroupSetShards{group_uid: GroupUID, shards: u32},
 }
@@ -1,5 +1,6 @@
 pub mod util;
 pub mod actions;
+pub mod policy;
 
 pub mod prelude {
     use crate::api::actions;
@@ -0,0 +1,260 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{api::{actions::{RustpermsDelta, RustpermsOperation}, util::group_to_shards}, prelude::*};
+
+// declarative description of groups, their weights, inheritance and rules
+// members are runtime data and intentionally not a part of the policy
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    #[serde(default)]
+    pub groups: BTreeMap<GroupUID, GroupPolicy>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupPolicy {
+    #[serde(default)]
+    pub weight: i32,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub shards: u32,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub inherits: BTreeSet<GroupUID>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rules: BTreeMap<String, bool>,
+}
+
+fn is_zero(v: &u32) -> bool {*v == 0}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyChange {
+    GroupCreate{group: GroupUID, weight: i32},
+    GroupRemove(GroupUID),
+    Weight{group: GroupUID, from: i32, to: i32},
+    Shards{group: GroupUID, from: u32, to: u32},
+    RuleSet{group: GroupUID, rule: String, from: Option<bool>, to: bool},
+    RuleRemove{group: GroupUID, rule: String, was: bool},
+    InheritAdd{group: GroupUID, parent: GroupUID},
+    InheritRemove{group: GroupUID, parent: GroupUID},
+}
+
+impl Display for PolicyChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyChange::GroupCreate{group, weight} => write!(f, "+ group {group} (weight {weight})"),
+            PolicyChange::GroupRemove(group) => write!(f, "- group {group}"),
+            PolicyChange::Weight{group, from, to} => write!(f, "~ {group}: weight {from} -> {to}"),
+            PolicyChange::Shards{group, from, to} => write!(f, "~ {group}: shards {from} -> {to}"),
+            PolicyChange::RuleSet{group, rule, from: None, to} => write!(f, "+ {group}: {rule} = {to}"),
+            PolicyChange::RuleSet{group, rule, from: Some(from), to} => write!(f, "~ {group}: {rule} = {from} -> {to}"),
+            PolicyChange::RuleRemove{group, rule, was} => write!(f, "- {group}: {rule} (was {was})"),
+            PolicyChange::InheritAdd{group, parent} => write!(f, "+ {group} inherits {parent}"),
+            PolicyChange::InheritRemove{group, parent} => write!(f, "- {group} inherits {parent}"),
+        }
+    }
+}
+
+impl Policy {
+    pub fn from_yaml(s: &str) -> anyhow::Result<Self> {
+        Ok(serde_yaml::from_str(s)?)
+    }
+
+    pub fn to_yaml(&self) -> anyhow::Result<String> {
+        Ok(serde_yaml::to_string(self)?)
+    }
+
+    /// Builds policy from live groups, shards are folded back into their base group
+    pub fn from_groups(groups: &HashMap<GroupUID, Group>) -> Self {
+        let mut policy = Policy::default();
+        for group in groups.values().filter(|g| g.get_shard_of().is_none()) {
+            let shards = group_to_shards(group.get_group_uid(), group.get_shards() as usize);
+            let rules = group.get_rules().into_iter()
+                .chain(shards.iter().filter_map(|s| groups.get(s)).flat_map(|s| s.get_rules()))
+                .map(|(path, enabled)| (path.format(), enabled))
+                .collect();
+            let inherits = group.get_parents().iter()
+                .filter(|p| !shards.contains(p))
+                .cloned()
+                .collect();
+            policy.groups.insert(group.get_group_uid().clone(), GroupPolicy {
+                weight: group.get_weight(),
+                shards: group.get_shards(),
+                inherits,
+                rules,
+            });
+        }
+        policy
+    }
+
+    /// Changes required to turn `live` into `self`.
+    /// Groups missing in the policy are left untouched unless `prune` is set.
+    pub fn diff(&self, live: &Policy, prune: bool) -> Vec<PolicyChange> {
+        let mut creates = vec![];
+        let mut updates = vec![];
+        let mut rules = vec![];
+        let mut inherits = vec![];
+        let mut removes = vec![];
+
+        for (name, desired) in self.groups.iter() {
+            let created = GroupPolicy{weight: desired.weight, ..Default::default()};
+            let current = match live.groups.get(name) {
+                Some(current) => current,
+                None => {
+                    creates.push(PolicyChange::GroupCreate{group: name.clone(), weight: desired.weight});
+                    &created
+                }
+            };
+            if current.weight != desired.weight {
+                updates.push(PolicyChange::Weight{group: name.clone(), from: current.weight, to: desired.weight});
+            }
+            if current.shards != desired.shards {
+                updates.push(PolicyChange::Shards{group: name.clone(), from: current.shards, to: desired.shards});
+            }
+            for (rule, to) in desired.rules.iter() {
+                let from = current.rules.get(rule).copied();
+                if from != Some(*to) {
+                    rules.push(PolicyChange::RuleSet{group: name.clone(), rule: rule.clone(), from, to: *to});
+                }
+            }
+            for (rule, was) in current.rules.iter().filter(|(r, _)| !desired.rules.contains_key(*r)) {
+                rules.push(PolicyChange::RuleRemove{group: name.clone(), rule: rule.clone(), was: *was});
+            }
+            for parent in desired.inherits.difference(&current.inherits) {
+                inherits.push(PolicyChange::InheritAdd{group: name.clone(), parent: parent.clone()});
+            }
+            for parent in current.inherits.difference(&desired.inherits) {
+                inherits.push(PolicyChange::InheritRemove{group: name.clone(), parent: parent.clone()});
+            }
+        }
+        if prune {
+            for name in live.groups.keys().filter(|g| !self.groups.contains_key(*g)) {
+                removes.push(PolicyChange::GroupRemove(name.clone()));
+            }
+        }
+
+        // groups have to exist before anything refers to them
+        creates.into_iter()
+            .chain(updates)
+            .chain(rules)
+            .chain(inherits)
+            .chain(removes)
+            .collect()
+    }
+
+    /// Minimal delta which makes `live` converge to the policy
+    pub fn delta(&self, live: &Policy, prune: bool) -> RustpermsDelta {
+        changes_to_delta(self.diff(live, prune))
+    }
+}
+
+/// Converts changes into operations, neighbouring rule and inheritance changes of one group are batched
+pub fn changes_to_delta(changes: Vec<PolicyChange>) -> RustpermsDelta {
+    let mut ops: Vec<RustpermsOperation> = vec![];
+    for change in changes {
+        match (ops.last_mut(), change) {
+            (Some(RustpermsOperation::GroupUpdatePerms(g, ps)), PolicyChange::RuleSet{group, rule, to, ..}) if *g == group => {
+                ps.push((rule.into_perm(), to));
+            }
+            (Some(RustpermsOperation::GroupRemovePerms(g, ps)), PolicyChange::RuleRemove{group, rule, ..}) if *g == group => {
+                ps.push(rule.into_perm());
+            }
+            (Some(RustpermsOperation::GroupAddGroupsToInherit(g, gs)), PolicyChange::InheritAdd{group, parent}) if *g == group => {
+                gs.push(parent);
+            }
+            (Some(RustpermsOperation::GroupRemoveToInherit(g, gs)), PolicyChange::InheritRemove{group, parent}) if *g == group => {
+                gs.push(parent);
+            }
+            (_, change) => ops.push(change.into()),
+        }
+    }
+    ops.into()
+}
+
+impl From<PolicyChange> for RustpermsOperation {
+    fn from(change: PolicyChange) -> Self {
+        match change {
+            PolicyChange::GroupCreate{group, weight} => RustpermsOperation::GroupCreate{group_uid: group, weight},
+            PolicyChange::GroupRemove(group) => RustpermsOperation::GroupRemove(group),
+            PolicyChange::Weight{group, to, ..} => RustpermsOperation::GroupUpdate{group_uid: group, weight: to},
+            PolicyChange::Shards{group, to, ..} => RustpermsOperation::GroupReshard{group_uid: group, shards: to},
+            PolicyChange::RuleSet{group, rule, to, ..} => RustpermsOperation::GroupUpdatePerms(group, vec![(rule.into_perm(), to)]),
+            PolicyChange::RuleRemove{group, rule, ..} => RustpermsOperation::GroupRemovePerms(group, vec![rule.into_perm()]),
+            PolicyChange::InheritAdd{group, parent} => RustpermsOperation::GroupAddGroupsToInherit(group, vec![parent]),
+            PolicyChange::InheritRemove{group, parent} => RustpermsOperation::GroupRemoveToInherit(group, vec![parent]),
+        }
+    }
+}
+
+impl AsyncManager {
+    pub async fn export_policy(&self) -> Policy {
+        Policy::from_groups(&*self.groups.read().await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn live() -> AsyncManager {
+        AsyncManager::from(RustpermsDelta::from(vec![
+            RustpermsOperation::GroupCreate { group_uid: "default".into(), weight: 0 },
+            RustpermsOperation::GroupCreate { group_uid: "authed".into(), weight: 10 },
+            RustpermsOperation::GroupReshard { group_uid: "authed".into(), shards: 4 },
+            RustpermsOperation::GroupUpdatePerms("default".into(), vec![("calls.*".into_perm(), true)]),
+            RustpermsOperation::GroupUpdatePerms("authed".into(), vec![("store.upload.*".into_perm(), true), ("a.b".into_perm(), false)]),
+            RustpermsOperation::GroupAddGroupsToInherit("authed".into(), vec!["default".into()]),
+        ]))
+    }
+
+    #[tokio::test]
+    async fn export_folds_shards() {
+        let policy = live().export_policy().await;
+        assert_eq!(policy.groups.len(), 2);
+        let authed = policy.groups.get("authed").unwrap();
+        assert_eq!(authed.shards, 4);
+        assert_eq!(authed.inherits, BTreeSet::from(["default".to_string()]));
+        assert_eq!(authed.rules.len(), 2);
+        assert_eq!(Policy::from_yaml(&policy.to_yaml().unwrap()).unwrap(), policy);
+    }
+
+    #[tokio::test]
+    async fn delta_converges() {
+        let manager = live();
+        let current = manager.export_policy().await;
+        let desired = Policy::from_yaml(r#"
+groups:
+  default:
+    weight: 0
+    rules:
+      calls.*: true
+      calls.room.*: false
+  authed:
+    weight: 15
+    shards: 2
+    inherits: [default]
+    rules:
+      store.upload.*: true
+  moderator:
+    weight: 20
+    inherits: [authed]
+"#).unwrap();
+
+        assert!(!desired.delta(&current, false).is_empty());
+        manager.apply(desired.delta(&current, false)).await;
+        let converged = manager.export_policy().await;
+        assert_eq!(converged, desired);
+        assert!(desired.delta(&converged, false).is_empty());
+    }
+
+    #[tokio::test]
+    async fn prune_removes_undeclared_groups() {
+        let current = live().export_policy().await;
+        let mut desired = current.clone();
+        desired.groups.remove("default");
+        desired.groups.get_mut("authed").unwrap().inherits.clear();
+
+        assert!(desired.diff(&current, false).iter().all(|c| !matches!(c, PolicyChange::GroupRemove(_))));
+        assert!(desired.diff(&current, true).contains(&PolicyChange::GroupRemove("default".into())));
+    }
+}
@@ -14,13 +14,18 @@ pub type GroupUID = String;
 pub struct Group {
     pub(crate) name: GroupUID,
     pub(crate) members: HashSet<UserUID>,
+    pub(crate) scoped_members: HashSet<UserUID>,
 
     pub(crate) permissions: PermissionRuleNode,
 
     pub(crate) parents: HashSet<GroupUID>,
     pub(crate) children: HashSet<GroupUID>,
 
-    pub(crate) weight: i32
+    pub(crate) weight: i32,
+
+    // engine-managed sharding: base group keeps the shard count, every shard points back to its base
+    pub(crate) shards: u32,
+    pub(crate) shard_of: Option<GroupUID>,
 }
 
 impl Group {
@@ -28,10 +33,13 @@ impl Group {
         Self {
             name,
             members: HashSet::new(),
+            scoped_members: HashSet::new(),
             permissions: PermissionRuleNode::new(),
             parents: HashSet::new(),
             children: HashSet::new(),
-            weight
+            weight,
+            shards: 0,
+            shard_of: None,
         }
     }
     pub fn get_group_uid(&self) -> &GroupUID {&self.name}
@@ -43,6 +51,10 @@ impl Group {
     pub fn remove_member(&mut self, member: &UserUID) {self.members.remove(member);}
     pub fn remove_members(&mut self, members: Vec<UserUID>) {for member in members {self.remove_member(&member);}}
 
+    pub fn get_scoped_members(&self) -> &HashSet<UserUID> {&self.scoped_members}
+    pub fn add_scoped_member(&mut self, member: UserUID) {self.scoped_members.insert(member);}
+    pub fn remove_scoped_member(&mut self, member: &UserUID) {self.scoped_members.remove(member);}
+
     pub fn get_parents(&self) -> &HashSet<GroupUID> {&self.parents}
     pub fn has_parent(&self, parent: &GroupUID) -> bool {self.parents.contains(parent)}
     pub fn add_parent(&mut self, parent: GroupUID) {self.parents.insert(parent);}
@@ -60,6 +72,10 @@ impl Group {
     pub fn with_weight(self, weight: i32) -> Self {Self {weight, ..self} }
     pub fn set_weight(&mut self, weight: i32) { self.weight = weight }
     pub fn get_weight(&self) -> i32 {self.weight}
+
+    pub fn get_shards(&self) -> u32 {self.shards}
+    pub fn is_sharded(&self) -> bool {self.shards != 0}
+    pub fn get_shard_of(&self) -> Option<&GroupUID> {self.shard_of.as_ref()}
 }
 
 impl PermissionInterface for Group {
@@ -1,8 +1,9 @@
 use std::{collections::{hash_map::Entry, HashMap, HashSet, VecDeque}};
-use crate::{api::actions::{RustpermsDelta, RustpermsOperation}, prelude::*};
+use crate::{api::{actions::{RustpermsDelta, RustpermsOperation}, util::{group_to_shards, rule_to_shard, user_to_shard}}, prelude::*};
 use base64::{prelude::BASE64_URL_SAFE_NO_PAD, Engine};
 use bincode::serde::{decode_from_slice, encode_to_vec};
 use ::tokio::sync::RwLock;
+use serde::{Deserialize, Serialize};
 
 
 
@@ -33,40 +34,77 @@ impl From<RustpermsDelta> for AsyncManager {
         let mut users = HashMap::new();
         let mut groups = HashMap::new();
         for action in actions.into_iter() {
-            Self::apply_action(&mut users, &mut groups, action);
+            for action in Self::route_action(&groups, action) {
+                Self::apply_action(&mut users, &mut groups, action);
+            }
         }
         Self {users: RwLock::new(users), groups: RwLock::new(groups)}
     }
 }
 
 use anyhow::Result;
-use tracing::info;
 
 pub const GUEST_GROUP : &str = "guest";
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum ExplainSource {
+    User(UserUID),
+    Group(GroupUID),
+}
+
+/// One visited user or group, `rule` is the best rule it has for the checked path.
+/// `depth` is the distance from the user: 0 for the user itself, 1 for its direct groups
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExplainStep {
+    pub source: ExplainSource,
+    pub depth: usize,
+    pub weight: i32,
+    pub rule: Option<(bool, MatchType)>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Explanation {
+    pub result: Option<(bool, MatchType)>,
+    pub steps: Vec<ExplainStep>,
+}
+
 impl AsyncManager {
     pub async fn check_perm(&self, user_uid: &UserUID, permission: &PermissionPath) -> Option<(bool, MatchType)> {
+        self.check_perm_with(user_uid, permission, |_| {}).await
+    }
+
+    /// Same as `check_perm`, but also records every user/group visited during resolution
+    pub async fn explain_perm(&self, user_uid: &UserUID, permission: &PermissionPath) -> Explanation {
+        let mut steps = vec![];
+        let result = self.check_perm_with(user_uid, permission, |step| steps.push(step)).await;
+        Explanation { result, steps }
+    }
+
+    /// Resolves permission calling `on_step` for every visited user and group
+    pub async fn check_perm_with(&self, user_uid: &UserUID, permission: &PermissionPath, mut on_step: impl FnMut(ExplainStep)) -> Option<(bool, MatchType)> {
         let mut result_rule;
-        let mut to_check: VecDeque<GroupUID> ;
+        let mut to_check: VecDeque<(GroupUID, usize)> ;
         if user_uid == &"" {
             result_rule = (None, 0);
-            to_check = VecDeque::from([GUEST_GROUP.to_string()]);
+            to_check = VecDeque::from([(GUEST_GROUP.to_string(), 1)]);
         } else {
             let users = self.users.read().await;
             let user= users.get(user_uid)?;
             result_rule = (user.get_perm(permission), RUSTPERMS_USER_WEIGHT);
-            to_check = user.get_groups().iter().cloned().collect();
+            on_step(ExplainStep { source: ExplainSource::User(user_uid.clone()), depth: 0, weight: RUSTPERMS_USER_WEIGHT, rule: result_rule.0 });
+            to_check = user.get_groups().iter().map(|g| (g.clone(), 1)).collect();
+            to_check.extend(user.groups_in_scope(permission).map(|g| (g.clone(), 1)));
             drop(users);
         }
-        info!("Checking permission {} for user {}", permission.format(), user_uid);
-        info!("Groups to check: {:#?}", to_check);
 
         let mut checked: HashSet<GroupUID> = HashSet::new();
         let groups = self.groups.read().await;
-        while let Some(group_uid) = to_check.pop_front() {
+        while let Some((group_uid, depth)) = to_check.pop_front() {
             if let Some(group) = groups.get(&group_uid) {
+                let matched = group.get_perm(permission);
+                on_step(ExplainStep { source: ExplainSource::Group(group_uid.clone()), depth, weight: group.get_weight(), rule: matched });
                 'rule_update: {
-                    let Some(allowed)= group.get_perm(permission) else {break 'rule_update};
+                    let Some(allowed) = matched else {break 'rule_update};
                     let w = group.get_weight();
                     match result_rule.0 {
                         Some(result_allowed) => {
@@ -86,14 +124,15 @@ impl AsyncManager {
                         None => result_rule = (Some(allowed), w),
                     }
                 }
-                info!("Checked group {}", group_uid);
-                info!("State: {:#?}", result_rule);
-                info!("Parents: {:#?}", group.get_parents());
                 for parent in group.get_parents() {
                     if !checked.contains::<GroupUID>(parent) {
-                        to_check.push_back(parent.clone());
+                        to_check.push_back((parent.clone(), depth + 1));
                     }
                 }
+                // shard members inherit everything from the base group
+                if let Some(base) = group.get_shard_of() && !checked.contains::<GroupUID>(base) {
+                    to_check.push_back((base.clone(), depth + 1));
+                }
             }
             checked.insert(group_uid);
         }
@@ -135,6 +174,9 @@ impl AsyncManager {
                 for g in user.groups {
                     groups.entry(g).and_modify(|g| g.remove_member(&u));
                 }
+                for g in user.scoped_groups.into_keys() {
+                    groups.entry(g).and_modify(|g| g.remove_scoped_member(&u));
+                }
                 true
             }
             RustpermsOperation::UserUpdatePerms(u, p) => {
@@ -162,6 +204,9 @@ impl AsyncManager {
                 for u in group.members {
                     users.entry(u).and_modify(|u| u.remove_group(&g));
                 }
+                for u in group.scoped_members {
+                    users.entry(u).and_modify(|u| u.remove_all_scoped_group(&g));
+                }
                 for gc in group.children {
                     groups.entry(gc).and_modify(|gc| gc.remove_parent(&g));
                 }
@@ -234,14 +279,150 @@ impl AsyncManager {
                 g.remove_members(us);
                 true
             },
+            RustpermsOperation::GroupAddScopedUsers(g, scope, us) => {
+                let Some(group) = groups.get_mut(&g) else {return false};
+                for user in us {
+                    let Some(u) = users.get_mut(&user) else {continue};
+                    u.add_scoped_group(g.clone(), scope.clone());
+                    group.add_scoped_member(user);
+                }
+                true
+            },
+            RustpermsOperation::GroupRemoveScopedUsers(g, scope, us) => {
+                let Some(group) = groups.get_mut(&g) else {return false};
+                for user in us {
+                    let Some(u) = users.get_mut(&user) else {continue};
+                    if u.remove_scoped_group(&g, &scope) {
+                        group.remove_scoped_member(&user);
+                    }
+                }
+                true
+            },
+            RustpermsOperation::GroupReshard { .. } => {
+                let mut changed = false;
+                for action in Self::route_action(groups, action) {
+                    changed |= Self::apply_action(users, groups, action);
+                }
+                changed
+            },
+            RustpermsOperation::GroupSetShards { group_uid: g, shards } => {
+                let Some(group) = groups.get_mut(&g) else {return false};
+                let previous = group.shards;
+                group.shards = shards;
+                for shard in group_to_shards(&g, previous as usize) {
+                    groups.entry(shard).and_modify(|s| s.shard_of = None);
+                }
+                for shard in group_to_shards(&g, shards as usize) {
+                    groups.entry(shard).and_modify(|s| s.shard_of = Some(g.clone()));
+                }
+                true
+            },
+        }
+    }
+
+    /// Expands operation on sharded group into primitive operations on its shards.
+    /// Result depends only on current state, so master and replicas route the same way.
+    pub fn route_action(groups: &HashMap<GroupUID, Group>, action: RustpermsOperation) -> Vec<RustpermsOperation> {
+        let sharded = |g: &GroupUID| groups.get(g).filter(|g| g.is_sharded()).map(|g| g.get_shards() as usize);
+        match action {
+            RustpermsOperation::GroupAddUsers(g, us) if let Some(shards) = sharded(&g) => {
+                let mut routed: HashMap<GroupUID, Vec<UserUID>> = HashMap::new();
+                for u in us {
+                    routed.entry(user_to_shard(&g, &u, shards)).or_default().push(u);
+                }
+                routed.into_iter().map(|(s, us)| RustpermsOperation::GroupAddUsers(s, us)).collect()
+            },
+            RustpermsOperation::GroupRemoveUsers(g, us) if let Some(shards) = sharded(&g) => {
+                let mut routed: HashMap<GroupUID, Vec<UserUID>> = HashMap::new();
+                for u in us {
+                    routed.entry(user_to_shard(&g, &u, shards)).or_default().push(u);
+                }
+                routed.into_iter().map(|(s, us)| RustpermsOperation::GroupRemoveUsers(s, us)).collect()
+            },
+            RustpermsOperation::GroupUpdatePerms(g, ps) if let Some(shards) = sharded(&g) => {
+                let mut routed: HashMap<GroupUID, Vec<PermissionRule>> = HashMap::new();
+                for p in ps {
+                    routed.entry(rule_to_shard(&g, &p.0, shards)).or_default().push(p);
+                }
+                routed.into_iter().map(|(s, ps)| RustpermsOperation::GroupUpdatePerms(s, ps)).collect()
+            },
+            RustpermsOperation::GroupRemovePerms(g, ps) if let Some(shards) = sharded(&g) => {
+                let mut routed: HashMap<GroupUID, Vec<PermissionPath>> = HashMap::new();
+                for p in ps {
+                    routed.entry(rule_to_shard(&g, &p, shards)).or_default().push(p);
+                }
+                routed.into_iter().map(|(s, ps)| RustpermsOperation::GroupRemovePerms(s, ps)).collect()
+            },
+            RustpermsOperation::GroupUpdate { group_uid: g, weight } if let Some(shards) = sharded(&g) => {
+                group_to_shards(&g, shards).into_iter()
+                    .chain([g])
+                    .map(|group_uid| RustpermsOperation::GroupUpdate { group_uid, weight })
+                    .collect()
+            },
+            RustpermsOperation::GroupRemove(g) if let Some(shards) = sharded(&g) => {
+                group_to_shards(&g, shards).into_iter()
+                    .chain([g])
+                    .map(RustpermsOperation::GroupRemove)
+                    .collect()
+            },
+            RustpermsOperation::GroupReshard { group_uid: g, shards } => {
+                let Some(base) = groups.get(&g) else {return vec![]};
+                // already distributed, nothing to move
+                if base.get_shards() == shards && (shards == 0 || (base.get_members().is_empty() && base.get_rules().is_empty())) {
+                    return vec![];
+                }
+                Self::reshard(groups, base, shards)
+            },
+            action => vec![action],
+        }
+    }
+
+    // Collects rules and members from base group and all of its shards and redistributes them over new shards.
+    // Metadata is cleared first, so routed operations stay primitive even if routed again.
+    fn reshard(groups: &HashMap<GroupUID, Group>, base: &Group, shards: u32) -> Vec<RustpermsOperation> {
+        let g = base.get_group_uid().clone();
+        let old_shards = group_to_shards(&g, base.get_shards() as usize);
+        let mut rules = base.get_rules();
+        let mut members: Vec<UserUID> = base.get_members().iter().cloned().collect();
+        for shard in old_shards.iter().filter_map(|s| groups.get(s)) {
+            rules.extend(shard.get_rules());
+            members.extend(shard.get_members().iter().cloned());
+        }
+
+        let mut ops = vec![RustpermsOperation::GroupSetShards { group_uid: g.clone(), shards: 0 }];
+        ops.extend(old_shards.into_iter().map(RustpermsOperation::GroupRemove));
+        if shards == 0 {
+            ops.push(RustpermsOperation::GroupUpdatePerms(g.clone(), rules));
+            ops.push(RustpermsOperation::GroupAddUsers(g, members));
+            return ops;
+        }
+        ops.push(RustpermsOperation::GroupRemovePerms(g.clone(), base.get_rules().into_iter().map(|(p, _)| p).collect()));
+        ops.push(RustpermsOperation::GroupRemoveUsers(g.clone(), base.get_members().iter().cloned().collect()));
+        let new_shards = group_to_shards(&g, shards as usize);
+        ops.extend(new_shards.iter().map(|s| RustpermsOperation::GroupCreate { group_uid: s.clone(), weight: base.get_weight() }));
+        ops.push(RustpermsOperation::GroupAddGroupsToInherit(g.clone(), new_shards));
+        ops.push(RustpermsOperation::GroupSetShards { group_uid: g.clone(), shards });
+
+        let mut routed_rules: HashMap<GroupUID, Vec<PermissionRule>> = HashMap::new();
+        for rule in rules {
+            routed_rules.entry(rule_to_shard(&g, &rule.0, shards as usize)).or_default().push(rule);
+        }
+        let mut routed_members: HashMap<GroupUID, Vec<UserUID>> = HashMap::new();
+        for member in members {
+            routed_members.entry(user_to_shard(&g, &member, shards as usize)).or_default().push(member);
         }
+        ops.extend(routed_rules.into_iter().map(|(s, rs)| RustpermsOperation::GroupUpdatePerms(s, rs)));
+        ops.extend(routed_members.into_iter().map(|(s, us)| RustpermsOperation::GroupAddUsers(s, us)));
+        ops
     }
 
     pub async fn apply(&self, actions: RustpermsDelta) {
         let mut users = self.users.write().await;
         let mut groups = self.groups.write().await;
         for action in actions.into_iter() {
-            Self::apply_action(&mut users, &mut groups, action);
+            for action in Self::route_action(&groups, action) {
+                Self::apply_action(&mut users, &mut groups, action);
+            }
         }
     }
 }
@@ -407,4 +588,125 @@ mod tests {
         let res = manager.check_perm(&"u".into(), &path("a.b")).await;
         assert_eq!(res, Some((true, MatchType::Exact)));
     }
+
+    #[tokio::test]
+    async fn sharded_group_routes_members_and_rules() {
+        let manager = AsyncManager::default();
+        manager.apply(vec![
+            RustpermsOperation::GroupCreate { group_uid: "g".into(), weight: 10 },
+            RustpermsOperation::GroupReshard { group_uid: "g".into(), shards: 4 },
+            RustpermsOperation::UserCreate("u1".into()),
+            RustpermsOperation::UserCreate("u2".into()),
+            RustpermsOperation::GroupAddUsers("g".into(), vec!["u1".into(), "u2".into()]),
+            RustpermsOperation::GroupUpdatePerms("g".into(), vec![rule("a.b", true), rule("c.d", true)]),
+        ].into()).await;
+
+        let groups = manager.groups.read().await;
+        let base = groups.get("g").unwrap();
+        assert_eq!(base.get_shards(), 4);
+        assert!(base.get_members().is_empty());
+        assert!(base.get_rules().is_empty());
+        assert_eq!(groups.get("g.0").unwrap().get_shard_of(), Some(&"g".to_string()));
+        drop(groups);
+
+        for user in ["u1", "u2"] {
+            for p in ["a.b", "c.d"] {
+                let res = manager.check_perm(&user.into(), &path(p)).await;
+                assert_eq!(res, Some((true, MatchType::Exact)));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reshard_preserves_members_and_rules() {
+        let manager = AsyncManager::default();
+        manager.apply(vec![
+            RustpermsOperation::GroupCreate { group_uid: "g".into(), weight: 10 },
+            RustpermsOperation::UserCreate("u".into()),
+            RustpermsOperation::GroupAddUsers("g".into(), vec!["u".into()]),
+            RustpermsOperation::GroupUpdatePerms("g".into(), vec![rule("a.b", true)]),
+            RustpermsOperation::GroupReshard { group_uid: "g".into(), shards: 3 },
+            RustpermsOperation::GroupReshard { group_uid: "g".into(), shards: 7 },
+        ].into()).await;
+
+        assert_eq!(manager.check_perm(&"u".into(), &path("a.b")).await, Some((true, MatchType::Exact)));
+        let groups = manager.groups.read().await;
+        assert_eq!(groups.get("g").unwrap().get_shards(), 7);
+        assert_eq!(groups.keys().filter(|g| g.starts_with("g.")).count(), 7);
+    }
+
+    #[tokio::test]
+    async fn unshard_merges_back_into_base() {
+        let manager = AsyncManager::default();
+        manager.apply(vec![
+            RustpermsOperation::GroupCreate { group_uid: "g".into(), weight: 10 },
+            RustpermsOperation::GroupReshard { group_uid: "g".into(), shards: 5 },
+            RustpermsOperation::UserCreate("u".into()),
+            RustpermsOperation::GroupAddUsers("g".into(), vec!["u".into()]),
+            RustpermsOperation::GroupUpdatePerms("g".into(), vec![rule("a.b", true)]),
+            RustpermsOperation::GroupReshard { group_uid: "g".into(), shards: 0 },
+        ].into()).await;
+
+        assert_eq!(manager.check_perm(&"u".into(), &path("a.b")).await, Some((true, MatchType::Exact)));
+        let groups = manager.groups.read().await;
+        let base = groups.get("g").unwrap();
+        assert!(!base.is_sharded());
+        assert!(base.get_members().contains("u"));
+        assert!(base.get_parents().is_empty());
+        assert_eq!(groups.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn scoped_group_applies_only_under_scope() {
+        let manager = AsyncManager::default();
+        manager.apply(vec![
+            RustpermsOperation::UserCreate("u".into()),
+            RustpermsOperation::GroupCreate { group_uid: "moderator".into(), weight: 10 },
+            RustpermsOperation::GroupUpdatePerms("moderator".into(), vec![rule("calls.room.?.kick", true)]),
+            RustpermsOperation::GroupAddScopedUsers("moderator".into(), path("calls.room.r1"), vec!["u".into()]),
+        ].into()).await;
+
+        assert_eq!(manager.check_perm(&"u".into(), &path("calls.room.r1.kick")).await, Some((true, MatchType::Any)));
+        assert_eq!(manager.check_perm(&"u".into(), &path("calls.room.r2.kick")).await, None);
+    }
+
+    #[tokio::test]
+    async fn scoped_group_removal() {
+        let manager = AsyncManager::default();
+        manager.apply(vec![
+            RustpermsOperation::UserCreate("u".into()),
+            RustpermsOperation::GroupCreate { group_uid: "editor".into(), weight: 10 },
+            RustpermsOperation::GroupUpdatePerms("editor".into(), vec![rule("posts.post.*", true)]),
+            RustpermsOperation::GroupAddScopedUsers("editor".into(), path("posts.post.p1"), vec!["u".into()]),
+            RustpermsOperation::GroupAddScopedUsers("editor".into(), path("posts.post.p2"), vec!["u".into()]),
+            RustpermsOperation::GroupRemoveScopedUsers("editor".into(), path("posts.post.p1"), vec!["u".into()]),
+        ].into()).await;
+
+        assert_eq!(manager.check_perm(&"u".into(), &path("posts.post.p1.edit")).await, None);
+        assert_eq!(manager.check_perm(&"u".into(), &path("posts.post.p2.edit")).await, Some((true, MatchType::Wildcard)));
+
+        manager.apply(vec![RustpermsOperation::GroupRemove("editor".into())].into()).await;
+        assert!(manager.users.read().await.get("u").unwrap().get_scoped_groups().is_empty());
+    }
+
+    #[tokio::test]
+    async fn explain_records_visited_groups() {
+        let manager = AsyncManager::default();
+        manager.apply(vec![
+            RustpermsOperation::UserCreate("u".into()),
+            RustpermsOperation::GroupCreate { group_uid: "g1".into(), weight: 10 },
+            RustpermsOperation::GroupCreate { group_uid: "g2".into(), weight: 20 },
+            RustpermsOperation::GroupUpdatePerms("g1".into(), vec![rule("a.*", true)]),
+            RustpermsOperation::GroupUpdatePerms("g2".into(), vec![rule("a.b", false)]),
+            RustpermsOperation::GroupAddGroupsToInherit("g2".into(), vec!["g1".into()]),
+            RustpermsOperation::GroupAddUsers("g2".into(), vec!["u".into()]),
+        ].into()).await;
+
+        let explanation = manager.explain_perm(&"u".into(), &path("a.b")).await;
+        assert_eq!(explanation.result, manager.check_perm(&"u".into(), &path("a.b")).await);
+        assert_eq!(explanation.result, Some((false, MatchType::Exact)));
+        assert_eq!(explanation.steps.len(), 3);
+        assert_eq!(explanation.steps[0], ExplainStep { source: ExplainSource::User("u".into()), depth: 0, weight: RUSTPERMS_USER_WEIGHT, rule: None });
+        assert_eq!(explanation.steps[2], ExplainStep { source: ExplainSource::Group("g1".into()), depth: 2, weight: 10, rule: Some((true, MatchType::Wildcard)) });
+    }
 }
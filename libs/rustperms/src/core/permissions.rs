@@ -72,6 +72,7 @@ impl Default for PermissionRuleNode {
     }
 }
 #[repr(u8)]
+#[derive(Serialize, Deserialize)]
 #[derive(Debug, Eq, PartialEq, PartialOrd, Ord, Copy, Clone)]
 pub enum MatchType {
     Wildcard,
@@ -180,6 +181,22 @@ impl PermissionRuleNode {
         records
     }
 
+    pub fn get_rules(&self) -> Vec<PermissionRule> {
+        let mut rules: Vec<PermissionRule> = Vec::new();
+        if let Some(enabled) = self.enabled {
+            rules.push((SmallVec::new(), enabled));
+        }
+        for (key, child) in self.children.iter() {
+            for (path, enabled) in child.get_rules() {
+                let mut new_path = SmallVec::with_capacity(path.len() + 1);
+                new_path.push(key.clone());
+                new_path.extend(path);
+                rules.push((new_path, enabled));
+            }
+        }
+        rules
+    }
+
     pub fn merge(&mut self, other: Self) {
         if other.enabled.is_some() {
             self.enabled = other.enabled;
@@ -203,6 +220,7 @@ pub trait PermissionInterface {
     fn get_perm(&self, path: &PermissionPath) -> Option<(bool, MatchType)>;
     fn get_perms(&self) -> &PermissionRuleNode;
     fn get_records(&self) -> Vec<PermissionPath> {self.get_perms().get_records()}
+    fn get_rules(&self) -> Vec<PermissionRule> {self.get_perms().get_rules()}
     fn merge(&mut self, other: Self);
 }
 
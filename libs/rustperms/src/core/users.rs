@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
 
@@ -14,6 +14,8 @@ pub type UserUID = String;
 pub struct User {
     pub user_uid: UserUID,
     pub groups: HashSet<GroupUID>,
+    // group -> resource prefixes, group rules apply only under one of them
+    pub scoped_groups: HashMap<GroupUID, HashSet<PermissionPath>>,
     pub permissions: PermissionRuleNode,
 }
 
@@ -24,6 +26,7 @@ impl User {
         Self {
             user_uid,
             groups: HashSet::new(),
+            scoped_groups: HashMap::new(),
             permissions: PermissionRuleNode::new(),
         }
     }
@@ -34,6 +37,26 @@ impl User {
     pub fn add_group(&mut self, group: GroupUID) {self.groups.insert(group);}
     pub fn remove_group(&mut self, group: &GroupUID) {self.groups.remove(group);}
 
+    pub fn get_scoped_groups(&self) -> &HashMap<GroupUID, HashSet<PermissionPath>> {&self.scoped_groups}
+    pub fn add_scoped_group(&mut self, group: GroupUID, scope: PermissionPath) {self.scoped_groups.entry(group).or_default().insert(scope);}
+    /// Returns true if user has no scopes left in the group
+    pub fn remove_scoped_group(&mut self, group: &GroupUID, scope: &PermissionPath) -> bool {
+        let Some(scopes) = self.scoped_groups.get_mut(group) else {return true};
+        scopes.remove(scope);
+        if scopes.is_empty() {
+            self.scoped_groups.remove(group);
+            return true
+        }
+        false
+    }
+    pub fn remove_all_scoped_group(&mut self, group: &GroupUID) {self.scoped_groups.remove(group);}
+    /// Groups which are assigned for scope containing the path
+    pub fn groups_in_scope<'a>(&'a self, path: &'a PermissionPath) -> impl Iterator<Item = &'a GroupUID> + 'a {
+        self.scoped_groups.iter()
+            .filter(|(_, scopes)| scopes.iter().any(|scope| path.starts_with(scope)))
+            .map(|(group, _)| group)
+    }
+
     pub fn get_perms(&self) -> &PermissionRuleNode {&self.permissions}
     
 }
@@ -2,6 +2,8 @@ use axum::Router;
 use anyhow::{Error, Result};
 use tracing::{info, warn};
 
+pub mod telemetry;
+
 
 pub struct Service {
     router: Option<Router>
@@ -10,7 +12,7 @@ pub struct Service {
 
 impl Service {
     pub fn begin() -> Self {
-        shared::utils::logger::init_logger();
+        telemetry::init_telemetry();
         Service { router: None }
     }
     pub fn route(
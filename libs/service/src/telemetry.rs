@@ -0,0 +1,119 @@
+use opentelemetry::{global, trace::TracerProvider};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::{level_filters::LevelFilter, Subscriber};
+use tracing_subscriber::{layer::SubscriberExt, registry::LookupSpan, Layer};
+
+shared::env_config!(
+    ".env" => ENV = Env {
+        // OTLP/HTTP collector, e.g. http://localhost:4318, empty disables the export
+        OTEL_EXPORTER_OTLP_ENDPOINT : String = String::new(),
+        // defaults to the binary name
+        OTEL_SERVICE_NAME : String = String::new(),
+    }
+);
+
+fn service_name() -> String {
+    if !ENV.OTEL_SERVICE_NAME.is_empty() {
+        return ENV.OTEL_SERVICE_NAME.clone();
+    }
+    std::env::current_exe().ok()
+        .and_then(|exe| exe.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| "unknown_service".to_string())
+}
+
+fn tracer_provider() -> anyhow::Result<SdkTracerProvider> {
+    let endpoint = format!("{}/v1/traces", ENV.OTEL_EXPORTER_OTLP_ENDPOINT.trim_end_matches('/'));
+    let exporter = SpanExporter::builder().with_http().with_endpoint(endpoint).build()?;
+    Ok(SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name()).build())
+        .build())
+}
+
+/// Exports `tracing` spans through `provider`
+pub fn otel_layer<S>(provider: &SdkTracerProvider) -> impl Layer<S> + Send + Sync
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer("tracing"))
+        .with_filter(LevelFilter::INFO)
+}
+
+/// Logger of [`shared::utils::logger::init_logger`] plus the OpenTelemetry export if `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+/// Trace context is propagated in W3C `traceparent` headers either way.
+pub fn init_telemetry() {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    let provider = if ENV.OTEL_EXPORTER_OTLP_ENDPOINT.is_empty() {
+        None
+    } else {
+        tracer_provider().inspect_err(|e| eprintln!("Can't build OTLP exporter, traces won't be exported: {e}")).ok()
+    };
+    let otel = provider.as_ref().map(otel_layer);
+    let subscriber = tracing_subscriber::registry()
+        .with(otel)
+        .with(shared::utils::logger::log_layer());
+    tracing::subscriber::set_global_default(subscriber).ok();
+    if let Some(provider) = provider {
+        global::set_tracer_provider(provider);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TraceContextExt;
+    use opentelemetry_sdk::trace::InMemorySpanExporter;
+    use shared::utils::telemetry::{continue_trace, trace_headers};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    use super::*;
+
+    #[test]
+    fn remote_span_continues_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let subscriber = tracing_subscriber::registry().with(otel_layer(&provider));
+
+        tracing::subscriber::with_default(subscriber, || {
+            // auth calls master, headers travel in tonic metadata or NATS message headers
+            let auth = tracing::info_span!("auth");
+            let headers = auth.in_scope(trace_headers);
+            assert!(headers.contains_key("traceparent"));
+
+            let master = tracing::info_span!("master");
+            continue_trace(&master, |name| headers.get(name).map(|v| v.as_str()));
+            assert_eq!(
+                master.context().span().span_context().trace_id(),
+                auth.context().span().span_context().trace_id(),
+            );
+        });
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        let auth = spans.iter().find(|s| s.name == "auth").unwrap();
+        let master = spans.iter().find(|s| s.name == "master").unwrap();
+        assert_eq!(master.span_context.trace_id(), auth.span_context.trace_id());
+        assert_eq!(master.parent_span_id, auth.span_context.span_id());
+    }
+
+    #[test]
+    fn span_without_headers_is_root() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder().with_simple_exporter(exporter.clone()).build();
+        let subscriber = tracing_subscriber::registry().with(otel_layer(&provider));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("email");
+            continue_trace(&span, |_| None);
+        });
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].parent_span_id, opentelemetry::trace::SpanId::INVALID);
+    }
+}
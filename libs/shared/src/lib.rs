@@ -22,5 +22,12 @@ env_config!(
     ".cfg" => CFG = EnvCfg{
         MIN_NICKNAME_LENGTH : usize,
         MAX_NICKNAME_LENGTH : usize,
+
+        // keyring.json and the active private key, see tokens::keys
+        JWT_KEYS_DIR : String = "keys".to_string(),
+        JWT_KEYS_RELOAD_INTERVAL : u64 = 60,
+        // asked for keys the local keyring doesn't have
+        JWKS_URL : String = "http://localhost/api/auth/.well-known/jwks.json".to_string(),
+        JWKS_REFRESH_COOLDOWN : u64 = 30,
     }
 );
\ No newline at end of file
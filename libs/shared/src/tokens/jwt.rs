@@ -1,7 +1,6 @@
 use axum::{body::Body, response::IntoResponse};
 //use axum_extra::extract::CookieJar;
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
-use once_cell::sync::Lazy;
+use jsonwebtoken::Validation;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use anyhow::Result;
@@ -9,12 +8,43 @@ use anyhow::Result;
 
 //use super::cookies::TokenCookie;
 
-use include_bytes_plus::include_bytes;
+use super::keys::{keys, ALGORITHM};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AccessTokenPayload {
     pub user: Uuid,
-    pub exp: i64
+    pub exp: i64,
+    /// Issued at, compared with user revocation watermark
+    #[serde(default)]
+    pub iat: i64,
+    /// Session id, `rtid` of the refresh token issued together with this token
+    #[serde(default)]
+    pub sid: Option<Uuid>,
+}
+
+
+/// Token of an internal service, `sub` is the service name
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServiceTokenPayload {
+    pub sub: String,
+    pub aud: String,
+    pub scopes: Vec<String>,
+    pub exp: i64,
+}
+
+impl ServiceTokenPayload {
+    pub fn new(service: &str, audience: &str, scopes: &[&str], lifetime: i64) -> Self {
+        Self {
+            sub: service.to_string(),
+            aud: audience.to_string(),
+            scopes: scopes.iter().map(|s| s.to_string()).collect(),
+            exp: chrono::Utc::now().timestamp() + lifetime,
+        }
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope)
+    }
 }
 
 
@@ -60,7 +90,19 @@ pub struct RefreshTokenRecord {
     pub email: String,
     pub fingerprint: String,
     pub ip: String,
-    pub user_agent: String
+    pub user_agent: String,
+    /// Opaque id shared by all rotations of one login, nil for records issued before sessions were tracked
+    #[serde(default)]
+    pub session: Uuid,
+    /// Login time of the session
+    #[serde(default)]
+    pub created_at: i64,
+    /// Issue time of this record, the last refresh of the session
+    #[serde(default)]
+    pub refreshed_at: i64,
+    /// `rtid` of the record this one was rotated from
+    #[serde(default)]
+    pub parent: Option<Uuid>,
 }
 
 // impl RefreshTokenRecord {
@@ -99,44 +141,36 @@ pub struct RefreshTokenResponse {
     pub exp: i64
 }
 
-pub fn get_public_key() -> [u8; 451] {
-    include_bytes!("public.pem")
-}
-
-pub static PUBLIC_DECODING_KEY : Lazy<DecodingKey> = Lazy::new(|| {
-    DecodingKey::from_rsa_pem(&get_public_key()).expect("Can't load public.pem")
-});
-
-static PRIVATE_ENCODING_KEY : Lazy<EncodingKey> = Lazy::new(|| {
-    EncodingKey::from_rsa_pem(&include_bytes!("private.pem")).expect("Can't load private.pem")
-});
-
-static ALGORITHM : jsonwebtoken::Algorithm = jsonwebtoken::Algorithm::RS256;
-
 impl TokenEncoder {
     pub fn encode_access(payload: AccessTokenPayload) -> Result<String>{
-        let encoded = encode(&Header::new(ALGORITHM), &payload, &PRIVATE_ENCODING_KEY)?;
-        Ok(encoded)
+        keys().encode(&payload)
     }
 
     pub fn encode_refresh(payload: RefreshTokenPayload) -> Result<String>{
-        let encoded = encode(&Header::new(ALGORITHM), &payload, &PRIVATE_ENCODING_KEY)?;
-        Ok(encoded)
+        keys().encode(&payload)
     }
 
     pub fn decode_refresh(token: String) -> Option<RefreshTokenPayload> {
-        let token = decode::<RefreshTokenPayload>(&token, &PUBLIC_DECODING_KEY, &Validation::new(ALGORITHM)).ok()?;
-        Some(token.claims)
+        keys().decode(&token, &Validation::new(ALGORITHM))
     }
 
     pub fn decode_access(token: String) -> Option<AccessTokenPayload> {
-        let token = decode::<AccessTokenPayload>(&token, &PUBLIC_DECODING_KEY, &Validation::new(ALGORITHM)).ok()?;
-        Some(token.claims)
+        keys().decode(&token, &Validation::new(ALGORITHM))
+    }
+
+    pub fn encode_service(payload: ServiceTokenPayload) -> Result<String>{
+        keys().encode(&payload)
+    }
+
+    /// Only tokens issued for `audience` are accepted
+    pub fn decode_service(token: &str, audience: &str) -> Option<ServiceTokenPayload> {
+        let mut validation = Validation::new(ALGORITHM);
+        validation.set_audience(&[audience]);
+        keys().decode(token, &validation)
     }
 
     pub fn encode_timestamp(timestamp: i64) -> Result<String> {
-        let encoded = encode(&Header::new(ALGORITHM), &timestamp, &PRIVATE_ENCODING_KEY)?;
-        Ok(encoded)
+        keys().encode(&timestamp)
     }
 }
 
@@ -0,0 +1,292 @@
+use std::{collections::HashMap, fs, path::Path, sync::{Arc, Mutex, PoisonError, RwLock}, time::{Duration, Instant}};
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, decode_header, encode, jwk::{AlgorithmParameters, CommonParameters, Jwk, JwkSet, KeyAlgorithm, PublicKeyUse, RSAKeyParameters, RSAKeyType}, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use once_cell::sync::Lazy;
+use rsa::{pkcs1::DecodeRsaPublicKey, pkcs8::{DecodePublicKey, EncodePrivateKey, EncodePublicKey, LineEnding}, traits::PublicKeyParts, RsaPrivateKey, RsaPublicKey};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::CFG;
+
+pub const ALGORITHM: Algorithm = Algorithm::RS256;
+/// Kid of `public.pem`/`private.pem` used before the keyring, tokens without a kid are checked with it
+pub const LEGACY_KID: &str = "legacy";
+const KEYRING_FILE: &str = "keyring.json";
+const RSA_BITS: usize = 2048;
+
+/// `keyring.json` of the keys dir. Only the active key has its private part, `{kid}.pem` next to the keyring.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Keyring {
+    pub active: Option<String>,
+    pub keys: Vec<KeyringEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyringEntry {
+    pub kid: String,
+    pub public_pem: String,
+    /// Set on rotation, tokens signed with the key are expired by then and the key is dropped
+    #[serde(default)]
+    pub retire_at: Option<i64>,
+}
+
+impl Keyring {
+    fn read(dir: &Path) -> Result<Option<Self>> {
+        let path = dir.join(KEYRING_FILE);
+        if !path.exists() {return Ok(None)}
+        Ok(Some(serde_json::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    /// Keyring holding only `public.pem` of the working dir, if there is one
+    fn legacy() -> Self {
+        let keys = fs::read_to_string("public.pem").ok()
+            .map(|public_pem| KeyringEntry {kid: LEGACY_KID.to_string(), public_pem, retire_at: None})
+            .into_iter()
+            .collect();
+        Self {active: None, keys}
+    }
+
+    fn live_keys(&self, now: i64) -> impl Iterator<Item = &KeyringEntry> {
+        self.keys.iter().filter(move |k| k.retire_at.is_none_or(|t| t > now))
+    }
+}
+
+/// Signing key and every key tokens may still be signed with
+#[derive(Clone)]
+pub struct Keys {
+    signing: Option<(String, EncodingKey)>,
+    verifying: HashMap<String, DecodingKey>,
+    /// Fetched from the auth service, replaced on every fetch
+    remote: HashMap<String, DecodingKey>,
+    jwks: JwkSet,
+}
+
+impl Keys {
+    pub fn empty() -> Self {
+        Self {signing: None, verifying: HashMap::new(), remote: HashMap::new(), jwks: JwkSet {keys: vec![]}}
+    }
+
+    /// Reads the keyring of `dir`, without one falls back to `private.pem`/`public.pem` of the working dir.
+    /// Services which only verify tokens may have no private key at all.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut keys = Self::empty();
+        let Some(keyring) = Keyring::read(dir)? else {
+            for entry in &Keyring::legacy().keys {
+                keys.add_public(&entry.kid, &entry.public_pem)?;
+            }
+            if let Ok(private_pem) = fs::read("private.pem") {
+                keys.signing = Some((LEGACY_KID.to_string(), EncodingKey::from_rsa_pem(&private_pem)?));
+            }
+            return Ok(keys)
+        };
+        for entry in keyring.live_keys(chrono::Utc::now().timestamp()) {
+            keys.add_public(&entry.kid, &entry.public_pem)?;
+        }
+        if let Some(active) = keyring.active {
+            if let Ok(private_pem) = fs::read(dir.join(format!("{active}.pem"))) {
+                keys.signing = Some((active, EncodingKey::from_rsa_pem(&private_pem)?));
+            }
+        }
+        Ok(keys)
+    }
+
+    fn add_public(&mut self, kid: &str, public_pem: &str) -> Result<()> {
+        let jwk = rsa_jwk(kid, public_pem)?;
+        self.verifying.insert(kid.to_string(), DecodingKey::from_jwk(&jwk)?);
+        self.jwks.keys.push(jwk);
+        Ok(())
+    }
+
+    pub fn with_remote(mut self, jwks: &JwkSet) -> Self {
+        self.remote = jwks.keys.iter()
+            .filter_map(|jwk| Some((jwk.common.key_id.clone()?, DecodingKey::from_jwk(jwk).ok()?)))
+            .collect();
+        self
+    }
+
+    fn key(&self, kid: &str) -> Option<&DecodingKey> {
+        self.verifying.get(kid).or_else(|| self.remote.get(kid))
+    }
+
+    /// Public keys of the keyring, remote ones aren't republished
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+
+    pub fn encode<T: Serialize>(&self, claims: &T) -> Result<String> {
+        let (kid, key) = self.signing.as_ref().ok_or_else(|| anyhow!("No signing key loaded"))?;
+        let mut header = Header::new(ALGORITHM);
+        header.kid = Some(kid.clone());
+        Ok(encode(&header, claims, key)?)
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, token: &str, validation: &Validation) -> Option<T> {
+        let kid = decode_header(token).ok()?.kid;
+        let key = self.key(kid.as_deref().unwrap_or(LEGACY_KID))?;
+        Some(decode::<T>(token, key, validation).ok()?.claims)
+    }
+}
+
+fn rsa_jwk(kid: &str, public_pem: &str) -> Result<Jwk> {
+    let key = RsaPublicKey::from_public_key_pem(public_pem).or_else(|_| RsaPublicKey::from_pkcs1_pem(public_pem))?;
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(KeyAlgorithm::RS256),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: AlgorithmParameters::RSA(RSAKeyParameters {
+            key_type: RSAKeyType::RSA,
+            n: URL_SAFE_NO_PAD.encode(key.n().to_bytes_be()),
+            e: URL_SAFE_NO_PAD.encode(key.e().to_bytes_be()),
+        }),
+    })
+}
+
+fn write_private(path: &Path, pem: &str) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use std::{io::Write, os::unix::fs::OpenOptionsExt};
+        fs::OpenOptions::new().write(true).create_new(true).mode(0o600).open(path)?.write_all(pem.as_bytes())?;
+    }
+    #[cfg(not(unix))]
+    fs::write(path, pem)?;
+    Ok(())
+}
+
+/// Generates a new signing key in `dir`. The previous keys keep verifying for `keep_old_for` seconds,
+/// retired ones are dropped. A dir without a keyring starts from the legacy `public.pem`.
+pub fn rotate_in(dir: &Path, keep_old_for: i64) -> Result<String> {
+    fs::create_dir_all(dir)?;
+    let mut keyring = Keyring::read(dir)?.unwrap_or_else(Keyring::legacy);
+    let now = chrono::Utc::now().timestamp();
+    keyring.keys = keyring.live_keys(now).cloned().collect();
+    for entry in keyring.keys.iter_mut().filter(|k| k.retire_at.is_none()) {
+        entry.retire_at = Some(now + keep_old_for);
+    }
+
+    let kid = Uuid::new_v4().simple().to_string();
+    let private = RsaPrivateKey::new(&mut rsa::rand_core::OsRng, RSA_BITS)?;
+    write_private(&dir.join(format!("{kid}.pem")), &private.to_pkcs8_pem(LineEnding::LF)?)?;
+    keyring.keys.push(KeyringEntry {kid: kid.clone(), public_pem: private.to_public_key().to_public_key_pem(LineEnding::LF)?, retire_at: None});
+    let previous = keyring.active.replace(kid.clone());
+
+    // the keyring switches atomically, the old private key goes only after that
+    let tmp = dir.join(format!("{KEYRING_FILE}.tmp"));
+    fs::write(&tmp, serde_json::to_string_pretty(&keyring)?)?;
+    fs::rename(tmp, dir.join(KEYRING_FILE))?;
+    if let Some(previous) = previous {
+        fs::remove_file(dir.join(format!("{previous}.pem"))).ok();
+    }
+    info!("Signing key rotated to {kid}");
+    Ok(kid)
+}
+
+/// [`rotate_in`] the configured keys dir, running services pick the new key up on their next reload
+pub fn rotate(keep_old_for: i64) -> Result<String> {
+    rotate_in(Path::new(&CFG.JWT_KEYS_DIR), keep_old_for + CFG.JWT_KEYS_RELOAD_INTERVAL as i64)
+}
+
+fn load_configured() -> Keys {
+    Keys::load(Path::new(&CFG.JWT_KEYS_DIR)).unwrap_or_else(|e| {
+        warn!("Can't load signing keys from {}: {e}", CFG.JWT_KEYS_DIR);
+        Keys::empty()
+    })
+}
+
+static KEYS: Lazy<RwLock<Arc<Keys>>> = Lazy::new(|| RwLock::new(Arc::new(load_configured())));
+static LOADED_AT: Lazy<Mutex<Instant>> = Lazy::new(|| Mutex::new(Instant::now()));
+static JWKS_FETCHED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// Current keys, the keyring is reread every `JWT_KEYS_RELOAD_INTERVAL` seconds
+pub fn keys() -> Arc<Keys> {
+    let current = KEYS.read().unwrap_or_else(PoisonError::into_inner).clone();
+    let mut loaded_at = LOADED_AT.lock().unwrap_or_else(PoisonError::into_inner);
+    if loaded_at.elapsed() < Duration::from_secs(CFG.JWT_KEYS_RELOAD_INTERVAL) {return current}
+    *loaded_at = Instant::now();
+    let mut reloaded = load_configured();
+    reloaded.remote = current.remote.clone();
+    let reloaded = Arc::new(reloaded);
+    *KEYS.write().unwrap_or_else(PoisonError::into_inner) = reloaded.clone();
+    reloaded
+}
+
+/// Fetches the JWKS of the auth service if `token` is signed with an unknown key.
+/// Fetches are at most once per `JWKS_REFRESH_COOLDOWN` seconds, so junk kids can't flood the auth service.
+pub async fn refresh_for(token: &str) {
+    let Ok(header) = decode_header(token) else {return};
+    if keys().key(header.kid.as_deref().unwrap_or(LEGACY_KID)).is_some() {return}
+    {
+        let mut fetched_at = JWKS_FETCHED_AT.lock().unwrap_or_else(PoisonError::into_inner);
+        if fetched_at.is_some_and(|t| t.elapsed() < Duration::from_secs(CFG.JWKS_REFRESH_COOLDOWN)) {return}
+        *fetched_at = Some(Instant::now());
+    }
+    let jwks = async { anyhow::Ok(reqwest::get(&CFG.JWKS_URL).await?.error_for_status()?.json::<JwkSet>().await?) };
+    match jwks.await {
+        Ok(jwks) => {
+            info!("Fetched {} keys from {}", jwks.keys.len(), CFG.JWKS_URL);
+            let mut lock = KEYS.write().unwrap_or_else(PoisonError::into_inner);
+            *lock = Arc::new(lock.as_ref().clone().with_remote(&jwks));
+        }
+        Err(e) => warn!("Can't fetch JWKS from {}: {e}", CFG.JWKS_URL),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn claims() -> Value {
+        json!({"sub": "test", "exp": chrono::Utc::now().timestamp() + 60})
+    }
+
+    fn kid_of(token: &str) -> Option<String> {
+        decode_header(token).unwrap().kid
+    }
+
+    #[test]
+    fn rotation_keeps_old_keys_until_retired() {
+        let dir = tempfile::tempdir().unwrap();
+        let validation = Validation::new(ALGORITHM);
+
+        let first = rotate_in(dir.path(), 3600).unwrap();
+        let old_token = Keys::load(dir.path()).unwrap().encode(&claims()).unwrap();
+        assert_eq!(kid_of(&old_token).as_deref(), Some(first.as_str()));
+
+        let second = rotate_in(dir.path(), 3600).unwrap();
+        let keys = Keys::load(dir.path()).unwrap();
+        let new_token = keys.encode(&claims()).unwrap();
+        assert_eq!(kid_of(&new_token).as_deref(), Some(second.as_str()));
+        assert!(keys.decode::<Value>(&old_token, &validation).is_some());
+        assert!(keys.decode::<Value>(&new_token, &validation).is_some());
+        assert_eq!(keys.jwks().keys.len(), 2);
+        assert!(!dir.path().join(format!("{first}.pem")).exists());
+
+        // retiring right away drops the second key, the first one is still within its hour
+        rotate_in(dir.path(), -1).unwrap();
+        let keys = Keys::load(dir.path()).unwrap();
+        assert!(keys.decode::<Value>(&old_token, &validation).is_some());
+        assert!(keys.decode::<Value>(&new_token, &validation).is_none());
+    }
+
+    #[test]
+    fn verifies_with_fetched_jwks() {
+        let dir = tempfile::tempdir().unwrap();
+        rotate_in(dir.path(), 3600).unwrap();
+        let signer = Keys::load(dir.path()).unwrap();
+        let token = signer.encode(&claims()).unwrap();
+        let jwks: JwkSet = serde_json::from_str(&serde_json::to_string(signer.jwks()).unwrap()).unwrap();
+
+        let validation = Validation::new(ALGORITHM);
+        assert!(Keys::empty().decode::<Value>(&token, &validation).is_none());
+        let verifier = Keys::empty().with_remote(&jwks);
+        assert!(verifier.decode::<Value>(&token, &validation).is_some());
+        assert!(verifier.encode(&claims()).is_err());
+    }
+}
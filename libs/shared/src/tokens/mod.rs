@@ -1,2 +1,4 @@
 #[cfg(feature="jwt")]
-pub mod jwt;
\ No newline at end of file
+pub mod jwt;
+#[cfg(feature="jwt")]
+pub mod keys;
\ No newline at end of file
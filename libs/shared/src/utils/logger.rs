@@ -1,11 +1,29 @@
-use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{fmt, layer::SubscriberExt, Layer};
+use tracing::{level_filters::LevelFilter, Subscriber};
+use tracing_subscriber::{fmt, layer::SubscriberExt, registry::LookupSpan, Layer};
 
+crate::env_config!(
+    ".env" => LOG_ENV = LogEnv {
+        // `text` or `json`, json lines carry span fields such as `request_id`
+        LOG_FORMAT : String = "text".to_string(),
+    }
+);
 
+/// Log output in the configured format, other layers (e.g. telemetry export) can be stacked with it
+pub fn log_layer<S>() -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let layer = if LOG_ENV.LOG_FORMAT.eq_ignore_ascii_case("json") {
+        fmt::layer().json().with_current_span(true).with_span_list(true).boxed()
+    } else {
+        fmt::layer().boxed()
+    };
+    layer.with_filter(LevelFilter::INFO).boxed()
+}
 
 pub fn init_logger(){
     let subscriber = tracing_subscriber::registry()
-        .with(fmt::layer().with_filter(LevelFilter::INFO));
+        .with(log_layer());
     tracing::subscriber::set_global_default(subscriber).ok();
 }
 
@@ -13,4 +31,4 @@ pub fn init_logger(){
 // #[ctor::ctor]
 // fn setup_log_before_tests() {
 //     init_logger();
-// }
\ No newline at end of file
+// }
@@ -9,6 +9,8 @@ pub mod verify_turnstile;
 pub mod header;
 pub mod app_err;
 pub mod logger;
+pub mod request_id;
+pub mod telemetry;
 pub mod env;
 pub mod set_encoder;
 
@@ -0,0 +1,65 @@
+use std::future::Future;
+
+use tracing::Span;
+use uuid::Uuid;
+
+/// Carries the request id between services: http, tonic metadata and NATS message headers
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+pub fn new_request_id() -> String {
+    Uuid::new_v4().simple().to_string()
+}
+
+/// Incoming ids are trusted only if they are short and made of `[A-Za-z0-9-_]`
+pub fn accept_request_id(id: &str) -> Option<String> {
+    let valid = !id.is_empty() && id.len() <= 64 && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    valid.then(|| id.to_string())
+}
+
+/// Accepted id from the value of [`REQUEST_ID_HEADER`] or a new one
+pub fn request_id_or_new(header: Option<&str>) -> String {
+    header.and_then(accept_request_id).unwrap_or_else(new_request_id)
+}
+
+/// Id of the request handled by the current task, spawned tasks don't inherit it
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+pub async fn with_request_id<F: Future>(id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(id, future).await
+}
+
+/// Span of a tonic server call continuing the caller's trace, use with `Server::builder().trace_fn`
+pub fn grpc_span(request: &axum::http::Request<()>) -> Span {
+    let header = |name: &str| request.headers().get(name).and_then(|v| v.to_str().ok());
+    let id = request_id_or_new(header(REQUEST_ID_HEADER));
+    let span = tracing::info_span!("grpc", request_id = %id, path = %request.uri().path());
+    super::telemetry::continue_trace(&span, header);
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_safe_ids() {
+        assert_eq!(accept_request_id("a1b2-c3_d4").as_deref(), Some("a1b2-c3_d4"));
+        assert_eq!(accept_request_id(""), None);
+        assert_eq!(accept_request_id("id\nwith newline"), None);
+        assert_eq!(accept_request_id(&"a".repeat(65)), None);
+        assert_eq!(request_id_or_new(Some("bad id")).len(), 32);
+    }
+
+    #[tokio::test]
+    async fn current_id_is_scoped() {
+        assert_eq!(current_request_id(), None);
+        let id = with_request_id("abc".to_string(), async { current_request_id() }).await;
+        assert_eq!(id.as_deref(), Some("abc"));
+    }
+}
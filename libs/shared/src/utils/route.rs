@@ -1,3 +1,5 @@
+/// Builds nested routers, `(perm)` or `(perm, FailureMode::...)` after a handler adds a permission layer.
+/// Every guarded route is reported on startup with its permission and failure mode.
 #[macro_export]
 macro_rules! router {
     (
@@ -5,7 +7,7 @@ macro_rules! router {
         $(
             $root:literal: ($($layer:expr)*) => {
                 $(
-                    $method:ident $path:literal -> $handler:ident $(($perm:expr))?
+                    $method:ident $path:literal -> $handler:ident $(($perm:expr $(, $failure:expr)?))?
                 )*
             } 
         )*
@@ -16,7 +18,9 @@ macro_rules! router {
             $(
                 let mut route = axum::routing::$method($handler);
                 $(
-                    route = route.layer($p.build($perm).await?);
+                    let layer = $p.build($perm).await?$(.on_failure($failure))?;
+                    $crate::tracing::info!("{} {}{} requires `{}` ({})", stringify!($method), $root, $path, $perm, layer.failure_mode());
+                    route = route.layer(layer);
                 )?
                 nested_router = nested_router.route($path, route);
             )*
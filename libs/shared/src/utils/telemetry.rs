@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+
+use opentelemetry::global;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// W3C trace context fields, sent as http headers, tonic metadata and NATS message headers
+pub const TRACE_HEADERS: [&str; 2] = ["traceparent", "tracestate"];
+
+/// Trace context of the current span for an outgoing request or message
+pub fn trace_headers() -> HashMap<String, String> {
+    let mut headers = HashMap::new();
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut headers));
+    headers
+}
+
+/// Makes `span` a child of the remote span described by the [`TRACE_HEADERS`] returned by `get`.
+/// Without them `span` stays a root.
+pub fn continue_trace<'a>(span: &Span, get: impl Fn(&str) -> Option<&'a str>) {
+    let carrier: HashMap<String, String> = TRACE_HEADERS.iter()
+        .filter_map(|name| get(name).map(|value| (name.to_string(), value.to_string())))
+        .collect();
+    if carrier.is_empty() {return}
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    span.set_parent(context);
+}
@@ -1,15 +1,19 @@
-use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
 use axum_extra::extract::CookieJar;
 use layers::logging::UserInfoExt;
 use serde::{Deserialize, Serialize};
-use shared::utils::app_err::AppErr;
-use crate::{repository::cookies::TokenCookie};
+use serde_json::json;
+use shared::{tokens::jwt::RefreshRules, utils::{app_err::AppErr, token::generate_secure_token}, uuid::Uuid};
+use crate::{repository::{account_deletion::AccountRestoreStore, cookies::TokenCookie, tokens::{generate_access, generate_and_put_refresh}}};
 use crate::AppState;
+use anyhow::Result;
 // TODO!
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DeleteBody {
     pub email_or_uid: String,
     pub password: String,
+    #[serde(default)]
+    pub totp_code: Option<String>,
     // pub email: String,
     // pub email_code: String,
     // pub turnstile_token: String,
@@ -24,7 +28,36 @@ pub async fn delete_account(
     if !verify_turnstile(request_body.turnstile_token.clone(), get_user_ip(&headers)).await {return Ok((StatusCode::BAD_REQUEST, "Turnstile failed").into_response())};
     // #[cfg(not(feature = "disable_email"))]
     // if !state.verify_register_code(request_body.email_code.clone(), request_body.email.clone()).await? {return Ok((StatusCode::BAD_REQUEST, "Invalid email code!").into_response())};
-    let success = state.delete_user(request_body.email_or_uid, request_body.password).await?;
+    let success = state.delete_user(request_body.email_or_uid, request_body.password, request_body.totp_code.as_deref()).await?;
     if !success {return Ok(StatusCode::UNAUTHORIZED.into_response())}
     Ok(jar.rm_refresh().into_response())
 }
+
+
+/// Fully authenticated login into an account pending deletion stops here, the client may restore it with the returned token
+pub async fn offer_restore(state: &AppState, uid: Uuid) -> Result<Option<Response>> {
+    let Some(purge_at) = state.deletion_scheduled_at(&uid).await? else {return Ok(None)};
+    let token = generate_secure_token(256);
+    state.redis.put_account_restore(&token, &uid).await?;
+    Ok(Some((StatusCode::CONFLICT, Json(json!({"restore_token": token, "purge_at": purge_at.and_utc().timestamp()}))).into_response()))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RestoreBody {
+    pub token: String,
+}
+
+pub async fn restore_account(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Extension(user_info) : Extension<UserInfoExt>,
+    Json(RestoreBody { token }): Json<RestoreBody>
+) -> Result<impl IntoResponse, AppErr> {
+    let Some(uid) = state.redis.pop_account_restore(&token).await? else {return Ok(StatusCode::UNAUTHORIZED.into_response())};
+    let Some(user) = state.restore_account(&uid).await? else {return Ok((StatusCode::GONE, "Account can't be restored").into_response())};
+    let rules = RefreshRules{warn_suspicious_refresh: user.warn_suspicious_refresh, allow_suspicious_refresh: user.allow_suspicious_refresh};
+    state.send_new_login(user.email.clone(), user_info.ip.clone(), user_info.user_agent.clone()).await?;
+    let (jar, rtid) = generate_and_put_refresh(jar, &state, &user.guid, user_info, user.email, rules, None).await?;
+    let access_response = generate_access(user.guid, rtid)?;
+    Ok((jar, access_response).into_response())
+}
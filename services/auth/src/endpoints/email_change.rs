@@ -0,0 +1,58 @@
+use axum::{extract::{Query, State}, http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::{Deserialize, Serialize};
+use shared::{tokens::jwt::AccessTokenPayload, utils::{app_err::AppErr, validation::RegisterValidations}};
+
+use crate::{repository::email_change::{ConfirmEmailChange, EmailChangeStore, RequestEmailChange}, AppState};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailChangeRequest {
+    pub new_email: String,
+    pub password: String,
+    #[serde(default)]
+    pub totp_code: Option<String>,
+}
+
+pub async fn request_email_change(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Json(request_body): Json<EmailChangeRequest>,
+) -> Result<impl IntoResponse, AppErr> {
+    if !request_body.new_email.is_email_valid() {return Ok((StatusCode::BAD_REQUEST, "Invalid email!").into_response())}
+    Ok(match state.request_email_change(&payload.user, request_body.new_email, &request_body.password, request_body.totp_code.as_deref()).await? {
+        RequestEmailChange::Sent => StatusCode::ACCEPTED.into_response(),
+        RequestEmailChange::WrongCredentials => (StatusCode::UNAUTHORIZED, "Incorrect credentials!").into_response(),
+        RequestEmailChange::EmailTaken => (StatusCode::CONFLICT, "Email already used").into_response(),
+    })
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmailChangeConfirm {
+    pub code: String,
+}
+
+pub async fn confirm_email_change(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Json(EmailChangeConfirm { code }): Json<EmailChangeConfirm>,
+) -> Result<impl IntoResponse, AppErr> {
+    Ok(match state.confirm_email_change(&payload.user, &code).await? {
+        ConfirmEmailChange::Done => StatusCode::OK.into_response(),
+        ConfirmEmailChange::WrongCode => (StatusCode::UNAUTHORIZED, "Invalid email code!").into_response(),
+        ConfirmEmailChange::NoPendingChange => StatusCode::NOT_FOUND.into_response(),
+        ConfirmEmailChange::EmailTaken => (StatusCode::CONFLICT, "Email already used").into_response(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelQuery {
+    pub token: String,
+}
+
+/// Link from the mail sent to the old address, works without a session
+pub async fn cancel_email_change(
+    State(state): State<AppState>,
+    Query(CancelQuery { token }): Query<CancelQuery>,
+) -> Result<impl IntoResponse, AppErr> {
+    if !state.redis.cancel_email_change(&token).await? {return Ok((StatusCode::NOT_FOUND, "Nothing to cancel").into_response())}
+    Ok("Email change cancelled".into_response())
+}
@@ -0,0 +1,7 @@
+use axum::{response::IntoResponse, Json};
+use shared::tokens::keys::keys;
+
+/// Public keys tokens may be signed with, verifiers fetch it on an unknown `kid`
+pub async fn get_jwks() -> impl IntoResponse {
+    Json(keys().jwks().clone())
+}
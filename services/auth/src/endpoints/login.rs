@@ -4,7 +4,7 @@ use layers::logging::UserInfoExt;
 use serde::{Deserialize, Serialize};
 use shared::utils::app_err::AppErr;
 
-use crate::{repository::tokens::{generate_access, generate_and_put_refresh}, AppState};
+use crate::{endpoints::{delete::offer_restore, two_factor::require_second_factor}, repository::tokens::{generate_access, generate_and_put_refresh}, AppState};
 use anyhow::Result;
 
 #[cfg(not(feature = "disable_turnstile"))]
@@ -29,8 +29,10 @@ pub async fn login(
     let guid = state.login(&login_body).await?;
     let Some((guid, settings)) = guid else {return Ok((StatusCode::UNAUTHORIZED, "Incorrect credentials!").into_response())};
     // let Some(email) = state.get_email_from_login_cred(&login_body.email).await? else {return Ok((StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong!").into_response())};
-    state.send_new_login(login_body.email.clone(), user_info.ip.clone(), user_info.user_agent.clone()).await?; // TODO!: ADD TRUSTED USER DEVICES AND 2FA
-    let jar = generate_and_put_refresh(jar, &state, &guid, user_info, login_body.email, settings).await?;
-    let access_response = generate_access(guid)?;
+    if let Some(response) = require_second_factor(&state, guid, login_body.email.clone(), settings.clone()).await? {return Ok(response)}
+    if let Some(response) = offer_restore(&state, guid).await? {return Ok(response)}
+    state.send_new_login(login_body.email.clone(), user_info.ip.clone(), user_info.user_agent.clone()).await?; // TODO!: ADD TRUSTED USER DEVICES
+    let (jar, rtid) = generate_and_put_refresh(jar, &state, &guid, user_info, login_body.email, settings, None).await?;
+    let access_response = generate_access(guid, rtid)?;
     Ok((jar, access_response).into_response())
 }
\ No newline at end of file
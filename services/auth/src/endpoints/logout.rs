@@ -18,5 +18,6 @@ pub async fn logout(
     jar = jar.rm_refresh();
     let Some(refresh_payload) = TokenEncoder::decode_refresh(refresh_token_string) else {return Ok((jar, StatusCode::UNAUTHORIZED).into_response())};
     state.redis.rm_refresh(&refresh_payload.rtid).await?;
+    state.revoke_session(&refresh_payload.rtid).await?;
     Ok((jar, ()).into_response())
 }
\ No newline at end of file
@@ -8,4 +8,10 @@ pub mod refresh;
 pub mod register;
 pub mod login;
 pub mod username;
-pub mod timestamp;
\ No newline at end of file
+pub mod timestamp;
+pub mod two_factor;
+pub mod webauthn;
+pub mod email_change;
+pub mod uid_change;
+pub mod sessions;
+pub mod jwks;
\ No newline at end of file
@@ -1,32 +1,44 @@
 
 use std::collections::HashMap;
 
-use axum::{extract::{Query, State}, http::StatusCode, response::{IntoResponse, Redirect}, Extension, Json};
+use axum::{extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Redirect, Response}, Extension, Json};
 use axum_extra::extract::CookieJar;
 use bb8_redis::redis::AsyncCommands;
 use layers::logging::UserInfoExt;
-use oauth2::{basic::{BasicClient, BasicErrorResponseType, BasicTokenType}, AuthorizationCode, ClientId, CsrfToken, EmptyExtraTokenFields, EndpointNotSet, EndpointSet, RedirectUrl, RevocationErrorResponseType, Scope, StandardErrorResponse, StandardRevocableToken, StandardTokenIntrospectionResponse, StandardTokenResponse, TokenResponse};
-use postgre_entities::user_data;
 use redis_utils::redis::RedisConn;
+use postgre_entities::user_data;
 use serde::{Deserialize, Serialize};
-use shared::{tokens::jwt::RefreshRules, utils::{app_err::AppErr, validation::RegisterValidations}, uuid::Uuid};
+use shared::{tokens::jwt::{AccessTokenPayload, RefreshRules}, utils::{app_err::AppErr, validation::RegisterValidations}, uuid::Uuid};
 
-use crate::{repository::{db::OauthLogin, tokens::{generate_access, generate_and_put_refresh}}, AppState, ENV};
+use crate::{endpoints::{delete::offer_restore, two_factor::require_second_factor}, repository::{db::OauthLogin, identities::{LinkIdentity, OAuthLinkStore, UnlinkIdentity}, oauth_provider::{Discord, Google, OAuthProvider, OAuthUserInfo}, tokens::{generate_access, generate_and_put_refresh}}, AppState, CFG};
 use anyhow::Result;
 
-use oauth2::ClientSecret;
-use oauth2::TokenUrl;
-use oauth2::AuthUrl;
 use tracing::{error};
 
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone, Copy)]
 #[serde(rename_all = "lowercase")]
 pub enum Provider {
     Google,
     Discord
 }
 
+impl Provider {
+    pub fn column(self) -> user_data::Column {
+        match self {
+            Provider::Google => Google::COLUMN,
+            Provider::Discord => Discord::COLUMN,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::Google => "google",
+            Provider::Discord => "discord",
+        }
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct OauthCallbackQuery {
     pub provider: Provider,
@@ -37,57 +49,20 @@ pub struct OauthCallbackQuery {
     pub _prompt: Option<String>,
 }
 
-pub type GoogleClient = oauth2::Client<
-    StandardErrorResponse<BasicErrorResponseType>, 
-    StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>, 
-    StandardTokenIntrospectionResponse<EmptyExtraTokenFields, BasicTokenType>, 
-    StandardRevocableToken, 
-    StandardErrorResponse<RevocationErrorResponseType>, EndpointSet, EndpointNotSet,
-    EndpointNotSet,
-    EndpointNotSet,
-    EndpointSet
->;
-
-pub fn build_google_client() -> GoogleClient {
-    BasicClient::new(ClientId::new(ENV.GOOGLE_CLIENT_ID.clone()))
-        .set_client_secret(ClientSecret::new(ENV.GOOGLE_CLIENT_SECRET.clone()))
-        .set_auth_uri(AuthUrl::new("https://accounts.google.com/o/oauth2/v2/auth".into()).unwrap())
-        .set_token_uri(TokenUrl::new("https://oauth2.googleapis.com/token".into()).unwrap())
-        .set_redirect_uri(RedirectUrl::new(ENV.GOOGLE_REDIRECT_URI.clone()).unwrap())
-}
-
-
-
 pub async fn login_google(
     State(state): State<AppState>,
     Query(params): Query<HashMap<String, String>>
-) -> axum::response::Redirect {
-    let token = params.get("state").cloned().unwrap_or_default(); 
-    let csrf_token = CsrfToken::new(token);
-    let (auth_url, _csrf_token) = state.google_client
-        .authorize_url(|| csrf_token)
-        .add_scope(Scope::new("openid".into()))
-        .add_scope(Scope::new("email".into()))
-        .add_scope(Scope::new("profile".into()))
-        .url();
-    axum::response::Redirect::temporary(auth_url.as_ref())
-}
-
-pub async fn login_discord() -> axum::response::Redirect {
-    axum::response::Redirect::temporary(&ENV.DISCORD_AUTH_URI)
+) -> Redirect {
+    let csrf = params.get("state").cloned().unwrap_or_default();
+    Redirect::temporary(&state.oauth.google.authorize_url(csrf))
 }
 
-
-#[derive(Debug, Deserialize)]
-struct GoogleUserInfo {
-    sub: String,
-    email: String,
-    email_verified: bool,
-    name: String,
-    picture: String,
-    given_name: Option<String>,
-    family_name: Option<String>,
-    locale: Option<String>,
+pub async fn login_discord(
+    State(state): State<AppState>,
+    Query(params): Query<HashMap<String, String>>
+) -> Redirect {
+    let csrf = params.get("state").cloned().unwrap_or_default();
+    Redirect::temporary(&state.oauth.discord.authorize_url(csrf))
 }
 
 
@@ -158,79 +133,70 @@ impl TempTokenStore for RedisConn {
 
 
 
+/// Provider account to either a pending login or the account to register, None if its email is taken
+async fn resolve_account<P: OAuthProvider>(state: &AppState, provider: &P, code: String) -> Result<Option<Result<TempLoginToken, OAuthUserInfo>>, Response> {
+    let user_info = provider.fetch_user(&reqwest::Client::new(), code).await.map_err(|e| {
+        error!("OAuth user info fetch failed: {e:?}");
+        (StatusCode::BAD_GATEWAY, "OAuth provider error").into_response()
+    })?;
+    // unverified emails could be used to take over accounts registered with them
+    if !user_info.email_verified {return Err((StatusCode::FORBIDDEN, "Email isn't verified by the provider").into_response())}
+    let login = state
+        .login_oauth(P::COLUMN, &user_info.id, &user_info.email)
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    Ok(match login {
+        OauthLogin::Successful(uid, rules) => Some(Ok(TempLoginToken{email: user_info.email, rules, uid})),
+        OauthLogin::NeedRegistration => Some(Err(user_info)),
+        OauthLogin::EmailExists => None,
+    })
+}
+
+/// Second half of a link flow started with [`begin_link`]
+async fn link_account<P: OAuthProvider>(state: &AppState, provider: &P, user: &Uuid, code: String) -> Result<LinkIdentity, Response> {
+    let user_info = provider.fetch_user(&reqwest::Client::new(), code).await.map_err(|e| {
+        error!("OAuth user info fetch failed: {e:?}");
+        (StatusCode::BAD_GATEWAY, "OAuth provider error").into_response()
+    })?;
+    state.link_identity(user, P::COLUMN, user_info.id).await.map_err(|e| {
+        error!("Linking failed: {e:?}");
+        StatusCode::INTERNAL_SERVER_ERROR.into_response()
+    })
+}
+
 pub async fn oauth_callback(
     State(state): State<AppState>,
     Query(query): Query<OauthCallbackQuery>,
-) -> Result<impl IntoResponse, impl IntoResponse> {
-    let action: Result<TempLoginToken, TempRegistrationToken> = match query.provider {
-        Provider::Google => {
-            let code = query.code.ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing code").into_response())?;
-
-            let client = reqwest::Client::new();
-            
-            let Ok(token_result) = state.google_client
-                .exchange_code(AuthorizationCode::new(code))
-                .request_async(&client)
-                .await 
-            else {
-                return Err((StatusCode::BAD_REQUEST, "Missing code").into_response())
-            };
-            
-            let access_token = token_result.access_token().secret();
-            let user_info : GoogleUserInfo  = client
-                .get("https://www.googleapis.com/oauth2/v3/userinfo")
-                .bearer_auth(access_token)
-                .send()
-                .await
-                .map_err(|e| {
-                    error!("User info fetch failed: {e:?}");
-                    (StatusCode::BAD_GATEWAY, "Google user info error").into_response()
-                })?
-                .json()
-                .await
-                .map_err(|e| {
-                    error!("User info decode failed: {e:?}");
-                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to decode user info").into_response()
-                })?;
-            match state
-                    .login_oauth(user_data::Column::GoogleId, &user_info.sub, &user_info.email)
-                    .await
-                    .map_err(|_|(StatusCode::INTERNAL_SERVER_ERROR).into_response())? {
-                OauthLogin::Successful(uid, rules) =>
-                    Ok(TempLoginToken{
-                        email: user_info.email,
-                        rules,
-                        uid
-                    }),
-                OauthLogin::NeedRegistration =>
-                    Err(TempRegistrationToken {
-                        email: user_info.email,
-                        google_id: Some(user_info.sub),
-                        picture: Some(user_info.picture),
-                        discord_id: None
-                    }),
-                OauthLogin::EmailExists => {
-                    tracing::info!("Email exists");
-                    return Ok(Redirect::temporary("/oauth?err=Email%20already%20used.%20Please%20log%20in%20and%20link%20your%20account.").into_response());
-                }
-            }
-        },
-        Provider::Discord => {
-            // let code = query.code.ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing code").into_response())?;
-            // let user_info = reqwest::Client::new()
-            //     .post("POST https://discord.com/api/oauth2/token")
-            //     .bearer_auth(access_token.secret())
-            //     .send()
-            //     .await?
-            //     // .json::<GoogleUserInfo>()
-            //     // .await?
-            ;
-
-            // info!("{user_info:#?}")
-            todo!()
-
-        }
+) -> Result<Response, Response> {
+    let code = query.code.ok_or_else(|| (StatusCode::BAD_REQUEST, "Missing code").into_response())?;
+    let link = state.redis.pop_oauth_link(&query.state).await.map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())?;
+    if let Some(user) = link {
+        let linked = match query.provider {
+            Provider::Google => link_account(&state, state.oauth.google.as_ref(), &user, code).await?,
+            Provider::Discord => link_account(&state, state.oauth.discord.as_ref(), &user, code).await?,
+        };
+        let redirect = match linked {
+            LinkIdentity::Done => format!("/oauth?linked={}", query.provider.name()),
+            LinkIdentity::AlreadyLinked => "/oauth?err=Another%20account%20of%20this%20provider%20is%20already%20linked.".to_string(),
+            LinkIdentity::UsedByOther => "/oauth?err=This%20account%20is%20linked%20to%20another%20user.".to_string(),
+        };
+        return Ok(Redirect::temporary(&redirect).into_response());
+    }
+    let action = match query.provider {
+        Provider::Google => resolve_account(&state, state.oauth.google.as_ref(), code).await?,
+        Provider::Discord => resolve_account(&state, state.oauth.discord.as_ref(), code).await?,
+    };
+    let Some(action) = action else {
+        tracing::info!("Email exists");
+        return Ok(Redirect::temporary("/oauth?err=Email%20already%20used.%20Please%20log%20in%20and%20link%20your%20account.").into_response());
     };
+    let action = action.map_err(|user_info| {
+        let (google_id, discord_id) = match query.provider {
+            Provider::Google => (Some(user_info.id), None),
+            Provider::Discord => (None, Some(user_info.id)),
+        };
+        TempRegistrationToken {email: user_info.email, picture: user_info.picture, google_id, discord_id}
+    });
 
     let id = shared::utils::token::generate_secure_token(256);
     match action {
@@ -283,8 +249,8 @@ pub async fn oauth_register(
     let Ok((user_id, rules)) = r else {
         return Ok((StatusCode::CONFLICT, r.err().unwrap()).into_response())
     };
-    let jar = generate_and_put_refresh(jar, &state, &user_id, user_info, stored.email, rules).await?;
-    let access_response = generate_access(user_id)?;
+    let (jar, rtid) = generate_and_put_refresh(jar, &state, &user_id, user_info, stored.email, rules, None).await?;
+    let access_response = generate_access(user_id, rtid)?;
     state.redis.rm_temp(&req.temp_token).await.ok();
     Ok((jar, access_response).into_response())
 }
@@ -302,8 +268,51 @@ pub async fn oauth_login(
     Json(TokenRequest { token }): Json<TokenRequest>
 ) -> Result<impl IntoResponse, AppErr>  {
     let Some(stored) = state.redis.get_temp_login(&token).await? else {return Ok((StatusCode::UNAUTHORIZED).into_response())};
-    let jar = generate_and_put_refresh(jar, &state, &stored.uid, user_info, stored.email, stored.rules).await?;
-    let access_response = generate_access(stored.uid)?;
+    if let Some(response) = require_second_factor(&state, stored.uid, stored.email.clone(), stored.rules.clone()).await? {
+        state.redis.rm_temp(&token).await.ok();
+        return Ok(response)
+    }
+    if let Some(response) = offer_restore(&state, stored.uid).await? {
+        state.redis.rm_temp(&token).await.ok();
+        return Ok(response)
+    }
+    let (jar, rtid) = generate_and_put_refresh(jar, &state, &stored.uid, user_info, stored.email, stored.rules, None).await?;
+    let access_response = generate_access(stored.uid, rtid)?;
     state.redis.rm_temp(&token).await.ok();
     Ok((jar, access_response).into_response())
 }
+
+
+/// Authorize url to link a provider account to the current user, the callback recognises the flow by its state
+pub async fn begin_link(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Path(provider): Path<Provider>,
+) -> Result<impl IntoResponse, AppErr> {
+    let link_state = shared::utils::token::generate_secure_token(128);
+    state.redis.put_oauth_link(&link_state, &payload.user).await?;
+    let url = match provider {
+        Provider::Google => state.oauth.google.authorize_url(link_state),
+        Provider::Discord => state.oauth.discord.authorize_url(link_state),
+    };
+    Ok(Json(serde_json::json!({"url": url, "expires_in": CFG.OAUTH_LINK_LIFETIME})))
+}
+
+pub async fn list_identities(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+) -> Result<impl IntoResponse, AppErr> {
+    Ok(Json(state.linked_identities(&payload.user).await?))
+}
+
+pub async fn unlink(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Path(provider): Path<Provider>,
+) -> Result<impl IntoResponse, AppErr> {
+    Ok(match state.unlink_identity(&payload.user, provider.column()).await? {
+        UnlinkIdentity::Done => StatusCode::OK.into_response(),
+        UnlinkIdentity::NotLinked => StatusCode::NOT_FOUND.into_response(),
+        UnlinkIdentity::LastLoginMethod => (StatusCode::CONFLICT, "Can't unlink the last login method").into_response(),
+    })
+}
@@ -4,7 +4,7 @@ use axum::{extract::{Query, State}, http::{HeaderMap, StatusCode}, response::Int
 use serde::{Deserialize, Serialize};
 use shared::utils::{app_err::AppErr, validation::RegisterValidations};
 
-use crate::{AppState, CFG};
+use crate::{repository::email::PasswordRecovery, AppState, CFG};
 
 
 
@@ -29,7 +29,9 @@ pub async fn request_password_recovery(
 #[derive(Debug, Serialize, Deserialize)]
 pub struct UpdateRequest {
     pub new_password: String,
-    pub turnstile_token: String
+    pub turnstile_token: String,
+    #[serde(default)]
+    pub totp_code: Option<String>,
 }
 
 
@@ -44,10 +46,11 @@ pub async fn recovery_password(
     if !request_body.new_password.is_password_valid() {return Ok((StatusCode::BAD_REQUEST, "Bad password!").into_response())}
     if let Some(token) = params.get("token") {
         if token.chars().count() == CFG.RECOVERY_TOKEN_LEN {
-            let r = state.recovery_password(token, request_body.new_password).await?;
-            if r.is_some(){
+            match state.recovery_password(token, request_body.new_password, request_body.totp_code.as_deref()).await? {
                 // todo: send email
-                return Ok("New password set!".into_response())
+                PasswordRecovery::Done => return Ok("New password set!".into_response()),
+                PasswordRecovery::SecondFactorFailed => return Ok((StatusCode::UNAUTHORIZED, "Incorrect second factor").into_response()),
+                PasswordRecovery::InvalidToken => {}
             }
         }
     }
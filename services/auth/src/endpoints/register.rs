@@ -58,8 +58,8 @@ pub async fn register(
     let Ok((user_id, rules)) = r else {
         return Ok((StatusCode::CONFLICT, r.err().unwrap()).into_response())
     };
-    let jar = generate_and_put_refresh(jar, &state, &user_id, user_info, email, rules).await?;
-    let access_response = generate_access(user_id)?;
+    let (jar, rtid) = generate_and_put_refresh(jar, &state, &user_id, user_info, email, rules, None).await?;
+    let access_response = generate_access(user_id, rtid)?;
     Ok((jar, access_response).into_response())
 }
 
@@ -0,0 +1,20 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Extension, Json};
+use shared::{tokens::jwt::AccessTokenPayload, utils::app_err::AppErr, uuid::Uuid};
+
+use crate::AppState;
+
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+) -> Result<impl IntoResponse, AppErr> {
+    Ok(Json(state.list_sessions(&payload.user, payload.sid).await?))
+}
+
+pub async fn end_session(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Path(id): Path<Uuid>,
+) -> Result<impl IntoResponse, AppErr> {
+    if !state.end_session(&payload.user, &id).await? {return Ok(StatusCode::NOT_FOUND)}
+    Ok(StatusCode::OK)
+}
@@ -1,10 +1,10 @@
-use axum::{body::Body, extract::State, http::Response, Extension, Json};
+use axum::{body::Body, extract::State, http::{Response, StatusCode}, response::IntoResponse, Extension, Json};
 use axum_extra::extract::CookieJar;
 use layers::logging::UserInfoExt;
 use serde::{Deserialize, Serialize};
-use shared::tokens::jwt::RefreshRules;
+use shared::{tokens::jwt::{RefreshRules, TokenEncoder}, utils::app_err::ToResponseBody};
 
-use crate::{repository::refresh_processor::RefreshProcessor, AppState};
+use crate::{repository::{cookies::TokenCookie, refresh_processor::RefreshProcessor}, AppState};
 
 
 
@@ -12,7 +12,9 @@ use crate::{repository::refresh_processor::RefreshProcessor, AppState};
 #[derive(Serialize, Deserialize)]
 pub struct SetRefreshRules {
     pub allow_suspicious_refresh : bool,
-    pub warn_suspicious_refresh : bool
+    pub warn_suspicious_refresh : bool,
+    #[serde(default)]
+    pub totp_code: Option<String>,
 }
 
 pub async fn set_refresh_rules(
@@ -23,8 +25,14 @@ pub async fn set_refresh_rules(
     Json(SetRefreshRules{
         allow_suspicious_refresh,
         warn_suspicious_refresh,
+        totp_code,
     }): Json<SetRefreshRules>,
 ) -> Result<Response<Body>, Response<Body>> {
+    // checked before the refresh token is consumed, so a wrong code doesn't log the user out
+    let Some(refresh) = jar.get_refresh().and_then(TokenEncoder::decode_refresh) else {return Err(StatusCode::UNAUTHORIZED.into_response())};
+    if !state.check_second_factor_of(&refresh.user, totp_code.as_deref()).await.trough_app_err()? {
+        return Err((StatusCode::UNAUTHORIZED, "Incorrect second factor").into_response())
+    }
     let new_rules= RefreshRules { warn_suspicious_refresh, allow_suspicious_refresh};
     RefreshProcessor::begin(jar, &state, user_info).await?.check_refresh_rules().await?.update_refresh_rules(new_rules).await?.generate_tokens().await
 }
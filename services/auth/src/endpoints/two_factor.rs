@@ -0,0 +1,87 @@
+use axum::{extract::State, http::StatusCode, response::{IntoResponse, Response}, Extension, Json};
+use axum_extra::extract::CookieJar;
+use layers::logging::UserInfoExt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use shared::{tokens::jwt::{AccessTokenPayload, RefreshRules}, utils::{app_err::AppErr, token::generate_secure_token}, uuid::Uuid};
+
+use crate::{endpoints::delete::offer_restore, repository::{tokens::{generate_access, generate_and_put_refresh}, two_factor::{PendingTwoFactor, TwoFactorStore}}, AppState, CFG};
+use anyhow::Result;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+/// Login of a user with TOTP enabled stops here, the client has to send the code with the returned token
+pub async fn require_second_factor(state: &AppState, uid: Uuid, email: String, rules: RefreshRules) -> Result<Option<Response>> {
+    if !state.is_totp_enabled(&uid).await? {return Ok(None)}
+    let id = generate_secure_token(256);
+    state.redis.put_pending_2fa(&id, &PendingTwoFactor{uid, email, rules, attempts: 0}).await?;
+    Ok(Some((StatusCode::ACCEPTED, Json(json!({"two_factor_token": id}))).into_response()))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SecondFactorBody {
+    pub token: String,
+    pub code: String,
+}
+
+pub async fn login_second_factor(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Extension(user_info) : Extension<UserInfoExt>,
+    Json(SecondFactorBody { token, code }): Json<SecondFactorBody>
+) -> Result<impl IntoResponse, AppErr> {
+    let Some(mut pending) = state.redis.get_pending_2fa(&token).await? else {return Ok(StatusCode::UNAUTHORIZED.into_response())};
+    if !state.check_second_factor_of(&pending.uid, Some(&code)).await? {
+        pending.attempts += 1;
+        if pending.attempts >= CFG.PENDING_2FA_ATTEMPTS {
+            state.redis.rm_pending_2fa(&token).await?;
+        } else {
+            state.redis.put_pending_2fa(&token, &pending).await?;
+        }
+        return Ok((StatusCode::UNAUTHORIZED, "Incorrect code!").into_response())
+    }
+    state.redis.rm_pending_2fa(&token).await?;
+    if let Some(response) = offer_restore(&state, pending.uid).await? {return Ok(response)}
+    state.send_new_login(pending.email.clone(), user_info.ip.clone(), user_info.user_agent.clone()).await?;
+    let (jar, rtid) = generate_and_put_refresh(jar, &state, &pending.uid, user_info, pending.email, pending.rules, None).await?;
+    let access_response = generate_access(pending.uid, rtid)?;
+    Ok((jar, access_response).into_response())
+}
+
+pub async fn begin_totp(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+) -> Result<impl IntoResponse, AppErr> {
+    let Some(enrolment) = state.begin_totp(&payload.user).await? else {return Ok((StatusCode::CONFLICT, "TOTP is already enabled").into_response())};
+    Ok(Json(enrolment).into_response())
+}
+
+pub async fn confirm_totp(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Json(TotpCode { code }): Json<TotpCode>
+) -> Result<impl IntoResponse, AppErr> {
+    let Some(codes) = state.confirm_totp(&payload.user, &code).await? else {return Ok((StatusCode::UNAUTHORIZED, "Incorrect code!").into_response())};
+    Ok(Json(json!({"recovery_codes": codes})).into_response())
+}
+
+pub async fn disable_totp(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Json(TotpCode { code }): Json<TotpCode>
+) -> Result<impl IntoResponse, AppErr> {
+    if !state.disable_totp(&payload.user, &code).await? {return Ok((StatusCode::UNAUTHORIZED, "Incorrect code!").into_response())}
+    Ok(StatusCode::OK.into_response())
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Json(TotpCode { code }): Json<TotpCode>
+) -> Result<impl IntoResponse, AppErr> {
+    let Some(codes) = state.regenerate_recovery_codes(&payload.user, &code).await? else {return Ok((StatusCode::UNAUTHORIZED, "Incorrect code!").into_response())};
+    Ok(Json(json!({"recovery_codes": codes})).into_response())
+}
@@ -0,0 +1,25 @@
+use axum::{extract::State, http::{header, StatusCode}, response::IntoResponse, Extension, Json};
+use serde::{Deserialize, Serialize};
+use shared::{tokens::jwt::AccessTokenPayload, utils::{app_err::AppErr, validation::RegisterValidations}};
+
+use crate::{repository::uid_change::ChangeUid, AppState};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UidChangeRequest {
+    pub uid: String,
+}
+
+pub async fn change_uid(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Json(UidChangeRequest { uid }): Json<UidChangeRequest>,
+) -> Result<impl IntoResponse, AppErr> {
+    let uid = uid.to_lowercase();
+    if !uid.is_uid_valid() {return Ok((StatusCode::BAD_REQUEST, "Invalid uid!").into_response())}
+    Ok(match state.change_uid(&payload.user, &uid).await? {
+        ChangeUid::Done => StatusCode::OK.into_response(),
+        ChangeUid::Cooldown(left) => (StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, left.to_string())], "Uid was changed recently").into_response(),
+        ChangeUid::Taken => (StatusCode::CONFLICT, "The uid is already taken").into_response(),
+        ChangeUid::NotFound => StatusCode::NOT_FOUND.into_response(),
+    })
+}
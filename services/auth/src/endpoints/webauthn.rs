@@ -0,0 +1,75 @@
+use axum::{extract::{Path, State}, http::StatusCode, response::IntoResponse, Extension, Json};
+use axum_extra::extract::CookieJar;
+use layers::logging::UserInfoExt;
+use serde::Deserialize;
+use shared::{tokens::jwt::{AccessTokenPayload, RefreshRules}, utils::app_err::AppErr};
+
+use crate::{endpoints::delete::offer_restore, repository::{tokens::{generate_access, generate_and_put_refresh}, webauthn::{AuthenticationCredential, RegistrationCredential}}, AppState};
+use anyhow::Result;
+
+pub async fn begin_passkey_registration(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+) -> Result<impl IntoResponse, AppErr> {
+    let Some(options) = state.begin_passkey_registration(&payload.user).await? else {return Ok(StatusCode::NOT_FOUND.into_response())};
+    Ok(Json(options).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyRegistration {
+    pub name: String,
+    pub credential: RegistrationCredential,
+}
+
+pub async fn finish_passkey_registration(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Json(PasskeyRegistration { name, credential }): Json<PasskeyRegistration>
+) -> Result<impl IntoResponse, AppErr> {
+    if !state.finish_passkey_registration(&payload.user, name, credential).await? {return Ok((StatusCode::BAD_REQUEST, "Passkey rejected").into_response())}
+    Ok(StatusCode::CREATED.into_response())
+}
+
+pub async fn list_passkeys(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+) -> Result<impl IntoResponse, AppErr> {
+    Ok(Json(state.list_passkeys(&payload.user).await?))
+}
+
+pub async fn remove_passkey(
+    State(state): State<AppState>,
+    Extension(payload): Extension<AccessTokenPayload>,
+    Path(id): Path<String>,
+) -> Result<impl IntoResponse, AppErr> {
+    if !state.remove_passkey(&payload.user, id).await? {return Ok(StatusCode::NOT_FOUND)}
+    Ok(StatusCode::OK)
+}
+
+pub async fn begin_passkey_login(
+    State(state): State<AppState>,
+) -> Result<impl IntoResponse, AppErr> {
+    Ok(Json(state.begin_passkey_login().await?))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasskeyLogin {
+    pub token: String,
+    pub credential: AuthenticationCredential,
+}
+
+/// User verification of the passkey counts as the second factor, so TOTP isn't asked here
+pub async fn finish_passkey_login(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Extension(user_info) : Extension<UserInfoExt>,
+    Json(PasskeyLogin { token, credential }): Json<PasskeyLogin>
+) -> Result<impl IntoResponse, AppErr> {
+    let Some(user) = state.finish_passkey_login(&token, credential).await? else {return Ok((StatusCode::UNAUTHORIZED, "Passkey rejected").into_response())};
+    if let Some(response) = offer_restore(&state, user.guid).await? {return Ok(response)}
+    let rules = RefreshRules{warn_suspicious_refresh: user.warn_suspicious_refresh, allow_suspicious_refresh: user.allow_suspicious_refresh};
+    state.send_new_login(user.email.clone(), user_info.ip.clone(), user_info.user_agent.clone()).await?;
+    let (jar, rtid) = generate_and_put_refresh(jar, &state, &user.guid, user_info, user.email, rules, None).await?;
+    let access_response = generate_access(user.guid, rtid)?;
+    Ok((jar, access_response).into_response())
+}
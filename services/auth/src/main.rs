@@ -5,9 +5,9 @@ use axum::{
     error_handling::HandleErrorLayer, http::{HeaderValue, Request, StatusCode}, middleware::Next, response::IntoResponse, routing::{delete, get, post, put}, Router
 };
 use endpoints::{login::login, logout_other::logout_other, recovery_password::{recovery_password, request_password_recovery}, refresh::refresh_tokens, register::{register, request_register_code}, set_refresh_rules::set_refresh_rules, username::check_user_uid};
-use layers::rustperms::PermissionMiddlewareBuilder;
+use layers::{auth::AuthAccessLayer, revocation::RevocationStore, rustperms::PermissionMiddlewareBuilder};
 use message_broker::publisher::build_publisher;
-use shared::{env_config, router};
+use shared::{env_config, router, tokens::keys};
 use redis_utils::redis::RedisConn;
 use tower::{timeout::TimeoutLayer, ServiceBuilder};
 use tower_governor::{governor::GovernorConfigBuilder, key_extractor::SmartIpKeyExtractor, GovernorLayer};
@@ -18,21 +18,22 @@ use tracing::error;
 mod endpoints;
 mod repository;
 
-use rustperms_nodes::{connect_replica, proto::{rustperms_master_proto_client::RustpermsMasterProtoClient, rustperms_replica_proto_client::RustpermsReplicaProtoClient}};
+use rustperms_nodes::{connect_replica, MasterClient, ReplicaClient};
 
 #[derive(Clone)]
 pub struct AppState {
     pub db : sea_orm::DatabaseConnection, // arc doesn't needed https://github.com/SeaQL/sea-orm/blob/3203a6c7ef4f737ed4ab5ee0491cf3c45d9cd71e/examples/axum_example/api/src/lib.rs#L42-L63
     pub redis: RedisConn, // also arc
     pub publisher: Arc<Context>,
-    pub google_client: GoogleClient,
-    pub rustperms_master: RustpermsMasterProtoClient<tonic::transport::Channel>,
-    pub rustperms_replica: RustpermsReplicaProtoClient<tonic::transport::Channel>
+    pub oauth: OAuthProviders,
+    pub rustperms_master: MasterClient,
+    pub rustperms_replica: ReplicaClient
 }
 
 use anyhow::Result;
 
-use crate::endpoints::{delete::delete_account, logout::logout, oauth::{build_google_client, login_discord, login_google, oauth_callback, oauth_login, oauth_register, GoogleClient}, timestamp::get_timestamp};
+use crate::repository::oauth_provider::OAuthProviders;
+use crate::endpoints::{delete::{delete_account, restore_account}, email_change::{cancel_email_change, confirm_email_change, request_email_change}, jwks::get_jwks, logout::logout, sessions::{end_session, list_sessions}, uid_change::change_uid, oauth::{begin_link, list_identities, login_discord, login_google, oauth_callback, oauth_login, oauth_register, unlink}, timestamp::get_timestamp, two_factor::{begin_totp, confirm_totp, disable_totp, login_second_factor, regenerate_recovery_codes}, webauthn::{begin_passkey_login, begin_passkey_registration, finish_passkey_login, finish_passkey_registration, list_passkeys, remove_passkey}};
 
 env_config!(
     ".env" => ENV = Env {
@@ -41,11 +42,20 @@ env_config!(
         TURNSTILE_SECRET : String,
         EMAIL_SEND_NATS_EVENT : String,
 
-        DISCORD_AUTH_URI: String,
-        
+        DISCORD_REDIRECT_URI : String,
+        DISCORD_CLIENT_SECRET : String,
+        DISCORD_CLIENT_ID : String,
+
         GOOGLE_REDIRECT_URI : String,
         GOOGLE_CLIENT_SECRET : String,
         GOOGLE_CLIENT_ID : String,
+
+        // base64 of 32 bytes, encrypts TOTP secrets in user_data
+        TOTP_ENCRYPTION_KEY : String,
+
+        // domain the passkeys are bound to and the page origin, e.g. example.com and https://example.com
+        WEBAUTHN_RP_ID : String,
+        WEBAUTHN_ORIGIN : String,
     }
     ".cfg" => CFG = Cfg {
         REFRESH_TOKEN_LIFETIME : u64 = 30 * 24 * 60 * 60, // 30 days
@@ -58,23 +68,76 @@ env_config!(
         RECOVERY_TOKEN_LEN : usize = 128,
 
         USERNAME_CHECKS_PER_SEC : u64 = 10,
+
+        TOTP_ISSUER : String = "Vesper".to_string(),
+        PENDING_2FA_LIFETIME : u64 = 5 * 60,
+        PENDING_2FA_ATTEMPTS : u8 = 5,
+        RECOVERY_CODES_COUNT : usize = 10,
+
+        WEBAUTHN_RP_NAME : String = "Vesper".to_string(),
+        WEBAUTHN_CHALLENGE_LIFETIME : u64 = 5 * 60,
+
+        GOOGLE_AUTHORIZE_URI : String = "https://accounts.google.com/o/oauth2/v2/auth".to_string(),
+        GOOGLE_TOKEN_URI : String = "https://oauth2.googleapis.com/token".to_string(),
+        GOOGLE_USERINFO_URI : String = "https://www.googleapis.com/oauth2/v3/userinfo".to_string(),
+        DISCORD_AUTHORIZE_URI : String = "https://discord.com/oauth2/authorize".to_string(),
+        DISCORD_TOKEN_URI : String = "https://discord.com/api/oauth2/token".to_string(),
+        DISCORD_USERINFO_URI : String = "https://discord.com/api/users/@me".to_string(),
+        OAUTH_LINK_LIFETIME : u64 = 5 * 60,
+
+        EMAIL_CHANGE_LIFETIME : u64 = 15 * 60,
+        EMAIL_CHANGE_ATTEMPTS : u8 = 5,
+        // link in the mail to the old address, gets `?token=`
+        EMAIL_CHANGE_CANCEL_URL : String = "http://localhost/api/auth/email/cancel".to_string(),
+
+        // reuse of a just rotated refresh token within this many seconds isn't treated as theft
+        REFRESH_REUSE_GRACE : i64 = 10,
+
+        // deleted accounts can be restored until then
+        ACCOUNT_DELETION_GRACE : i64 = 14 * 24 * 60 * 60,
+        ACCOUNT_RESTORE_LIFETIME : u64 = 10 * 60,
+        ACCOUNT_PURGE_INTERVAL : u64 = 60 * 60,
+
+        UID_CHANGE_COOLDOWN : i64 = 30 * 24 * 60 * 60, // 30 days
+        // old uid keeps redirecting to the user and can't be registered by others
+        UID_RESERVATION_TIME : u64 = 14 * 24 * 60 * 60,
     }
 );
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let mut service = service::Service::begin();
-    let replica = connect_replica().await?;
+    if std::env::args().nth(1).as_deref() == Some("rotate-keys") {
+        // old keys have to outlive every token signed with them
+        let kid = keys::rotate(CFG.REFRESH_TOKEN_LIFETIME.max(CFG.ACCESS_TOKEN_LIFETIME) as i64)?;
+        println!("Signing key rotated, new kid: {kid}");
+        return Ok(())
+    }
+    let replica = connect_replica("auth").await?;
     let state = AppState{
         db: db::open_database_connection().await?,
         redis: RedisConn::default().await,
         publisher: Arc::new(build_publisher().await?),
-        google_client: build_google_client(),
-        rustperms_master: rustperms_nodes::connect_master().await?,
+        oauth: OAuthProviders::from_env()?,
+        rustperms_master: rustperms_nodes::connect_master("auth").await?,
         rustperms_replica: replica.clone()
     };
 
+    let purge_state = state.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(CFG.ACCOUNT_PURGE_INTERVAL));
+        loop {
+            interval.tick().await;
+            match purge_state.purge_deleted_accounts().await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {purged} deleted accounts"),
+                Err(e) => error!("Account purge failed: {e}"),
+            }
+        }
+    });
+
     let p = PermissionMiddlewareBuilder::new(replica);
+    let revocation = RevocationStore::connect().await?;
 
     let timeout_layer = ServiceBuilder::new()
         .layer(HandleErrorLayer::new(|_: axum::BoxError| async {
@@ -126,16 +189,20 @@ async fn main() -> Result<()> {
             "/api/auth" : () => {
                 post "/account" -> register
                 delete "/account" -> delete_account
+                post "/account/restore" -> restore_account
                 get "/account/uid_check" -> check_user_uid
                 post "/account/request_register_code" -> request_register_code
 
                 post "/session" -> login
+                post "/session/2fa" -> login_second_factor
                 delete "/session" -> logout
                 delete "/sessions" -> logout_other
 
                 post "/tokens/refresh" -> refresh_tokens
                 put "/tokens/rules" -> set_refresh_rules
 
+                get "/email/cancel" -> cancel_email_change
+
                 get "/password_recovery" -> request_password_recovery
                 post "/password_recovery" -> recovery_password
 
@@ -146,7 +213,39 @@ async fn main() -> Result<()> {
                 post "/oauth/account" -> oauth_register
                 post "/oauth/session" -> oauth_login
 
+                post "/webauthn/session/challenge" -> begin_passkey_login
+                post "/webauthn/session" -> finish_passkey_login
+
                 get "/timestamp" -> get_timestamp
+                get "/.well-known/jwks.json" -> get_jwks
+            }
+            "/api/auth/2fa" : (AuthAccessLayer::only_authorized().with_revocation(revocation.clone())) => {
+                post "/totp" -> begin_totp
+                post "/totp/confirm" -> confirm_totp
+                delete "/totp" -> disable_totp
+                post "/recovery_codes" -> regenerate_recovery_codes
+            }
+            "/api/auth/webauthn" : (AuthAccessLayer::only_authorized().with_revocation(revocation.clone())) => {
+                post "/credentials/challenge" -> begin_passkey_registration
+                post "/credentials" -> finish_passkey_registration
+                get "/credentials" -> list_passkeys
+                delete "/credentials/{id}" -> remove_passkey
+            }
+            "/api/auth/email" : (AuthAccessLayer::only_authorized().with_revocation(revocation.clone())) => {
+                post "/" -> request_email_change
+                post "/confirm" -> confirm_email_change
+            }
+            "/api/auth/identities" : (AuthAccessLayer::only_authorized().with_revocation(revocation.clone())) => {
+                get "/" -> list_identities
+                post "/{provider}" -> begin_link
+                delete "/{provider}" -> unlink
+            }
+            "/api/auth/uid" : (AuthAccessLayer::only_authorized().with_revocation(revocation.clone())) => {
+                put "/" -> change_uid
+            }
+            "/api/auth/sessions" : (AuthAccessLayer::only_authorized().with_revocation(revocation.clone())) => {
+                get "/" -> list_sessions
+                delete "/{id}" -> end_session
             }
         )
             .with_state(state)
@@ -0,0 +1,97 @@
+use anyhow::Result;
+use bb8_redis::redis::AsyncCommands;
+use chrono::{Duration, NaiveDateTime, Utc};
+use postgre_entities::user_data;
+use redis_utils::{redis::RedisConn, redis_tokens::RedisTokens, users::RedisUsers};
+use rustperms::prelude::RustpermsDelta;
+use rustperms_nodes::proto::WriteRequest;
+use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use tracing::{error, info, warn};
+
+use crate::{AppState, CFG};
+
+/// Restore offer made on a login into an account pending deletion
+pub trait AccountRestoreStore {
+    async fn put_account_restore(&self, token: &str, user: &Uuid) -> Result<()>;
+    async fn pop_account_restore(&self, token: &str) -> Result<Option<Uuid>>;
+}
+
+fn restore_to_key(token: &str) -> String {
+    format!("ACCOUNT_RESTORE:{token}")
+}
+
+impl AccountRestoreStore for RedisConn {
+    async fn put_account_restore(&self, token: &str, user: &Uuid) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _ : () = conn.set_ex(restore_to_key(token), user.to_string(), CFG.ACCOUNT_RESTORE_LIFETIME).await?;
+        Ok(())
+    }
+
+    async fn pop_account_restore(&self, token: &str) -> Result<Option<Uuid>> {
+        let mut conn = self.pool.get().await?;
+        let r : Option<String> = conn.get_del(restore_to_key(token)).await?;
+        Ok(r.and_then(|r| r.parse().ok()))
+    }
+}
+
+impl AppState {
+    /// Revokes sessions, hides the profile and keeps the uid reserved,
+    /// the account is purged by [`AppState::purge_deleted_accounts`] after `CFG.ACCOUNT_DELETION_GRACE`
+    pub async fn schedule_deletion(&self, user: user_data::Model) -> Result<()> {
+        if user.deletion_scheduled_at.is_some() {return Ok(())}
+        let (guid, uid) = (user.guid, user.uid.clone());
+        let mut model: user_data::ActiveModel = user.into();
+        model.deletion_scheduled_at = Set(Some(Utc::now().naive_utc() + Duration::seconds(CFG.ACCOUNT_DELETION_GRACE)));
+        model.update(&self.db).await?;
+        self.redis.hide_user(&guid, &uid, CFG.ACCOUNT_DELETION_GRACE as u64).await.inspect_err(|e| error!("Failed to hide user in redis: {e}")).ok();
+        self.redis.rm_all_refresh(&guid).await.inspect_err(|e| error!("Failed to remove refresh tokens from redis: {e}")).ok();
+        self.revoke_user_tokens(&guid).await.inspect_err(|e| error!("Failed to revoke access tokens: {e}")).ok();
+        info!("Deletion of {guid} scheduled");
+        Ok(())
+    }
+
+    pub async fn deletion_scheduled_at(&self, user: &Uuid) -> Result<Option<NaiveDateTime>> {
+        let model = user_data::Entity::find_by_id(*user).one(&self.db).await?;
+        Ok(model.and_then(|m| m.deletion_scheduled_at))
+    }
+
+    /// Cancels a pending deletion which isn't due yet
+    pub async fn restore_account(&self, user: &Uuid) -> Result<Option<user_data::Model>> {
+        let Some(model) = user_data::Entity::find_by_id(*user).one(&self.db).await? else {return Ok(None)};
+        if model.deletion_scheduled_at.is_none_or(|at| at <= Utc::now().naive_utc()) {return Ok(None)}
+        let mut model: user_data::ActiveModel = model.into();
+        model.deletion_scheduled_at = Set(None);
+        let model = model.update(&self.db).await?;
+        self.redis.unhide_user(&model.guid, &model.uid).await?;
+        info!("Account {user} restored");
+        Ok(Some(model))
+    }
+
+    /// Removes accounts whose grace period is over, profiles, posts and friends go with `user_data`
+    pub async fn purge_deleted_accounts(&self) -> Result<usize> {
+        let due = user_data::Entity::find()
+            .filter(user_data::Column::DeletionScheduledAt.lte(Utc::now().naive_utc()))
+            .all(&self.db).await?;
+        let mut purged = 0;
+        for user in due {
+            // a restore in the meantime makes it a no-op
+            let deleted = user_data::Entity::delete_many()
+                .filter(user_data::Column::Guid.eq(user.guid))
+                .filter(user_data::Column::DeletionScheduledAt.lte(Utc::now().naive_utc()))
+                .exec(&self.db).await?;
+            if deleted.rows_affected == 0 {continue}
+            self.redis.remove_user(&user.guid, &user.uid).await.inspect_err(|e| error!("Failed to remove user from redis: {e}")).ok();
+            let d : RustpermsDelta = perms::user::delete_user(&user.guid).into();
+            if let Ok(d) = d.serialize_to_string() {
+                self.rustperms_master.clone().write_changes(WriteRequest{serialized_delta: d})
+                    .await
+                    .inspect_err(|e| error!("Failed to send rustperms delta for deleting user: {e}")).ok();
+            } else {
+                warn!("Failed to serialize rustperms delta for deleting user");
+            }
+            info!("Account {} purged", user.guid);
+            purged += 1;
+        }
+        Ok(purged)
+    }
+}
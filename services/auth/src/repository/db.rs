@@ -1,9 +1,9 @@
 
 use crate::{endpoints::login::LoginBody, AppState};
-use redis_utils::{redis_tokens::RedisTokens, users::RedisUsers};
+use redis_utils::users::RedisUsers;
 use rustperms::prelude::RustpermsDelta;
 use rustperms_nodes::proto::WriteRequest;
-use sea_orm::{prelude::Uuid, sea_query::Expr as QExpr, sqlx::Transaction, QuerySelect, *};
+use sea_orm::{prelude::Uuid, *};
 
 use anyhow::Result;
 
@@ -25,7 +25,7 @@ impl AppState {
     pub async fn is_uid_available(&self, uid: String) -> Result<bool> {
         let uid = uid.to_lowercase();
         let v = self.redis.get_user_guid(&uid).await?;
-        Ok(v.is_none())
+        Ok(v.is_none() && !self.is_uid_reserved_for_other(&uid, None).await?)
     }
 
     pub async fn login(&self, login_body: &LoginBody) -> Result<Option<(Uuid, RefreshRules)>> {
@@ -37,55 +37,40 @@ impl AppState {
         Ok(Some((user.guid, RefreshRules{warn_suspicious_refresh: user.warn_suspicious_refresh, allow_suspicious_refresh: user.allow_suspicious_refresh})))
     }
     
-    pub async fn login_oauth(&self, provider: user_data::Column, id: &String, email: &String) -> Result<OauthLogin> {
-        let users = user_data::Entity::find()
-            .filter(user_data::Column::Email.eq(email.clone()))
-            .column(user_data::Column::Guid)
-            .column(user_data::Column::GoogleId)
-            .column(user_data::Column::DiscordId)
-            .column(user_data::Column::WarnSuspiciousRefresh)
-            .column(user_data::Column::AllowSuspiciousRefresh)
-            .expr_as(
-                QExpr::col((user_data::Entity, provider))
-                    .is_not_null()
-                    .and(QExpr::col((user_data::Entity, provider)).ne(id.clone())),
-                "email_exists",
-            )
-            .all(&self.db)
+    /// `provider` is the `user_data` column of the provider account id.
+    /// An account linked to a different identity of the same provider, or not linked at all, is only reported by email.
+    pub async fn login_oauth(&self, provider: user_data::Column, id: &str, email: &str) -> Result<OauthLogin> {
+        let linked = user_data::Entity::find()
+            .filter(provider.eq(id))
+            .one(&self.db)
             .await?;
-        if users.is_empty() {
-            return Ok(OauthLogin::NeedRegistration);
-        }
-        let matching_user = users.iter().find(|u| u.google_id.as_ref() == Some(id));
-        if let Some(user) = matching_user {
+        if let Some(user) = linked {
             return Ok(OauthLogin::Successful(
-                user.guid.clone(),
+                user.guid,
                 RefreshRules {
                     warn_suspicious_refresh: user.warn_suspicious_refresh,
                     allow_suspicious_refresh: user.allow_suspicious_refresh,
                 },
             ));
         }
-        return Ok(OauthLogin::EmailExists);
+        let email_used = user_data::Entity::find()
+            .filter(user_data::Column::Email.eq(email))
+            .count(&self.db)
+            .await? > 0;
+        if email_used {
+            return Ok(OauthLogin::EmailExists);
+        }
+        Ok(OauthLogin::NeedRegistration)
     }
 
-    pub async fn delete_user(&self, email_or_uid: String, password: String) -> Result<bool> {
+    pub async fn delete_user(&self, email_or_uid: String, password: String, totp_code: Option<&str>) -> Result<bool> {
         let user = user_data::Entity::find()
             .filter(user_data::Column::Uid.eq(&email_or_uid).or(user_data::Column::Email.eq(&email_or_uid)))
             .one(&self.db).await?;
         let Some(user) = user else {return Ok(false)};
         if !bcrypt::verify(&password, &user.password)? {return Ok(false)}
-        let d : RustpermsDelta = perms::user::delete_user(&user.guid).into();
-        self.redis.remove_user(&user.guid, &user.uid).await.inspect_err(|e| error!("Failed to remove user from redis: {e}")).ok();
-        self.redis.rm_all_refresh(&user.guid).await.inspect_err(|e| error!("Failed to remove refresh tokens from redis: {e}")).ok();
-        user.delete(&self.db).await?;
-        if let Ok(d) = d.serialize_to_string() {
-            self.rustperms_master.clone().write_changes(WriteRequest{serialized_delta: d})
-                .await
-                .inspect_err(|e| error!("Failed to send rustperms delta for deleting user: {e}")).ok();
-        } else {
-            warn!("Failed to serialize rustperms delta for deleting user");
-        }
+        if !self.check_second_factor(&user, totp_code).await? {return Ok(false)}
+        self.schedule_deletion(user).await?;
         Ok(true)
     }
 
@@ -151,6 +136,9 @@ impl AppState {
         let allow_suspicious_refresh = false;
         let warn_suspicious_refresh = true;
         let uid = uid.to_lowercase();
+        if self.is_uid_reserved_for_other(&uid, None).await? {
+            return Ok(Err("The uid is already taken. Please choose another, or try to log in.".to_string()));
+        }
 
         let user = user_data::ActiveModel {
             guid: Set(user_guid),
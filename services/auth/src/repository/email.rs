@@ -3,6 +3,7 @@ use crate::{AppState, CFG, ENV};
 use message_broker::email::types::ChangedField;
 use message_broker::email::types::Email;
 use message_broker::email::types::EmailKind;
+use message_broker::headers::request_headers;
 use rand::distr::Alphanumeric;
 use rand::Rng;
 use bb8_redis::redis::AsyncCommands;
@@ -43,7 +44,7 @@ impl EmailCode {
         format!("{}:{}", self.kind.to_prefix(), self.email)
     }
 
-    pub fn recovery_key(code: &String) -> String {
+    pub fn recovery_key(code: &str) -> String {
         format!("{}:{}", CodeKind::PasswordRecovery.to_prefix(), format!("{:x}", Sha256::digest(code.as_bytes())))
     }
 
@@ -112,6 +113,7 @@ trait RedisEmailCode {
     async fn set_code(&self, value: EmailCode) -> Result<()>;
     async fn set_recovery_code(&self, value: EmailCode) -> Result<()>;
     async fn verify_code(&self, value: EmailCode) -> Result<bool>;
+    async fn get_recovery_email(&self, code: &str) -> Result<Option<String>>;
     async fn pop_recovery_email(&self, code: &String) -> Result<Option<String>>;
 }
 
@@ -148,6 +150,12 @@ impl RedisEmailCode for RedisConn {
         }
         Ok(false)
     }
+    async fn get_recovery_email(&self, code: &str) -> Result<Option<String>> {
+        let key = EmailCode::recovery_key(code);
+        let mut conn = self.pool.get().await?;
+        let r : Option<String> = conn.get(&key).await?;
+        Ok(r)
+    }
     // code hash -> email
     async fn pop_recovery_email(&self, code: &String) -> Result<Option<String>> {
         // bad: let (key , _) = EmailCode{kind: CodeKind::PasswordRecovery,code,email: "".to_owned(),}.key_value();
@@ -160,6 +168,12 @@ impl RedisEmailCode for RedisConn {
 
 
 
+pub enum PasswordRecovery {
+    Done,
+    InvalidToken,
+    SecondFactorFailed,
+}
+
 impl AppState {
     pub async fn try_send_recovery_code(&self, email_or_uid: &String) -> Result<()> {
         let Some(email) = self.get_email_from_login_cred(email_or_uid).await? else {return Ok(())};
@@ -205,6 +219,15 @@ impl AppState {
         Ok(())
     }
 
+    pub async fn send_refresh_reuse(&self, email: String, ip : String, user_agent : String) -> Result<()> {
+        let email = Email{
+            to: email,
+            kind: EmailKind::RefreshTokenReuse { ip, user_agent }
+        };
+        self.send_email(email).await?;
+        Ok(())
+    }
+
     pub async fn send_refresh_rules_update(&self, email: String, ip : String, user_agent : String) -> Result<()> {
         let email = Email{
             to: email,
@@ -222,19 +245,22 @@ impl AppState {
         };
         self.redis.verify_code(email_code).await
     }
-    pub async fn recovery_password(&self, code: &String, new_password: String) -> Result<Option<()>> {
+    pub async fn recovery_password(&self, code: &String, new_password: String, totp_code: Option<&str>) -> Result<PasswordRecovery> {
+        // the token survives a wrong second factor, so the user can retry
+        let Some(email) = self.redis.get_recovery_email(code).await? else {return Ok(PasswordRecovery::InvalidToken)};
+        if !self.check_second_factor_by_email(&email, totp_code).await? {return Ok(PasswordRecovery::SecondFactorFailed)}
         if let Some(email) = self.redis.pop_recovery_email(code).await? {
             self.set_password(&email, new_password).await?;
             self.send_changed_notification(email, ChangedField::Password).await?;
-            return Ok(Some(()));
+            return Ok(PasswordRecovery::Done);
         };
-        Ok(None)
+        Ok(PasswordRecovery::InvalidToken)
     }
 
     pub async fn send_email(&self, email: Email) -> Result<()> {
         info!("Sending \"{}\" to {}", email.kind.name(), email.to);
         let encoded =  bincode::encode_to_vec(&email, bincode::config::standard())?;
-        self.publisher.publish(ENV.EMAIL_SEND_NATS_EVENT.clone(), encoded.into())
+        self.publisher.publish_with_headers(ENV.EMAIL_SEND_NATS_EVENT.clone(), request_headers(), encoded.into())
             .await?
             //.await? // todo: checks that msg can be read
             ;
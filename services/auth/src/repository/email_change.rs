@@ -0,0 +1,148 @@
+use anyhow::Result;
+use bb8_redis::redis::AsyncCommands;
+use message_broker::email::types::{ChangedField, Email, EmailKind};
+use postgre_entities::user_data;
+use rand::Rng;
+use redis_utils::{redis::RedisConn, redis_tokens::RedisTokens};
+use sea_orm::{prelude::Uuid, sea_query::Expr, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{AppState, CFG};
+
+/// Change waiting for the code sent to the new address
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingEmailChange {
+    pub old_email: String,
+    pub new_email: String,
+    pub code: String,
+    pub cancel_token: String,
+    pub attempts: u8,
+}
+
+pub enum RequestEmailChange {
+    Sent,
+    WrongCredentials,
+    EmailTaken,
+}
+
+pub enum ConfirmEmailChange {
+    Done,
+    WrongCode,
+    /// Expired, cancelled or out of attempts
+    NoPendingChange,
+    EmailTaken,
+}
+
+pub trait EmailChangeStore {
+    async fn put_email_change(&self, user: &Uuid, change: &PendingEmailChange) -> Result<()>;
+    async fn pop_email_change(&self, user: &Uuid) -> Result<Option<PendingEmailChange>>;
+    /// Drops the change the cancel token belongs to
+    async fn cancel_email_change(&self, cancel_token: &str) -> Result<bool>;
+}
+
+fn change_to_key(user: &Uuid) -> String {
+    format!("EMAIL_CHANGE:{}", user.simple())
+}
+
+fn cancel_to_key(cancel_token: &str) -> String {
+    format!("EMAIL_CHANGE_CANCEL:{cancel_token}")
+}
+
+impl EmailChangeStore for RedisConn {
+    async fn put_email_change(&self, user: &Uuid, change: &PendingEmailChange) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _ : () = conn.set_ex(change_to_key(user), serde_json::to_string(change)?, CFG.EMAIL_CHANGE_LIFETIME).await?;
+        let _ : () = conn.set_ex(cancel_to_key(&change.cancel_token), user.to_string(), CFG.EMAIL_CHANGE_LIFETIME).await?;
+        Ok(())
+    }
+
+    async fn pop_email_change(&self, user: &Uuid) -> Result<Option<PendingEmailChange>> {
+        let mut conn = self.pool.get().await?;
+        let r : Option<String> = conn.get_del(change_to_key(user)).await?;
+        Ok(r.and_then(|r| serde_json::from_str(&r).ok()))
+    }
+
+    async fn cancel_email_change(&self, cancel_token: &str) -> Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let user : Option<String> = conn.get_del(cancel_to_key(cancel_token)).await?;
+        let Some(user) = user.and_then(|u| u.parse::<Uuid>().ok()) else {return Ok(false)};
+        let _ : () = conn.del(change_to_key(&user)).await?;
+        info!("Email change of {user} cancelled");
+        Ok(true)
+    }
+}
+
+impl AppState {
+    async fn is_email_taken(&self, email: &str) -> Result<bool> {
+        Ok(user_data::Entity::find().filter(user_data::Column::Email.eq(email)).count(&self.db).await? > 0)
+    }
+
+    /// Sends a code to the new address and a cancel link to the old one, a previous pending change is replaced
+    pub async fn request_email_change(&self, user: &Uuid, new_email: String, password: &str, totp_code: Option<&str>) -> Result<RequestEmailChange> {
+        let Some(model) = user_data::Entity::find_by_id(*user).one(&self.db).await? else {return Ok(RequestEmailChange::WrongCredentials)};
+        if !bcrypt::verify(password, &model.password)? || !self.check_second_factor(&model, totp_code).await? {
+            return Ok(RequestEmailChange::WrongCredentials)
+        }
+        if self.is_email_taken(&new_email).await? {return Ok(RequestEmailChange::EmailTaken)}
+        if let Some(previous) = self.redis.pop_email_change(user).await? {
+            self.redis.cancel_email_change(&previous.cancel_token).await?;
+        }
+        let change = PendingEmailChange {
+            old_email: model.email,
+            new_email,
+            code: rand::rng().random_range(100_000..1_000_000).to_string(),
+            cancel_token: shared::utils::token::generate_secure_token(128),
+            attempts: 0,
+        };
+        self.redis.put_email_change(user, &change).await?;
+        self.send_email(Email {to: change.new_email.clone(), kind: EmailKind::EmailChangeCode {code: change.code.clone()}}).await?;
+        self.send_email(Email {
+            to: change.old_email.clone(),
+            kind: EmailKind::EmailChangeRequested {
+                new_email: change.new_email.clone(),
+                cancel_link: format!("{}?token={}", CFG.EMAIL_CHANGE_CANCEL_URL, change.cancel_token),
+            }
+        }).await?;
+        info!("Email change requested by {user}");
+        Ok(RequestEmailChange::Sent)
+    }
+
+    /// Swaps `user_data.email` if it's still the address the change was requested from,
+    /// then rewrites the email of live refresh sessions
+    pub async fn confirm_email_change(&self, user: &Uuid, code: &str) -> Result<ConfirmEmailChange> {
+        let Some(mut change) = self.redis.pop_email_change(user).await? else {return Ok(ConfirmEmailChange::NoPendingChange)};
+        if change.code != code.trim() {
+            change.attempts += 1;
+            if change.attempts < CFG.EMAIL_CHANGE_ATTEMPTS {
+                self.redis.put_email_change(user, &change).await?;
+            } else {
+                self.redis.cancel_email_change(&change.cancel_token).await?;
+            }
+            return Ok(ConfirmEmailChange::WrongCode)
+        }
+        self.redis.cancel_email_change(&change.cancel_token).await?;
+
+        // single conditional update, a concurrent change of the same account makes it a no-op
+        let updated = user_data::Entity::update_many()
+            .col_expr(user_data::Column::Email, Expr::value(change.new_email.clone()))
+            .filter(user_data::Column::Guid.eq(*user))
+            .filter(user_data::Column::Email.eq(&change.old_email))
+            .exec(&self.db)
+            .await;
+        let updated = match updated {
+            Ok(updated) => updated,
+            // unique constraint, the address was registered meanwhile
+            Err(e) => {
+                warn!("Email change of {user} failed: {e}");
+                return Ok(ConfirmEmailChange::EmailTaken)
+            }
+        };
+        if updated.rows_affected != 1 {return Ok(ConfirmEmailChange::NoPendingChange)}
+
+        self.redis.set_refresh_email(user, &change.new_email).await?;
+        self.send_email(Email::changed(change.old_email, ChangedField::Email)).await?;
+        info!("Email of {user} changed");
+        Ok(ConfirmEmailChange::Done)
+    }
+}
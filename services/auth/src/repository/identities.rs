@@ -0,0 +1,107 @@
+use anyhow::Result;
+use bb8_redis::redis::AsyncCommands;
+use postgre_entities::{user_data, webauthn_credential};
+use redis_utils::redis::RedisConn;
+use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, EntityTrait, ModelTrait, PaginatorTrait, QueryFilter, Value};
+use serde::Serialize;
+use tracing::info;
+
+use crate::{AppState, CFG};
+
+/// Providers and their `user_data` columns
+const PROVIDERS: [(&str, user_data::Column); 2] = [
+    ("google", user_data::Column::GoogleId),
+    ("discord", user_data::Column::DiscordId),
+];
+
+#[derive(Debug, Serialize)]
+pub struct LinkedIdentity {
+    pub provider: &'static str,
+    pub id: String,
+}
+
+pub enum LinkIdentity {
+    Done,
+    /// The user has another account of this provider linked
+    AlreadyLinked,
+    /// The provider account belongs to another user
+    UsedByOther,
+}
+
+pub enum UnlinkIdentity {
+    Done,
+    NotLinked,
+    /// Nothing else to log in with
+    LastLoginMethod,
+}
+
+fn linked_id(model: &user_data::Model, column: user_data::Column) -> Option<String> {
+    match model.get(column) {
+        Value::String(Some(id)) => Some(*id),
+        _ => None,
+    }
+}
+
+/// OAuth `state` of a link flow to the user who started it
+pub trait OAuthLinkStore {
+    async fn put_oauth_link(&self, state: &str, user: &Uuid) -> Result<()>;
+    async fn pop_oauth_link(&self, state: &str) -> Result<Option<Uuid>>;
+}
+
+fn link_to_key(state: &str) -> String {
+    format!("OAUTH_LINK:{state}")
+}
+
+impl OAuthLinkStore for RedisConn {
+    async fn put_oauth_link(&self, state: &str, user: &Uuid) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _ : () = conn.set_ex(link_to_key(state), user.to_string(), CFG.OAUTH_LINK_LIFETIME).await?;
+        Ok(())
+    }
+
+    async fn pop_oauth_link(&self, state: &str) -> Result<Option<Uuid>> {
+        let mut conn = self.pool.get().await?;
+        let r : Option<String> = conn.get_del(link_to_key(state)).await?;
+        Ok(r.and_then(|r| r.parse().ok()))
+    }
+}
+
+impl AppState {
+    pub async fn link_identity(&self, user: &Uuid, column: user_data::Column, id: String) -> Result<LinkIdentity> {
+        let owner = user_data::Entity::find().filter(column.eq(&id)).one(&self.db).await?;
+        if let Some(owner) = owner {
+            return Ok(if owner.guid == *user {LinkIdentity::Done} else {LinkIdentity::UsedByOther})
+        }
+        let Some(model) = user_data::Entity::find_by_id(*user).one(&self.db).await? else {return Ok(LinkIdentity::UsedByOther)};
+        if linked_id(&model, column).is_some() {return Ok(LinkIdentity::AlreadyLinked)}
+        let mut model: user_data::ActiveModel = model.into();
+        model.set(column, Some(id).into());
+        model.update(&self.db).await?;
+        info!("Linked {column:?} to {user}");
+        Ok(LinkIdentity::Done)
+    }
+
+    pub async fn linked_identities(&self, user: &Uuid) -> Result<Vec<LinkedIdentity>> {
+        let Some(model) = user_data::Entity::find_by_id(*user).one(&self.db).await? else {return Ok(vec![])};
+        Ok(PROVIDERS.into_iter()
+            .filter_map(|(provider, column)| linked_id(&model, column).map(|id| LinkedIdentity {provider, id}))
+            .collect())
+    }
+
+    pub async fn unlink_identity(&self, user: &Uuid, column: user_data::Column) -> Result<UnlinkIdentity> {
+        let Some(model) = user_data::Entity::find_by_id(*user).one(&self.db).await? else {return Ok(UnlinkIdentity::NotLinked)};
+        if linked_id(&model, column).is_none() {return Ok(UnlinkIdentity::NotLinked)}
+        let passkeys = webauthn_credential::Entity::find()
+            .filter(webauthn_credential::Column::UserGuid.eq(*user))
+            .count(&self.db).await?;
+        let login_methods = usize::from(!model.password.is_empty())
+            + usize::from(passkeys > 0)
+            + PROVIDERS.iter().filter(|(_, c)| linked_id(&model, *c).is_some()).count();
+        if login_methods <= 1 {return Ok(UnlinkIdentity::LastLoginMethod)}
+        let mut model: user_data::ActiveModel = model.into();
+        model.set(column, Option::<String>::None.into());
+        model.update(&self.db).await?;
+        info!("Unlinked {column:?} from {user}");
+        Ok(UnlinkIdentity::Done)
+    }
+}
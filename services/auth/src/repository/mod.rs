@@ -2,4 +2,13 @@ pub mod tokens;
 pub mod email;
 pub mod db;
 pub mod cookies;
-pub mod refresh_processor;
\ No newline at end of file
+pub mod refresh_processor;
+pub mod revocation;
+pub mod two_factor;
+pub mod webauthn;
+pub mod oauth_provider;
+pub mod identities;
+pub mod email_change;
+pub mod uid_change;
+pub mod account_deletion;
+pub mod sessions;
\ No newline at end of file
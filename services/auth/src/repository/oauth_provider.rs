@@ -0,0 +1,262 @@
+use anyhow::{anyhow, Result};
+use oauth2::{basic::{BasicClient, BasicErrorResponseType, BasicTokenType}, AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EmptyExtraTokenFields, EndpointNotSet, EndpointSet, RedirectUrl, RevocationErrorResponseType, Scope, StandardErrorResponse, StandardRevocableToken, StandardTokenIntrospectionResponse, StandardTokenResponse, TokenResponse, TokenUrl};
+use postgre_entities::user_data;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{CFG, ENV};
+
+pub type OAuthClient = oauth2::Client<
+    StandardErrorResponse<BasicErrorResponseType>,
+    StandardTokenResponse<EmptyExtraTokenFields, BasicTokenType>,
+    StandardTokenIntrospectionResponse<EmptyExtraTokenFields, BasicTokenType>,
+    StandardRevocableToken,
+    StandardErrorResponse<RevocationErrorResponseType>, EndpointSet, EndpointNotSet,
+    EndpointNotSet,
+    EndpointNotSet,
+    EndpointSet
+>;
+
+/// Client credentials and endpoints, endpoints are configurable to run against a mock server
+#[derive(Debug, Clone)]
+pub struct OAuthConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub authorize_uri: String,
+    pub token_uri: String,
+    pub userinfo_uri: String,
+}
+
+impl OAuthConfig {
+    fn build_client(&self) -> Result<OAuthClient> {
+        Ok(BasicClient::new(ClientId::new(self.client_id.clone()))
+            .set_client_secret(ClientSecret::new(self.client_secret.clone()))
+            .set_auth_uri(AuthUrl::new(self.authorize_uri.clone())?)
+            .set_token_uri(TokenUrl::new(self.token_uri.clone())?)
+            .set_redirect_uri(RedirectUrl::new(self.redirect_uri.clone())?))
+    }
+}
+
+/// Provider account mapped to what registration and login need
+#[derive(Debug, Clone, PartialEq)]
+pub struct OAuthUserInfo {
+    pub id: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub picture: Option<String>,
+}
+
+pub trait OAuthProvider {
+    /// Userinfo response of the provider
+    type UserInfo: DeserializeOwned + Into<OAuthUserInfo>;
+    /// `user_data` column holding the provider account id
+    const COLUMN: user_data::Column;
+    const SCOPES: &'static [&'static str];
+
+    fn client(&self) -> &OAuthClient;
+    fn userinfo_uri(&self) -> &str;
+
+    fn authorize_url(&self, state: String) -> String {
+        self.client()
+            .authorize_url(|| CsrfToken::new(state))
+            .add_scopes(Self::SCOPES.iter().map(|s| Scope::new(s.to_string())))
+            .url().0.to_string()
+    }
+
+    /// Exchanges the authorization code and fetches the account behind it
+    async fn fetch_user(&self, http: &reqwest::Client, code: String) -> Result<OAuthUserInfo> {
+        let token = self.client()
+            .exchange_code(AuthorizationCode::new(code))
+            .request_async(http)
+            .await
+            .map_err(|e| anyhow!("Code exchange failed: {e}"))?;
+        let user_info: Self::UserInfo = http
+            .get(self.userinfo_uri())
+            .bearer_auth(token.access_token().secret())
+            .send().await?
+            .error_for_status()?
+            .json().await?;
+        Ok(user_info.into())
+    }
+}
+
+pub struct Google {
+    client: OAuthClient,
+    userinfo_uri: String,
+}
+
+impl Google {
+    pub fn new(config: OAuthConfig) -> Result<Self> {
+        Ok(Self {client: config.build_client()?, userinfo_uri: config.userinfo_uri})
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Self::new(OAuthConfig {
+            client_id: ENV.GOOGLE_CLIENT_ID.clone(),
+            client_secret: ENV.GOOGLE_CLIENT_SECRET.clone(),
+            redirect_uri: ENV.GOOGLE_REDIRECT_URI.clone(),
+            authorize_uri: CFG.GOOGLE_AUTHORIZE_URI.clone(),
+            token_uri: CFG.GOOGLE_TOKEN_URI.clone(),
+            userinfo_uri: CFG.GOOGLE_USERINFO_URI.clone(),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GoogleUserInfo {
+    sub: String,
+    email: String,
+    email_verified: bool,
+    picture: Option<String>,
+}
+
+impl From<GoogleUserInfo> for OAuthUserInfo {
+    fn from(info: GoogleUserInfo) -> Self {
+        Self {id: info.sub, email: info.email, email_verified: info.email_verified, picture: info.picture}
+    }
+}
+
+impl OAuthProvider for Google {
+    type UserInfo = GoogleUserInfo;
+    const COLUMN: user_data::Column = user_data::Column::GoogleId;
+    const SCOPES: &'static [&'static str] = &["openid", "email", "profile"];
+
+    fn client(&self) -> &OAuthClient {&self.client}
+    fn userinfo_uri(&self) -> &str {&self.userinfo_uri}
+}
+
+pub struct Discord {
+    client: OAuthClient,
+    userinfo_uri: String,
+}
+
+impl Discord {
+    pub fn new(config: OAuthConfig) -> Result<Self> {
+        Ok(Self {client: config.build_client()?, userinfo_uri: config.userinfo_uri})
+    }
+
+    pub fn from_env() -> Result<Self> {
+        Self::new(OAuthConfig {
+            client_id: ENV.DISCORD_CLIENT_ID.clone(),
+            client_secret: ENV.DISCORD_CLIENT_SECRET.clone(),
+            redirect_uri: ENV.DISCORD_REDIRECT_URI.clone(),
+            authorize_uri: CFG.DISCORD_AUTHORIZE_URI.clone(),
+            token_uri: CFG.DISCORD_TOKEN_URI.clone(),
+            userinfo_uri: CFG.DISCORD_USERINFO_URI.clone(),
+        })
+    }
+}
+
+/// `GET /users/@me`, email fields need the `email` scope
+#[derive(Debug, Deserialize)]
+pub struct DiscordUserInfo {
+    id: String,
+    email: Option<String>,
+    #[serde(default)]
+    verified: bool,
+    avatar: Option<String>,
+}
+
+impl From<DiscordUserInfo> for OAuthUserInfo {
+    fn from(info: DiscordUserInfo) -> Self {
+        let picture = info.avatar.map(|hash| format!("https://cdn.discordapp.com/avatars/{}/{hash}.png", info.id));
+        // accounts without an email can't be matched or registered, treat them as unverified
+        let email_verified = info.verified && info.email.is_some();
+        Self {id: info.id, email: info.email.unwrap_or_default(), email_verified, picture}
+    }
+}
+
+impl OAuthProvider for Discord {
+    type UserInfo = DiscordUserInfo;
+    const COLUMN: user_data::Column = user_data::Column::DiscordId;
+    const SCOPES: &'static [&'static str] = &["identify", "email"];
+
+    fn client(&self) -> &OAuthClient {&self.client}
+    fn userinfo_uri(&self) -> &str {&self.userinfo_uri}
+}
+
+/// Configured providers, cheap to clone
+#[derive(Clone)]
+pub struct OAuthProviders {
+    pub google: std::sync::Arc<Google>,
+    pub discord: std::sync::Arc<Discord>,
+}
+
+impl OAuthProviders {
+    pub fn from_env() -> Result<Self> {
+        Ok(Self {google: Google::from_env()?.into(), discord: Discord::from_env()?.into()})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{extract::Form, http::HeaderMap, routing::{get, post}, Json, Router};
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    /// Token endpoint accepting only `good-code`, userinfo accepting only the issued token
+    async fn mock_server(userinfo: Value) -> String {
+        let app = Router::new()
+            .route("/token", post(|Form(form): Form<std::collections::HashMap<String, String>>| async move {
+                if form.get("code").map(String::as_str) != Some("good-code") {
+                    return (axum::http::StatusCode::BAD_REQUEST, Json(json!({"error": "invalid_grant"})));
+                }
+                (axum::http::StatusCode::OK, Json(json!({"access_token": "mock-token", "token_type": "Bearer", "expires_in": 3600})))
+            }))
+            .route("/userinfo", get(move |headers: HeaderMap| async move {
+                if headers.get("authorization").and_then(|v| v.to_str().ok()) != Some("Bearer mock-token") {
+                    return (axum::http::StatusCode::UNAUTHORIZED, Json(Value::Null));
+                }
+                (axum::http::StatusCode::OK, Json(userinfo))
+            }));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{addr}")
+    }
+
+    fn config(base: &str) -> OAuthConfig {
+        OAuthConfig {
+            client_id: "client".into(),
+            client_secret: "secret".into(),
+            redirect_uri: "http://localhost/api/auth/oauth".into(),
+            authorize_uri: format!("{base}/authorize"),
+            token_uri: format!("{base}/token"),
+            userinfo_uri: format!("{base}/userinfo"),
+        }
+    }
+
+    #[tokio::test]
+    async fn google_flow() {
+        let base = mock_server(json!({"sub": "g-1", "email": "a@example.com", "email_verified": true, "picture": "https://pic"})).await;
+        let google = Google::new(config(&base)).unwrap();
+        let info = google.fetch_user(&reqwest::Client::new(), "good-code".into()).await.unwrap();
+        assert_eq!(info, OAuthUserInfo {id: "g-1".into(), email: "a@example.com".into(), email_verified: true, picture: Some("https://pic".into())});
+        assert!(google.fetch_user(&reqwest::Client::new(), "bad-code".into()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn discord_flow() {
+        let base = mock_server(json!({"id": "42", "username": "d", "email": "d@example.com", "verified": true, "avatar": "abc"})).await;
+        let discord = Discord::new(config(&base)).unwrap();
+        let info = discord.fetch_user(&reqwest::Client::new(), "good-code".into()).await.unwrap();
+        assert_eq!(info.id, "42");
+        assert!(info.email_verified);
+        assert_eq!(info.picture.as_deref(), Some("https://cdn.discordapp.com/avatars/42/abc.png"));
+    }
+
+    #[test]
+    fn discord_without_email_is_unverified() {
+        let info: OAuthUserInfo = DiscordUserInfo {id: "1".into(), email: None, verified: true, avatar: None}.into();
+        assert!(!info.email_verified);
+    }
+
+    #[test]
+    fn authorize_url_has_scopes_and_state() {
+        let url = Discord::new(config("http://mock")).unwrap().authorize_url("xyz".into());
+        assert!(url.starts_with("http://mock/authorize?"));
+        assert!(url.contains("state=xyz"));
+        assert!(url.contains("scope=identify+email"));
+    }
+}
@@ -28,7 +28,10 @@ impl<'a> RefreshProcessor<'a> {
         jar = jar.rm_refresh();
         let Some(refresh_payload) = TokenEncoder::decode_refresh(refresh_token_string) else {return Err((jar, StatusCode::UNAUTHORIZED).into_response())};
         let record = state.redis.pop_refresh(&refresh_payload.rtid).await.trough_app_err()?;
-        let Some(record) = record else {return Err((jar.rm_refresh(), StatusCode::UNAUTHORIZED).into_response())};
+        let Some(record) = record else {
+            state.handle_refresh_reuse(&refresh_payload.rtid, &user_info).await.trough_app_err()?;
+            return Err((jar.rm_refresh(), StatusCode::UNAUTHORIZED).into_response())
+        };
         let refresh_rules = refresh_payload.rules.clone();
         Ok(RefreshProcessor {
             jar,
@@ -50,6 +53,7 @@ impl<'a> RefreshProcessor<'a> {
 
     pub async fn rm_all_refresh(self) -> Result<Self, Response<Body>> {
         self.state.redis.rm_all_refresh(&self.refresh_payload.user).await.trough_app_err()?;
+        self.state.revoke_user_tokens(&self.refresh_payload.user).await.trough_app_err()?;
         Ok(self)
     }
 
@@ -61,8 +65,8 @@ impl<'a> RefreshProcessor<'a> {
 
 
     pub async fn generate_tokens(self) -> Result<Response<Body>, Response<Body>> {
-        let jar = generate_and_put_refresh(self.jar, self.state, &self.record.user, self.user_info, self.record.email, self.refresh_payload.rules).await.trough_app_err()?;
-        let access_response = generate_access(self.record.user).trough_app_err()?;
+        let (jar, rtid) = generate_and_put_refresh(self.jar, self.state, &self.record.user, self.user_info, self.record.email.clone(), self.refresh_payload.rules, Some(&self.record)).await.trough_app_err()?;
+        let access_response = generate_access(self.record.user, rtid).trough_app_err()?;
         let v = (StatusCode::OK, jar, access_response).into_response();
         Ok(v)
     }
@@ -0,0 +1,25 @@
+use anyhow::Result;
+use message_broker::{publisher::publish_revocation, revocation::types::RevocationEvent};
+use redis_utils::redis_revocation::RedisRevocation;
+use sea_orm::{prelude::Uuid, sqlx::types::chrono::Utc};
+use tracing::info;
+
+use crate::AppState;
+
+impl AppState {
+    /// Invalidates every access token of the user issued until now
+    pub async fn revoke_user_tokens(&self, user: &Uuid) -> Result<()> {
+        let before = Utc::now().timestamp();
+        info!("Revoking access tokens of {user} issued before {before}");
+        self.redis.revoke_user_before(user, before).await?;
+        publish_revocation(&self.publisher, &RevocationEvent::User{user: user.to_string(), before}).await?;
+        Ok(())
+    }
+
+    /// Invalidates access tokens bound to the refresh token `rtid`
+    pub async fn revoke_session(&self, sid: &Uuid) -> Result<()> {
+        self.redis.revoke_session(sid).await?;
+        publish_revocation(&self.publisher, &RevocationEvent::Session{sid: sid.to_string()}).await?;
+        Ok(())
+    }
+}
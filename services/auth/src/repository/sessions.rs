@@ -0,0 +1,63 @@
+use anyhow::Result;
+use layers::logging::UserInfoExt;
+use redis_utils::redis_tokens::RedisTokens;
+use sea_orm::{prelude::Uuid, sqlx::types::chrono::Utc};
+use serde::Serialize;
+use tracing::{info, warn};
+
+use crate::{AppState, CFG};
+
+/// Refresh session as shown to its owner, `id` is the opaque session id, never the `rtid`
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: Uuid,
+    pub user_agent: String,
+    pub ip: String,
+    pub created_at: i64,
+    pub refreshed_at: i64,
+    pub current: bool,
+}
+
+impl AppState {
+    /// `current_sid` is the `sid` of the caller's access token
+    pub async fn list_sessions(&self, user: &Uuid, current_sid: Option<Uuid>) -> Result<Vec<SessionInfo>> {
+        let mut sessions: Vec<SessionInfo> = self.redis.get_user_refresh(user).await?
+            .into_iter()
+            .map(|r| SessionInfo {
+                id: r.session,
+                current: current_sid == Some(r.rtid),
+                user_agent: r.user_agent,
+                ip: r.ip,
+                created_at: r.created_at,
+                refreshed_at: r.refreshed_at,
+            })
+            .collect();
+        sessions.sort_by_key(|s| std::cmp::Reverse(s.refreshed_at));
+        Ok(sessions)
+    }
+
+    /// Drops the refresh record of the session and revokes access tokens issued with it.
+    /// Sessions of other users are reported as missing.
+    pub async fn end_session(&self, user: &Uuid, session: &Uuid) -> Result<bool> {
+        let records = self.redis.get_user_refresh(user).await?;
+        let Some(record) = records.into_iter().find(|r| r.session == *session) else {return Ok(false)};
+        self.redis.rm_refresh(&record.rtid).await?;
+        self.revoke_session(&record.rtid).await?;
+        info!("Session {session} of {user} ended");
+        Ok(true)
+    }
+
+    /// Called for refresh tokens without a live record. A rotated one means either the owner
+    /// or someone who stole it already refreshed, so the whole session (token family) is ended.
+    pub async fn handle_refresh_reuse(&self, rtid: &Uuid, user_info: &UserInfoExt) -> Result<()> {
+        let Some(rotated) = self.redis.get_rotated_refresh(rtid).await? else {return Ok(())};
+        // concurrent refreshes with the same token, e.g. from several tabs
+        if Utc::now().timestamp() - rotated.rotated_at < CFG.REFRESH_REUSE_GRACE {return Ok(())}
+        warn!("Rotated refresh token {rtid} reused, ending session {} of {}. {user_info}", rotated.session, rotated.user);
+        self.end_session(&rotated.user, &rotated.session).await?;
+        // access tokens of earlier rotations are still alive, other sessions just refresh
+        self.revoke_user_tokens(&rotated.user).await?;
+        self.send_refresh_reuse(rotated.email, user_info.ip.clone(), user_info.user_agent.clone()).await?;
+        Ok(())
+    }
+}
@@ -9,11 +9,14 @@ use tracing::info;
 
 use crate::{repository::cookies::TokenCookie, AppState, CFG};
 
-pub fn generate_access(user_id: Uuid) ->  Result<AccessTokenResponse> { // todo: move to state
-    let exp = Utc::now().timestamp() + CFG.ACCESS_TOKEN_LIFETIME as i64;
+pub fn generate_access(user_id: Uuid, sid: Uuid) ->  Result<AccessTokenResponse> { // todo: move to state
+    let iat = Utc::now().timestamp();
+    let exp = iat + CFG.ACCESS_TOKEN_LIFETIME as i64;
     let access_payload = AccessTokenPayload {
         user: user_id,
-        exp
+        exp,
+        iat,
+        sid: Some(sid),
     };
     let access_token = TokenEncoder::encode_access(access_payload)?;
     Ok(AccessTokenResponse{
@@ -30,17 +33,28 @@ pub async fn generate_and_put_refresh(
     user_id: &Uuid,
     user_info: UserInfoExt,
     email: String,
-    rules: RefreshRules
-) -> Result<CookieJar> {
+    rules: RefreshRules,
+    rotated: Option<&RefreshTokenRecord>,
+) -> Result<(CookieJar, Uuid)> {
     let rtid: Uuid = Uuid::new_v4();
     info!("Generating refresh token for {}. {}", user_id, user_info);
+    let now = Utc::now().timestamp();
+    // a refresh continues the session of the rotated record, anything else starts a new one
+    let (session, created_at) = match rotated {
+        Some(r) if !r.session.is_nil() => (r.session, r.created_at),
+        _ => (Uuid::new_v4(), now),
+    };
     let refresh_record = RefreshTokenRecord {
         rtid,
         user: *user_id,
         fingerprint: user_info.fingerprint,
         user_agent: user_info.user_agent,
         ip: user_info.ip,
-        email
+        email,
+        session,
+        created_at,
+        refreshed_at: now,
+        parent: rotated.map(|r| r.rtid),
     };
     let refresh_payload = RefreshTokenPayload{
         rtid,
@@ -51,6 +65,6 @@ pub async fn generate_and_put_refresh(
     let refresh_token = TokenEncoder::encode_refresh(refresh_payload)?;
     info!("Rtid {rtid}");
     state.redis.set_refresh(refresh_record).await?;
-    Ok(jar.put_refresh(refresh_token))
+    Ok((jar.put_refresh(refresh_token), rtid))
 }
 
@@ -0,0 +1,214 @@
+use aes_gcm::{aead::Aead, Aes256Gcm, KeyInit, Nonce};
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose, Engine};
+use bb8_redis::redis::AsyncCommands;
+use postgre_entities::user_data;
+use rand::{distr::Alphanumeric, Rng, RngCore};
+use redis_utils::redis::RedisConn;
+use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, Set};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use shared::tokens::jwt::RefreshRules;
+use totp_rs::{Algorithm, Secret, TOTP};
+use tracing::{info, warn};
+
+use crate::{AppState, CFG, ENV};
+
+const NONCE_LEN: usize = 12;
+
+fn cipher() -> Result<Aes256Gcm> {
+    let key = general_purpose::STANDARD.decode(&ENV.TOTP_ENCRYPTION_KEY)?;
+    Aes256Gcm::new_from_slice(&key).map_err(|_| anyhow!("TOTP_ENCRYPTION_KEY must be 32 bytes encoded with base64"))
+}
+
+/// base64 of nonce and ciphertext
+fn encrypt_secret(secret: &[u8]) -> Result<String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rng().fill_bytes(&mut nonce);
+    let encrypted = cipher()?.encrypt(Nonce::from_slice(&nonce), secret).map_err(|_| anyhow!("Can't encrypt TOTP secret"))?;
+    Ok(general_purpose::STANDARD.encode([nonce.as_slice(), &encrypted].concat()))
+}
+
+fn decrypt_secret(stored: &str) -> Result<Vec<u8>> {
+    let bytes = general_purpose::STANDARD.decode(stored)?;
+    if bytes.len() <= NONCE_LEN {return Err(anyhow!("Stored TOTP secret is too short"))}
+    let (nonce, encrypted) = bytes.split_at(NONCE_LEN);
+    cipher()?.decrypt(Nonce::from_slice(nonce), encrypted).map_err(|_| anyhow!("Can't decrypt TOTP secret"))
+}
+
+fn build_totp(secret: Vec<u8>, account: &str) -> Result<TOTP> {
+    Ok(TOTP::new(Algorithm::SHA1, 6, 1, 30, secret, Some(CFG.TOTP_ISSUER.clone()), account.to_string())?)
+}
+
+fn hash_recovery_code(code: &str) -> String {
+    format!("{:x}", Sha256::digest(code.trim().to_uppercase().as_bytes()))
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..CFG.RECOVERY_CODES_COUNT)
+        .map(|_| rand::rng().sample_iter(&Alphanumeric).take(10).map(|c| (c as char).to_ascii_uppercase()).collect())
+        .collect()
+}
+
+/// Shown once on enrolment, the secret is stored encrypted until confirmation
+#[derive(Debug, Serialize)]
+pub struct TotpEnrolment {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+/// Login that passed the password check and waits for the second factor
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingTwoFactor {
+    pub uid: Uuid,
+    pub email: String,
+    pub rules: RefreshRules,
+    pub attempts: u8,
+}
+
+pub trait TwoFactorStore {
+    async fn put_pending_2fa(&self, id: &str, pending: &PendingTwoFactor) -> Result<()>;
+    async fn get_pending_2fa(&self, id: &str) -> Result<Option<PendingTwoFactor>>;
+    async fn rm_pending_2fa(&self, id: &str) -> Result<()>;
+    /// False if the code was already used, TOTP codes stay valid for a few steps
+    async fn mark_totp_used(&self, user: &Uuid, code: &str) -> Result<bool>;
+}
+
+fn pending_to_key(id: &str) -> String {
+    format!("PENDING_2FA:{id}")
+}
+
+fn used_totp_to_key(user: &Uuid, code: &str) -> String {
+    format!("TOTP_USED:{}:{code}", user.simple())
+}
+
+impl TwoFactorStore for RedisConn {
+    async fn put_pending_2fa(&self, id: &str, pending: &PendingTwoFactor) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _ : () = conn.set_ex(pending_to_key(id), serde_json::to_string(pending)?, CFG.PENDING_2FA_LIFETIME).await?;
+        Ok(())
+    }
+
+    async fn get_pending_2fa(&self, id: &str) -> Result<Option<PendingTwoFactor>> {
+        let mut conn = self.pool.get().await?;
+        let r : Option<String> = conn.get(pending_to_key(id)).await?;
+        Ok(r.and_then(|r| serde_json::from_str(&r).ok()))
+    }
+
+    async fn rm_pending_2fa(&self, id: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _ : () = conn.del(pending_to_key(id)).await?;
+        Ok(())
+    }
+
+    async fn mark_totp_used(&self, user: &Uuid, code: &str) -> Result<bool> {
+        let mut conn = self.pool.get().await?;
+        let fresh : bool = bb8_redis::redis::cmd("SET")
+            .arg(used_totp_to_key(user, code))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(90)
+            .query_async::<Option<String>>(&mut *conn)
+            .await?
+            .is_some();
+        Ok(fresh)
+    }
+}
+
+impl AppState {
+    async fn find_user(&self, user: &Uuid) -> Result<user_data::Model> {
+        user_data::Entity::find_by_id(*user).one(&self.db).await?.ok_or_else(|| anyhow!("Can't find user {user}"))
+    }
+
+    /// None if TOTP is already enabled
+    pub async fn begin_totp(&self, user: &Uuid) -> Result<Option<TotpEnrolment>> {
+        let model = self.find_user(user).await?;
+        if model.totp_enabled {return Ok(None)}
+        let secret = Secret::generate_secret().to_bytes().map_err(|e| anyhow!("Can't generate TOTP secret: {e:?}"))?;
+        let totp = build_totp(secret.clone(), &model.email)?;
+        let enrolment = TotpEnrolment {secret: totp.get_secret_base32(), otpauth_uri: totp.get_url()};
+        let mut model: user_data::ActiveModel = model.into();
+        model.totp_secret = Set(Some(encrypt_secret(&secret)?));
+        model.update(&self.db).await?;
+        Ok(Some(enrolment))
+    }
+
+    /// Enables TOTP if `code` matches the enrolled secret, returns new recovery codes
+    pub async fn confirm_totp(&self, user: &Uuid, code: &str) -> Result<Option<Vec<String>>> {
+        let model = self.find_user(user).await?;
+        if model.totp_enabled {return Ok(None)}
+        let Some(secret) = &model.totp_secret else {return Ok(None)};
+        let totp = build_totp(decrypt_secret(secret)?, &model.email)?;
+        if !totp.check_current(code)? || !self.redis.mark_totp_used(user, code).await? {return Ok(None)}
+        let codes = generate_recovery_codes();
+        let mut model: user_data::ActiveModel = model.into();
+        model.totp_enabled = Set(true);
+        model.totp_recovery_codes = Set(codes.iter().map(|c| hash_recovery_code(c)).collect());
+        model.update(&self.db).await?;
+        info!("TOTP enabled for {user}");
+        Ok(Some(codes))
+    }
+
+    /// False if the second factor is wrong
+    pub async fn disable_totp(&self, user: &Uuid, code: &str) -> Result<bool> {
+        let model = self.find_user(user).await?;
+        if !self.check_second_factor(&model, Some(code)).await? {return Ok(false)}
+        let mut model: user_data::ActiveModel = model.into();
+        model.totp_enabled = Set(false);
+        model.totp_secret = Set(None);
+        model.totp_recovery_codes = Set(vec![]);
+        model.update(&self.db).await?;
+        info!("TOTP disabled for {user}");
+        Ok(true)
+    }
+
+    /// Replaces recovery codes, None if the second factor is wrong or TOTP isn't enabled
+    pub async fn regenerate_recovery_codes(&self, user: &Uuid, code: &str) -> Result<Option<Vec<String>>> {
+        let model = self.find_user(user).await?;
+        if !model.totp_enabled || !self.check_second_factor(&model, Some(code)).await? {return Ok(None)}
+        let codes = generate_recovery_codes();
+        let mut model: user_data::ActiveModel = model.into();
+        model.totp_recovery_codes = Set(codes.iter().map(|c| hash_recovery_code(c)).collect());
+        model.update(&self.db).await?;
+        Ok(Some(codes))
+    }
+
+    /// True if TOTP is disabled, or `code` is a current TOTP code or an unused recovery code.
+    /// Recovery codes are consumed.
+    pub async fn check_second_factor(&self, model: &user_data::Model, code: Option<&str>) -> Result<bool> {
+        if !model.totp_enabled {return Ok(true)}
+        let Some(code) = code.map(str::trim).filter(|c| !c.is_empty()) else {return Ok(false)};
+        if let Some(secret) = &model.totp_secret {
+            let totp = build_totp(decrypt_secret(secret)?, &model.email)?;
+            if totp.check_current(code)? {
+                return self.redis.mark_totp_used(&model.guid, code).await;
+            }
+        }
+        let hashed = hash_recovery_code(code);
+        if !model.totp_recovery_codes.contains(&hashed) {return Ok(false)}
+        let remaining: Vec<String> = model.totp_recovery_codes.iter().filter(|c| **c != hashed).cloned().collect();
+        warn!("Recovery code used by {}, {} left", model.guid, remaining.len());
+        let mut active: user_data::ActiveModel = model.clone().into();
+        active.totp_recovery_codes = Set(remaining);
+        active.update(&self.db).await?;
+        Ok(true)
+    }
+
+    pub async fn check_second_factor_of(&self, user: &Uuid, code: Option<&str>) -> Result<bool> {
+        let model = self.find_user(user).await?;
+        self.check_second_factor(&model, code).await
+    }
+
+    /// True if there is no such user, the caller handles that case itself
+    pub async fn check_second_factor_by_email(&self, email: &str, code: Option<&str>) -> Result<bool> {
+        let model = user_data::Entity::find().filter(user_data::Column::Email.eq(email)).one(&self.db).await?;
+        let Some(model) = model else {return Ok(true)};
+        self.check_second_factor(&model, code).await
+    }
+
+    pub async fn is_totp_enabled(&self, user: &Uuid) -> Result<bool> {
+        Ok(self.find_user(user).await?.totp_enabled)
+    }
+}
+
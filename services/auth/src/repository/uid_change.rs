@@ -0,0 +1,52 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use message_broker::email::types::ChangedField;
+use postgre_entities::user_data;
+use redis_utils::users::RedisUsers;
+use sea_orm::{prelude::Uuid, ActiveModelTrait, EntityTrait, Set, SqlErr};
+use tracing::info;
+
+use crate::{AppState, CFG};
+
+pub enum ChangeUid {
+    Done,
+    /// Seconds left until the next change is allowed
+    Cooldown(i64),
+    /// Used by another user or reserved after their change
+    Taken,
+    NotFound,
+}
+
+impl AppState {
+    /// Reserved uids still redirect to their former owner, so only that owner may take them back
+    pub async fn is_uid_reserved_for_other(&self, uid: &str, user: Option<&Uuid>) -> Result<bool> {
+        let owner = self.redis.get_uid_redirect(uid).await?;
+        Ok(owner.is_some_and(|owner| Some(&owner) != user))
+    }
+
+    /// `last_uid_change` is set by the `user_data` hook, the old uid stays reserved for `CFG.UID_RESERVATION_TIME`
+    pub async fn change_uid(&self, user: &Uuid, new_uid: &str) -> Result<ChangeUid> {
+        let new_uid = new_uid.to_lowercase();
+        let Some(model) = user_data::Entity::find_by_id(*user).one(&self.db).await? else {return Ok(ChangeUid::NotFound)};
+        if model.uid == new_uid {return Ok(ChangeUid::Done)}
+        if let Some(last_change) = model.last_uid_change {
+            let left = last_change + Duration::seconds(CFG.UID_CHANGE_COOLDOWN) - Utc::now().naive_utc();
+            if left > Duration::zero() {return Ok(ChangeUid::Cooldown(left.num_seconds().max(1)))}
+        }
+        if self.is_uid_reserved_for_other(&new_uid, Some(user)).await? {return Ok(ChangeUid::Taken)}
+
+        let old_uid = model.uid.clone();
+        let email = model.email.clone();
+        let mut model: user_data::ActiveModel = model.into();
+        model.uid = Set(new_uid.clone());
+        if let Err(e) = model.update(&self.db).await {
+            if matches!(e.sql_err(), Some(SqlErr::UniqueConstraintViolation(_))) {return Ok(ChangeUid::Taken)}
+            return Err(e.into())
+        }
+
+        self.redis.rename_user(user, &old_uid, &new_uid, CFG.UID_RESERVATION_TIME).await?;
+        self.send_changed_notification(email, ChangedField::Uid).await?;
+        info!("Uid of {user} changed");
+        Ok(ChangeUid::Done)
+    }
+}
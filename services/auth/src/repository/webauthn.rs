@@ -0,0 +1,295 @@
+use anyhow::{anyhow, bail, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bb8_redis::redis::AsyncCommands;
+use ciborium::Value;
+use p256::{ecdsa::{signature::Verifier, Signature, VerifyingKey}, EncodedPoint};
+use postgre_entities::{user_data, webauthn_credential};
+use rand::RngCore;
+use redis_utils::redis::RedisConn;
+use sea_orm::{prelude::Uuid, ActiveModelTrait, ColumnTrait, EntityTrait, QueryFilter, QueryOrder, Set};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as Json};
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::{AppState, CFG, ENV};
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_DATA: u8 = 0x40;
+
+const COSE_KTY: i64 = 1;
+const COSE_ALG: i64 = 3;
+const COSE_CRV: i64 = -1;
+const COSE_X: i64 = -2;
+const COSE_Y: i64 = -3;
+const COSE_KTY_EC2: i64 = 2;
+const COSE_ALG_ES256: i64 = -7;
+const COSE_CRV_P256: i64 = 1;
+
+fn new_challenge() -> String {
+    let mut challenge = [0u8; 32];
+    rand::rng().fill_bytes(&mut challenge);
+    URL_SAFE_NO_PAD.encode(challenge)
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+fn verify_client_data(raw: &[u8], kind: &str, challenge: &str) -> Result<()> {
+    let data: ClientData = serde_json::from_slice(raw)?;
+    if data.kind != kind {bail!("Unexpected ceremony type {}", data.kind)}
+    if data.challenge != challenge {bail!("Challenge mismatch")}
+    if data.origin != ENV.WEBAUTHN_ORIGIN {bail!("Unexpected origin {}", data.origin)}
+    Ok(())
+}
+
+struct AuthenticatorData<'a> {
+    sign_count: u32,
+    /// Credential id and COSE public key, present only on registration
+    attested: Option<(&'a [u8], &'a [u8])>,
+}
+
+/// Checks rp id hash and that the user was present and verified
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>> {
+    if data.len() < 37 {bail!("Authenticator data is too short")}
+    if data[..32] != Sha256::digest(ENV.WEBAUTHN_RP_ID.as_bytes())[..] {bail!("RP id hash mismatch")}
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 || flags & FLAG_USER_VERIFIED == 0 {bail!("User wasn't verified")}
+    let sign_count = u32::from_be_bytes(data[33..37].try_into()?);
+    if flags & FLAG_ATTESTED_DATA == 0 {return Ok(AuthenticatorData {sign_count, attested: None})}
+    // aaguid (16) and credential id length (2)
+    let rest = data.get(37..).filter(|r| r.len() >= 18).ok_or_else(|| anyhow!("Attested data is too short"))?;
+    let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+    let id = rest.get(18..18 + id_len).ok_or_else(|| anyhow!("Credential id is truncated"))?;
+    Ok(AuthenticatorData {sign_count, attested: Some((id, &rest[18 + id_len..]))})
+}
+
+fn cose_field(map: &[(Value, Value)], label: i64) -> Option<&Value> {
+    map.iter().find(|(k, _)| k.as_integer() == Some(label.into())).map(|(_, v)| v)
+}
+
+fn cose_int(map: &[(Value, Value)], label: i64) -> Option<i64> {
+    cose_field(map, label)?.as_integer()?.try_into().ok()
+}
+
+/// Only ES256 keys are requested in `pubKeyCredParams`
+fn parse_cose_key(key: &[u8]) -> Result<VerifyingKey> {
+    let value: Value = ciborium::from_reader(key)?;
+    let map = value.as_map().ok_or_else(|| anyhow!("COSE key isn't a map"))?;
+    if cose_int(map, COSE_KTY) != Some(COSE_KTY_EC2) || cose_int(map, COSE_ALG) != Some(COSE_ALG_ES256) || cose_int(map, COSE_CRV) != Some(COSE_CRV_P256) {
+        bail!("Unsupported credential key")
+    }
+    let coordinate = |label| cose_field(map, label).and_then(Value::as_bytes).filter(|c| c.len() == 32).ok_or_else(|| anyhow!("Bad EC2 coordinate"));
+    let point = EncodedPoint::from_affine_coordinates(coordinate(COSE_X)?.as_slice().into(), coordinate(COSE_Y)?.as_slice().into(), false);
+    Ok(VerifyingKey::from_encoded_point(&point)?)
+}
+
+fn attestation_auth_data(attestation_object: &[u8]) -> Result<Vec<u8>> {
+    let value: Value = ciborium::from_reader(attestation_object)?;
+    // attestation is requested as "none", so the statement isn't verified
+    value.as_map()
+        .and_then(|map| map.iter().find(|(k, _)| k.as_text() == Some("authData")))
+        .and_then(|(_, v)| v.as_bytes().cloned())
+        .ok_or_else(|| anyhow!("Attestation object has no authData"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "attestationObject")]
+    pub attestation_object: String,
+}
+
+/// `PublicKeyCredential` of `navigator.credentials.create`, binary fields are base64url
+#[derive(Debug, Deserialize)]
+pub struct RegistrationCredential {
+    pub id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    #[serde(rename = "authenticatorData")]
+    pub authenticator_data: String,
+    pub signature: String,
+    #[serde(rename = "userHandle")]
+    pub user_handle: Option<String>,
+}
+
+/// `PublicKeyCredential` of `navigator.credentials.get`, binary fields are base64url
+#[derive(Debug, Deserialize)]
+pub struct AuthenticationCredential {
+    pub id: String,
+    pub response: AssertionResponse,
+}
+
+fn verify_registration(credential: &RegistrationCredential, challenge: &str) -> Result<(Vec<u8>, u32)> {
+    verify_client_data(&URL_SAFE_NO_PAD.decode(&credential.response.client_data_json)?, "webauthn.create", challenge)?;
+    let auth_data = attestation_auth_data(&URL_SAFE_NO_PAD.decode(&credential.response.attestation_object)?)?;
+    let parsed = parse_authenticator_data(&auth_data)?;
+    let (id, key) = parsed.attested.ok_or_else(|| anyhow!("No attested credential"))?;
+    if URL_SAFE_NO_PAD.encode(id) != credential.id {bail!("Credential id mismatch")}
+    Ok((parse_cose_key(key)?.to_encoded_point(false).as_bytes().to_vec(), parsed.sign_count))
+}
+
+fn verify_assertion(credential: &AuthenticationCredential, challenge: &str, stored: &webauthn_credential::Model) -> Result<u32> {
+    let client_data = URL_SAFE_NO_PAD.decode(&credential.response.client_data_json)?;
+    verify_client_data(&client_data, "webauthn.get", challenge)?;
+    if credential.response.user_handle.as_ref().is_some_and(|handle| *handle != URL_SAFE_NO_PAD.encode(stored.user_guid.as_bytes())) {
+        bail!("User handle mismatch")
+    }
+    let auth_data = URL_SAFE_NO_PAD.decode(&credential.response.authenticator_data)?;
+    let sign_count = parse_authenticator_data(&auth_data)?.sign_count;
+    let key = VerifyingKey::from_sec1_bytes(&stored.public_key)?;
+    let signature = Signature::from_der(&URL_SAFE_NO_PAD.decode(&credential.response.signature)?)?;
+    key.verify(&[auth_data.as_slice(), &Sha256::digest(&client_data)].concat(), &signature)?;
+    // authenticators without a counter always send 0, a counter going back means a cloned key
+    if (sign_count != 0 || stored.sign_count != 0) && i64::from(sign_count) <= stored.sign_count {
+        bail!("Sign count went back from {} to {sign_count}", stored.sign_count)
+    }
+    Ok(sign_count)
+}
+
+#[derive(Debug, Serialize)]
+pub struct PasskeyInfo {
+    pub id: String,
+    pub name: String,
+    pub created_at: chrono::NaiveDateTime,
+    pub last_used_at: Option<chrono::NaiveDateTime>,
+}
+
+pub trait WebauthnStore {
+    async fn put_challenge(&self, key: &str, challenge: &str) -> Result<()>;
+    async fn pop_challenge(&self, key: &str) -> Result<Option<String>>;
+}
+
+fn registration_key(user: &Uuid) -> String {
+    format!("WEBAUTHN_REG:{}", user.simple())
+}
+
+fn authentication_key(id: &str) -> String {
+    format!("WEBAUTHN_AUTH:{id}")
+}
+
+impl WebauthnStore for RedisConn {
+    async fn put_challenge(&self, key: &str, challenge: &str) -> Result<()> {
+        let mut conn = self.pool.get().await?;
+        let _ : () = conn.set_ex(key, challenge, CFG.WEBAUTHN_CHALLENGE_LIFETIME).await?;
+        Ok(())
+    }
+
+    async fn pop_challenge(&self, key: &str) -> Result<Option<String>> {
+        let mut conn = self.pool.get().await?;
+        let r : Option<String> = conn.get_del(key).await?;
+        Ok(r)
+    }
+}
+
+impl AppState {
+    /// `PublicKeyCredentialCreationOptions` for `navigator.credentials.create`
+    pub async fn begin_passkey_registration(&self, user: &Uuid) -> Result<Option<Json>> {
+        let Some(model) = user_data::Entity::find_by_id(*user).one(&self.db).await? else {return Ok(None)};
+        let existing = webauthn_credential::Entity::find()
+            .filter(webauthn_credential::Column::UserGuid.eq(*user))
+            .all(&self.db).await?;
+        let challenge = new_challenge();
+        self.redis.put_challenge(&registration_key(user), &challenge).await?;
+        Ok(Some(json!({
+            "publicKey": {
+                "rp": {"id": ENV.WEBAUTHN_RP_ID, "name": CFG.WEBAUTHN_RP_NAME},
+                "user": {"id": URL_SAFE_NO_PAD.encode(user.as_bytes()), "name": model.email, "displayName": model.nickname},
+                "challenge": challenge,
+                "pubKeyCredParams": [{"type": "public-key", "alg": COSE_ALG_ES256}],
+                "timeout": CFG.WEBAUTHN_CHALLENGE_LIFETIME * 1000,
+                "attestation": "none",
+                "authenticatorSelection": {"residentKey": "required", "userVerification": "required"},
+                "excludeCredentials": existing.iter().map(|c| json!({"type": "public-key", "id": c.credential_id})).collect::<Vec<_>>(),
+            }
+        })))
+    }
+
+    /// False if there is no pending registration or the attestation is invalid
+    pub async fn finish_passkey_registration(&self, user: &Uuid, name: String, credential: RegistrationCredential) -> Result<bool> {
+        let Some(challenge) = self.redis.pop_challenge(&registration_key(user)).await? else {return Ok(false)};
+        let (public_key, sign_count) = match verify_registration(&credential, &challenge) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Passkey registration of {user} rejected: {e}");
+                return Ok(false)
+            }
+        };
+        if webauthn_credential::Entity::find_by_id(credential.id.clone()).one(&self.db).await?.is_some() {return Ok(false)}
+        webauthn_credential::ActiveModel {
+            credential_id: Set(credential.id),
+            user_guid: Set(*user),
+            public_key: Set(public_key),
+            sign_count: Set(sign_count.into()),
+            name: Set(name),
+            ..Default::default()
+        }.insert(&self.db).await?;
+        info!("Passkey registered for {user}");
+        Ok(true)
+    }
+
+    /// Ceremony token and `PublicKeyCredentialRequestOptions`, credentials are discoverable so none are listed
+    pub async fn begin_passkey_login(&self) -> Result<Json> {
+        let token = shared::utils::token::generate_secure_token(256);
+        let challenge = new_challenge();
+        self.redis.put_challenge(&authentication_key(&token), &challenge).await?;
+        Ok(json!({
+            "token": token,
+            "publicKey": {
+                "challenge": challenge,
+                "rpId": ENV.WEBAUTHN_RP_ID,
+                "timeout": CFG.WEBAUTHN_CHALLENGE_LIFETIME * 1000,
+                "userVerification": "required",
+                "allowCredentials": [],
+            }
+        }))
+    }
+
+    /// User of a valid assertion
+    pub async fn finish_passkey_login(&self, token: &str, credential: AuthenticationCredential) -> Result<Option<user_data::Model>> {
+        let Some(challenge) = self.redis.pop_challenge(&authentication_key(token)).await? else {return Ok(None)};
+        let Some(stored) = webauthn_credential::Entity::find_by_id(credential.id.clone()).one(&self.db).await? else {return Ok(None)};
+        let sign_count = match verify_assertion(&credential, &challenge, &stored) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Passkey assertion of {} rejected: {e}", stored.user_guid);
+                return Ok(None)
+            }
+        };
+        let user = stored.user_guid;
+        let mut stored: webauthn_credential::ActiveModel = stored.into();
+        stored.sign_count = Set(sign_count.into());
+        stored.last_used_at = Set(Some(chrono::Utc::now().naive_utc()));
+        stored.update(&self.db).await?;
+        Ok(user_data::Entity::find_by_id(user).one(&self.db).await?)
+    }
+
+    pub async fn list_passkeys(&self, user: &Uuid) -> Result<Vec<PasskeyInfo>> {
+        let credentials = webauthn_credential::Entity::find()
+            .filter(webauthn_credential::Column::UserGuid.eq(*user))
+            .order_by_asc(webauthn_credential::Column::CreatedAt)
+            .all(&self.db).await?;
+        Ok(credentials.into_iter().map(|c| PasskeyInfo {id: c.credential_id, name: c.name, created_at: c.created_at, last_used_at: c.last_used_at}).collect())
+    }
+
+    /// False if the user has no such passkey
+    pub async fn remove_passkey(&self, user: &Uuid, id: String) -> Result<bool> {
+        let r = webauthn_credential::Entity::delete_many()
+            .filter(webauthn_credential::Column::CredentialId.eq(id))
+            .filter(webauthn_credential::Column::UserGuid.eq(*user))
+            .exec(&self.db).await?;
+        Ok(r.rows_affected > 0)
+    }
+}
@@ -1,7 +1,7 @@
 use axum::{
     body::Bytes, extract::ws::{Message, Utf8Bytes, WebSocket, WebSocketUpgrade}, http::HeaderMap, response::IntoResponse, routing::any, Router
 };
-use layers::{auth::AuthAccessLayer, rustperms::PermissionMiddlewareBuilder};
+use layers::{auth::AuthAccessLayer, revocation::RevocationStore, rustperms::{FailureMode, PermissionMiddlewareBuilder}};
 use redis_utils::redis::RedisConn;
 use rustperms_nodes::{connect_replica, proto::rustperms_replica_proto_client::RustpermsReplicaProtoClient};
 use serde::ser;
@@ -50,9 +50,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
 
 
-    let replica = connect_replica().await.unwrap();
+    let replica = connect_replica("calls").await.unwrap();
     let state = AppState::new().await;
-    let p = PermissionMiddlewareBuilder::new(replica);
+    let p = PermissionMiddlewareBuilder::new(replica).with_cache().await?;
+    let revocation = RevocationStore::connect().await?;
     let default_layer = ServiceBuilder::new()
         .layer(axum::middleware::from_fn(layers::layer_with_unique_span!("request ")))
         .layer(axum::middleware::from_fn(layers::logging::logging_middleware))
@@ -68,18 +69,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // TODO! : DELETE IS DEV ONLY
     service.route(router!(
             p,
-                "/api/calls": (AuthAccessLayer::allow_guests()) => {
+                "/api/calls": (AuthAccessLayer::allow_guests().with_revocation(revocation)) => {
                     delete "/rooms" -> delete_all_rooms ("calls.join")
 
                     any "/" -> connect ("calls.connect")
 
-                    get "/rooms" -> get_rooms ("calls.view")
+                    get "/rooms" -> get_rooms ("calls.view", FailureMode::Open)
                     get "/all_rooms" -> get_all_rooms ("calls.view.hidden")
                 }
             ).layer(
                 TraceLayer::new_for_http()
                     .make_span_with(DefaultMakeSpan::default().include_headers(true)),
         )
+            .layer(p.handle_layer())
             .layer(default_layer)
             .layer(cors)
             .with_state(state.clone())
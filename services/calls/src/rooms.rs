@@ -3,6 +3,8 @@ use std::{net::SocketAddr, ops::ControlFlow, time::Duration};
 use async_nats::Client;
 use axum::{extract::{ws::{CloseFrame, Message, Utf8Bytes, WebSocket}, ConnectInfo, Query, State, WebSocketUpgrade}, http::HeaderMap, response::IntoResponse, Extension, Json};
 use bytes::Bytes;
+use layers::rustperms::handle::Perms;
+use perms::groups::calls_perm;
 use chrono::Utc;
 use futures_util::{stream::SplitSink, SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
@@ -18,7 +20,11 @@ use anyhow::Result;
 
 pub async fn get_rooms(
     State(state): State<AppState>,
+    perms: Perms,
 ) -> impl IntoResponse {
+    if perms.can(&calls_perm("view.hidden")).await {
+        return get_all_rooms(State(state)).await.into_response();
+    }
     let Ok(rooms) = state.get_rooms().await else {
         return axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response();
     };
@@ -7,6 +7,9 @@ use serde::{Deserialize, Serialize};
 use shared::uuid::Uuid;
 
 use anyhow::Result;
+use rustperms::prelude::{RustpermsDelta, RustpermsOperation};
+use message_broker::headers::request_headers;
+use rustperms_nodes::{proto::WriteRequest, MasterClient};
 use tokio::sync::{mpsc, RwLock};
 use tracing::error;
 
@@ -19,6 +22,7 @@ pub struct AppState {
     pub inbox: String,
     pub signal_clients: Arc<RwLock<HashMap<String, mpsc::UnboundedSender<InnerSignal>>>>,
     pub jetstream: Arc<async_nats::jetstream::Context>,
+    pub rustperms_master: MasterClient,
 }
 
 pub(crate) trait JS {
@@ -33,7 +37,7 @@ impl JS for Arc<async_nats::jetstream::Context> {
         event: CallEvent
     ) -> Result<()> {
         let i = InnerSignal{event, rcv: crate::types::Receiver::All};
-        self.publish(ENV.CALLS_NATS_EVENT.clone(), serde_json::to_string(&i)?.into()).await?;
+        self.publish_with_headers(ENV.CALLS_NATS_EVENT.clone(), request_headers(), serde_json::to_string(&i)?.into()).await?;
         Ok(())
     }
     async fn send_to_room(
@@ -42,7 +46,7 @@ impl JS for Arc<async_nats::jetstream::Context> {
         room: String
     ) -> Result<()> {
         let i = InnerSignal{event, rcv: crate::types::Receiver::Room(room)};
-        self.publish(ENV.CALLS_NATS_EVENT.clone(), serde_json::to_string(&i)?.into()).await?;
+        self.publish_with_headers(ENV.CALLS_NATS_EVENT.clone(), request_headers(), serde_json::to_string(&i)?.into()).await?;
         Ok(())
     }
     async fn send_to_user(
@@ -51,7 +55,7 @@ impl JS for Arc<async_nats::jetstream::Context> {
         user: String
     ) -> Result<()> {
         let i = InnerSignal{event, rcv: crate::types::Receiver::User(user)};
-        self.publish(ENV.CALLS_NATS_EVENT.clone(), serde_json::to_string(&i)?.into()).await?;
+        self.publish_with_headers(ENV.CALLS_NATS_EVENT.clone(), request_headers(), serde_json::to_string(&i)?.into()).await?;
         Ok(())
     }
 }
@@ -144,9 +148,34 @@ impl AppState{
             signal_clients: Arc::new(RwLock::new(HashMap::new())),
             redis: RedisConn::default().await,
             jetstream: Arc::new(j),
+            rustperms_master: rustperms_nodes::connect_master("calls").await.expect("Can't connect to rustperms master!"),
             inbox
         }
     }
+
+    async fn write_perms(&self, ops: Vec<RustpermsOperation>) {
+        let Ok(d) = RustpermsDelta::from(ops).serialize_to_string() else {
+            error!("Failed to serialize rustperms delta");
+            return
+        };
+        self.rustperms_master.clone().write_changes(WriteRequest{serialized_delta: d}).await
+            .inspect_err(|e| error!("Failed to send rustperms delta: {e}")).ok();
+    }
+
+    // owner role is scoped to the room, guests are not rustperms users
+    async fn grant_room_owner(&self, room: &RoomRecord) {
+        if let User::Logged{guid} = &room.owner {
+            self.write_perms(perms::calls::grant_room_owner(guid, &room.guid)).await;
+        }
+    }
+
+    async fn revoke_room_roles(&self, room: &RoomRecord) {
+        let users = room.users.iter().chain([&room.owner])
+            .filter(|u| !u.is_guest())
+            .map(|u| u.guid())
+            .collect();
+        self.write_perms(perms::calls::revoke_room_roles(users, &room.guid)).await;
+    }
     pub async fn create_and_join_room(&self, room_name: String, private: bool, password: Option<String>, owner: User) -> RoomRecord {
         let guid = Uuid::new_v4().simple().to_string();
         let room = RoomRecord{
@@ -160,6 +189,7 @@ impl AppState{
             password: password
         };
         self.redis.hset(room_to_parent_key(&room.private), room_to_key(&room.guid), room.clone()).await.expect("Can't set room");
+        self.grant_room_owner(&room).await;
         self.jetstream.send_to_all(CallEvent::room_created(room.creator.clone(), room.clone().to_public())).await.expect("Can't send event");
         room
     }
@@ -170,6 +200,9 @@ impl AppState{
     }
 
     pub async fn delete_room(&self, room_guid: String, is_private: &bool) -> Result<()> {
+        if let Ok(Some(room)) = self.redis.hget::<RoomRecord>(room_to_parent_key(is_private), room_to_key(&room_guid)).await {
+            self.revoke_room_roles(&room).await;
+        }
         self.redis.hdel(room_to_parent_key(is_private), room_to_key(&room_guid)).await;
         self.jetstream.send_to_all(CallEvent::room_deleted(room_guid.clone())).await?;
         Ok(())
@@ -191,6 +224,7 @@ impl AppState{
             r.users.retain(|u| u.guid() != user.guid());
             if r.users.is_empty() {
                 self.redis.hdel(&pk, &rk).await?;
+                self.revoke_room_roles(&r).await;
                 self.jetstream.send_to_all(CallEvent::room_deleted(room_guid.clone())).await?;
             } else {
                 self.redis.hset(&pk, &rk, &r).await?;
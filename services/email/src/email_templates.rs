@@ -25,6 +25,9 @@ impl ToMessage for message_broker::email::types::Email {
             EmailKind::NewLogin {ip, user_agent} => default_message.subject("New login").singlepart(default_singlepart.body(format!("Ip: {ip} UserAgent: {user_agent}")))?,
             EmailKind::SuspiciousRefresh {ip, user_agent} => default_message.subject("Suspicious refresh").singlepart(default_singlepart.body(format!("Ip: {ip} UserAgent: {user_agent}")))?,
             EmailKind::RefreshRulesUpdate {ip, user_agent} => default_message.subject("Refresh rules update").singlepart(default_singlepart.body(format!("Someone updated yours refresh rules\nIp: {ip} UserAgent: {user_agent}")))?,
+            EmailKind::EmailChangeCode {code} => default_message.subject("Email change code").singlepart(default_singlepart.body(code))?,
+            EmailKind::EmailChangeRequested {new_email, cancel_link} => default_message.subject("Email change requested").singlepart(default_singlepart.body(format!("Your email is about to be changed to {new_email}\nNot you? Cancel it: {cancel_link}")))?,
+            EmailKind::RefreshTokenReuse {ip, user_agent} => default_message.subject("Session ended").singlepart(default_singlepart.body(format!("An old token of one of your sessions was used again, the session was ended. If it wasn't you, change your password\nIp: {ip} UserAgent: {user_agent}")))?,
         })
         
     }
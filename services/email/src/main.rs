@@ -1,5 +1,5 @@
 use lettre::Address;
-use shared::{env_config, utils::logger::init_logger};
+use shared::env_config;
 use tracing::{error, Instrument};
 
 
@@ -23,8 +23,8 @@ env_config!(
 
 #[tokio::main]
 async fn main() {
+    service::telemetry::init_telemetry();
     layers::make_unique_span!("email worker ", span); // todo: set global span
-    init_logger();
     let r = subscriber::run_subscriber().instrument(span).await;
     if let Err(e) = r {
         error!(e);
@@ -1,8 +1,8 @@
 use async_nats::jetstream::{self, consumer::PullConsumer};
 use bincode::decode_from_slice;
 use futures::StreamExt;
-use message_broker::email::types::Email;
-use tracing::error;
+use message_broker::{email::types::Email, headers::{continue_message_trace, message_request_id}};
+use tracing::{error, Instrument};
 
 use crate::{mailer::build_mailer, ENV};
 use anyhow::Result;
@@ -51,11 +51,13 @@ pub async fn run_subscriber() -> Result<(), async_nats::Error> {
         let mut messages = consumer.fetch().max_messages(15).messages().await?;
         while let Some(message) = messages.next().await {
             let message = message?;
-            'b : {
+            let span = tracing::info_span!("email", request_id = %message_request_id(&message));
+            continue_message_trace(&span, &message);
+            async { 'b : {
                 let (email, _) : (Email, usize)= ok_or!(decode_from_slice(&message.payload, bincode::config::standard()) ; "Can't deserialize message!" ; break 'b);
                 let msg = ok_or!(email.to_message() ; "Can't convert to message!" ; break 'b);
                 ok_or!(mailer.send_email(&msg).await ; "Can't send email!" ;  break 'b);
-            }
+            }}.instrument(span).await;
             message.ack().await?;
         }
     }
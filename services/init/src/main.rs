@@ -1,8 +1,9 @@
 use migration::MigratorTrait;
 use postgre_entities::user_data;
 use redis_utils::users::RedisUsers;
-use rustperms_nodes::proto::{rustperms_master_proto_client::RustpermsMasterProtoClient, WriteRequest};
-use sea_orm::EntityTrait;
+use rustperms::{api::policy::changes_to_delta, prelude::{AsyncManager, RustpermsDelta}};
+use rustperms_nodes::{connect_master_at, proto::WriteRequest};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use shared::utils::logger::init_logger;
 use tracing::info;
 
@@ -11,7 +12,10 @@ shared::env_config!(
     ".env" => ENV = Env {
         RUSTPERMS_MASTER_ADDR: String,
         RUSTPERMS_MASTER_PORT: u16,
-        
+        // only print the diff between defaults and live state
+        RUSTPERMS_POLICY_DRY_RUN: bool = false,
+        // remove groups which are not declared in defaults
+        RUSTPERMS_POLICY_PRUNE: bool = false,
 });
 
 #[tokio::main]
@@ -24,18 +28,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     shared::tracing::info!("Migrations done!");
     info!("Filling redis with users...");
     let redis = redis_utils::redis::RedisConn::default().await;
-    let users = user_data::Entity::find().all(&conn).await?;
+    // accounts pending deletion stay hidden
+    let users = user_data::Entity::find().filter(user_data::Column::DeletionScheduledAt.is_null()).all(&conn).await?;
     let users = users.into_iter().map(|u| (u.guid, u.uid)).collect::<Vec<_>>();
     redis.fill_users(users).await?;
     info!("Users filled!");
     shared::tracing::info!("Initializing default groups...");
-    let mut node = RustpermsMasterProtoClient
-        ::connect(format!("http://{}:{}", ENV.RUSTPERMS_MASTER_ADDR, ENV.RUSTPERMS_MASTER_PORT)).await
+    let mut node = connect_master_at(format!("http://{}:{}", ENV.RUSTPERMS_MASTER_ADDR, ENV.RUSTPERMS_MASTER_PORT), "init").await
         .inspect_err(|e|tracing::error!("Can't establish connection with master!: {e}"))?;
     let mut ops =  perms::groups::init_default();
+    ops.extend(perms::calls::init_default().into_iter());
+    ops.extend(perms::posts::init_default().into_iter());
     ops.extend(perms::groups::fill_with_defaults().into_iter());
-    let delta = rustperms::prelude::RustpermsDelta::from(ops);
-    node.write_changes(WriteRequest{serialized_delta: delta.serialize_to_string()?}).await?;
-    shared::tracing::info!("Default groups initialized!");
+    ops.extend(perms::calls::fill_with_defaults().into_iter());
+    ops.extend(perms::posts::fill_with_defaults().into_iter());
+    let desired = AsyncManager::from(RustpermsDelta::from(ops)).export_policy().await;
+
+    // converge live state to defaults instead of blindly reapplying them
+    let snapshot = node.get_snapshot(()).await?.into_inner();
+    let live = AsyncManager::from_serialized_string(&snapshot.serialized_users, &snapshot.serialized_groups)?
+        .export_policy().await;
+    let changes = desired.diff(&live, ENV.RUSTPERMS_POLICY_PRUNE);
+    for change in changes.iter() {
+        info!("{change}");
+    }
+    if changes.is_empty() {
+        shared::tracing::info!("Default groups are up to date!");
+    } else if ENV.RUSTPERMS_POLICY_DRY_RUN {
+        shared::tracing::info!("Dry run, {} changes are not applied", changes.len());
+    } else {
+        let delta = changes_to_delta(changes);
+        node.write_changes(WriteRequest{serialized_delta: delta.serialize_to_string()?}).await?;
+        shared::tracing::info!("Default groups initialized!");
+    }
     Ok(())
 }
@@ -7,15 +7,12 @@ use rustls::crypto::CryptoProvider;
 use tracing::info;
 use tracing_log::LogTracer;
 
-use tracing::level_filters::LevelFilter;
-use tracing_subscriber::{fmt, layer::SubscriberExt, Layer};
+use shared::utils::{logger::init_logger, request_id::{request_id_or_new, REQUEST_ID_HEADER}};
 
 
 fn main() -> anyhow::Result<()> {
     LogTracer::init()?;
-    let subscriber = tracing_subscriber::registry()
-        .with(fmt::layer().with_filter(LevelFilter::INFO));
-    tracing::subscriber::set_global_default(subscriber).ok();
+    init_logger();
     
     CryptoProvider::install_default(default_provider()).ok();
     let mut server = Server::new_with_opt_and_conf(None, ServerConf{
@@ -59,18 +56,26 @@ shared::env_config!(
 
 #[async_trait]
 impl ProxyHttp for Gateway {
-    type CTX = ();
-    fn new_ctx(&self) -> Self::CTX {}
+    /// Request id forwarded to the upstream
+    type CTX = String;
+    fn new_ctx(&self) -> Self::CTX {
+        String::new()
+    }
 
     async fn upstream_request_filter(
         &self,
         session: &mut Session,
         upstream_request: &mut RequestHeader,
-        _ctx: &mut Self::CTX,
+        ctx: &mut Self::CTX,
     ) -> Result<()>
     where
         Self::CTX: Send + Sync,
     {
+        // the entry point of a request, ids sent by clients are kept if they look sane
+        *ctx = request_id_or_new(session.req_header().headers.get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+        upstream_request
+            .insert_header(REQUEST_ID_HEADER, ctx.as_str())
+            .unwrap();
         let addr = session.client_addr().cloned().unwrap().as_inet().unwrap().ip().to_string();
         upstream_request
             .insert_header("X-Forwarded-For", addr.to_string())
@@ -155,6 +160,7 @@ impl ProxyHttp for Gateway {
             .response_written()
             .map_or(0, |resp| resp.status.as_u16());
         info!(
+            request_id = %ctx,
             "{} response code: {response_code}",
             self.request_summary(session, ctx)
         );
@@ -0,0 +1,96 @@
+use std::sync::{Arc, Mutex};
+
+use shared::{tokens::jwt::{ServiceTokenPayload, TokenEncoder}, utils::{request_id::{current_request_id, REQUEST_ID_HEADER}, telemetry::trace_headers}};
+use tonic::{metadata::{Ascii, MetadataKey, MetadataValue}, service::{interceptor::InterceptedService, Interceptor}, transport::Channel, Request, Status};
+
+use crate::{proto::{rustperms_master_proto_client::RustpermsMasterProtoClient, rustperms_replica_proto_client::RustpermsReplicaProtoClient}, ENV};
+
+pub const AUDIENCE: &str = "rustperms";
+pub const READ_SCOPE: &str = "rustperms.read";
+pub const WRITE_SCOPE: &str = "rustperms.write";
+
+pub type MasterClient = RustpermsMasterProtoClient<InterceptedService<Channel, ServiceAuth>>;
+pub type ReplicaClient = RustpermsReplicaProtoClient<InterceptedService<Channel, ServiceAuth>>;
+
+/// Authorization header and its expiration
+type CachedHeader = Option<(MetadataValue<Ascii>, i64)>;
+
+/// Client interceptor attaching a service token, the current request id and trace context, the token is renewed after half of its lifetime
+#[derive(Clone, Debug)]
+pub struct ServiceAuth {
+    service: String,
+    scopes: &'static [&'static str],
+    token: Arc<Mutex<CachedHeader>>,
+}
+
+impl ServiceAuth {
+    pub fn new(service: &str, scopes: &'static [&'static str]) -> Self {
+        Self {service: service.to_string(), scopes, token: Arc::default()}
+    }
+
+    fn header(&self) -> anyhow::Result<MetadataValue<Ascii>> {
+        let mut token = self.token.lock().map_err(|_| anyhow::anyhow!("Service token lock is poisoned"))?;
+        let now = chrono::Utc::now().timestamp();
+        if let Some((header, exp)) = token.as_ref() && exp - now > ENV.RUSTPERMS_SERVICE_TOKEN_LIFETIME / 2 {
+            return Ok(header.clone());
+        }
+        let payload = ServiceTokenPayload::new(&self.service, AUDIENCE, self.scopes, ENV.RUSTPERMS_SERVICE_TOKEN_LIFETIME);
+        let exp = payload.exp;
+        let encoded = TokenEncoder::encode_service(payload)?;
+        let header: MetadataValue<Ascii> = format!("Bearer {encoded}").parse()?;
+        *token = Some((header.clone(), exp));
+        Ok(header)
+    }
+}
+
+impl Interceptor for ServiceAuth {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let header = self.header().map_err(|e| Status::internal(format!("Can't issue service token: {e}")))?;
+        request.metadata_mut().insert("authorization", header);
+        if let Some(id) = current_request_id().and_then(|id| id.parse().ok()) {
+            request.metadata_mut().insert(REQUEST_ID_HEADER, id);
+        }
+        for (name, value) in trace_headers() {
+            if let (Ok(name), Ok(value)) = (MetadataKey::from_bytes(name.as_bytes()), value.parse()) {
+                request.metadata_mut().insert(name, value);
+            }
+        }
+        Ok(request)
+    }
+}
+
+/// Server interceptor, every call needs a service token with [`READ_SCOPE`].
+/// The token is put into request extensions for per method checks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequireService;
+
+impl Interceptor for RequireService {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let token = request.metadata()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .and_then(|token| TokenEncoder::decode_service(token, AUDIENCE))
+            .ok_or_else(|| Status::unauthenticated("Service token is missing or invalid"))?;
+        if !token.has_scope(READ_SCOPE) {
+            return Err(Status::permission_denied(format!("{} lacks {READ_SCOPE} scope", token.sub)));
+        }
+        request.extensions_mut().insert(token);
+        Ok(request)
+    }
+}
+
+/// Writers need [`WRITE_SCOPE`] and have to be listed in `RUSTPERMS_WRITERS`
+pub fn is_writer(token: &ServiceTokenPayload) -> bool {
+    token.has_scope(WRITE_SCOPE) && ENV.RUSTPERMS_WRITERS.split(',').any(|w| w.trim() == token.sub)
+}
+
+pub async fn connect_master_at(addr: String, service: &str) -> anyhow::Result<MasterClient> {
+    let channel = Channel::from_shared(addr)?.connect().await?;
+    Ok(RustpermsMasterProtoClient::with_interceptor(channel, ServiceAuth::new(service, &[READ_SCOPE, WRITE_SCOPE])))
+}
+
+pub async fn connect_replica_at(addr: String, service: &str) -> anyhow::Result<ReplicaClient> {
+    let channel = Channel::from_shared(addr)?.connect().await?;
+    Ok(RustpermsReplicaProtoClient::with_interceptor(channel, ServiceAuth::new(service, &[READ_SCOPE])))
+}
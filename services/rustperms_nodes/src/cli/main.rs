@@ -0,0 +1,295 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rustperms::prelude::*;
+use rustperms_nodes::{connect_master, connect_master_at, connect_replica, connect_replica_at, proto::{CheckPermRequest, SnapshotResponse, WriteRequest}, MasterClient, ReplicaClient};
+use serde_json::json;
+use anyhow::Result;
+
+mod output;
+mod snapshot;
+
+use output::{format_rule, Output};
+use snapshot::Snapshot;
+
+/// Subject of service tokens, has to be listed in `RUSTPERMS_WRITERS` for writes
+const SERVICE: &str = "rustperms-cli";
+
+/// Inspect and edit rustperms state through master and replica nodes
+#[derive(Parser)]
+#[command(name = "rustperms")]
+struct Cli {
+    /// Master address, e.g. http://127.0.0.1:50051 (defaults to RUSTPERMS_MASTER_ADDR/PORT)
+    #[arg(long, global = true)]
+    master: Option<String>,
+    /// Replica address (defaults to RUSTPERMS_REPLICA_ADDR/PORT)
+    #[arg(long, global = true)]
+    replica: Option<String>,
+    /// Node to read snapshots from
+    #[arg(long, global = true, value_enum, default_value_t = Source::Replica)]
+    source: Source,
+    /// Print JSON instead of tables
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Source {
+    Master,
+    Replica,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check permission on replica, use `-` as user for guests
+    Check { user: String, permission: String },
+    /// Show every user and group visited while resolving the permission
+    Explain { user: String, permission: String },
+    /// List users or show one of them
+    #[command(subcommand)]
+    Users(ListGet),
+    /// List groups or show one of them
+    #[command(subcommand)]
+    Groups(ListGet),
+    /// Set rules (enabled by default)
+    Grant {
+        #[command(flatten)]
+        target: Target,
+        #[arg(required = true)]
+        permissions: Vec<String>,
+        /// Set rules as disabled
+        #[arg(long)]
+        deny: bool,
+    },
+    /// Remove rules
+    Revoke {
+        #[command(flatten)]
+        target: Target,
+        #[arg(required = true)]
+        permissions: Vec<String>,
+    },
+    /// Add or remove group members
+    #[command(subcommand)]
+    Members(Members),
+    /// Dump state to file or load it back
+    #[command(subcommand)]
+    Snapshot(SnapshotCommand),
+}
+
+#[derive(Subcommand)]
+enum ListGet {
+    List,
+    Get { uid: String },
+}
+
+#[derive(Args)]
+#[group(required = true, multiple = false)]
+struct Target {
+    #[arg(long)]
+    user: Option<UserUID>,
+    #[arg(long)]
+    group: Option<GroupUID>,
+}
+
+#[derive(Subcommand)]
+enum Members {
+    Add {
+        group: GroupUID,
+        #[arg(required = true)]
+        users: Vec<UserUID>,
+        /// Resource prefix the membership is limited to
+        #[arg(long)]
+        scope: Option<String>,
+    },
+    Remove {
+        group: GroupUID,
+        #[arg(required = true)]
+        users: Vec<UserUID>,
+        #[arg(long)]
+        scope: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SnapshotCommand {
+    /// Write state to file, `.json` extension selects JSON, YAML otherwise
+    Dump { file: String },
+    /// Converge master state to the file
+    Load {
+        file: String,
+        /// Only print changes
+        #[arg(long)]
+        dry_run: bool,
+        /// Remove groups and users missing in the file
+        #[arg(long)]
+        prune: bool,
+    },
+}
+
+impl Cli {
+    async fn master(&self) -> Result<MasterClient> {
+        match &self.master {
+            Some(addr) => connect_master_at(addr.clone(), SERVICE).await,
+            None => connect_master(SERVICE).await,
+        }
+    }
+
+    async fn replica(&self) -> Result<ReplicaClient> {
+        match &self.replica {
+            Some(addr) => connect_replica_at(addr.clone(), SERVICE).await,
+            None => connect_replica(SERVICE).await,
+        }
+    }
+
+    async fn manager(&self, source: Source) -> Result<AsyncManager> {
+        let SnapshotResponse{serialized_users, serialized_groups} = match source {
+            Source::Master => self.master().await?.get_snapshot(()).await?.into_inner(),
+            Source::Replica => self.replica().await?.get_snapshot(()).await?.into_inner(),
+        };
+        AsyncManager::from_serialized_string(&serialized_users, &serialized_groups)
+    }
+
+    async fn write(&self, ops: Vec<RustpermsOperation>) -> Result<()> {
+        let delta = RustpermsDelta::from(ops);
+        let applied = delta.len();
+        self.master().await?.write_changes(WriteRequest{serialized_delta: delta.serialize_to_string()?}).await?;
+        let out = Output{json: self.json};
+        out.print(&json!({"applied": applied}), &["APPLIED"], vec![vec![applied.to_string()]])
+    }
+}
+
+fn guest_or(user: String) -> UserUID {
+    if user == "-" {String::new()} else {user}
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let out = Output{json: cli.json};
+    match &cli.command {
+        Command::Check { user, permission } => {
+            let reply = cli.replica().await?
+                .check_perm(CheckPermRequest{user_uid: guest_or(user.clone()), permission: permission.clone(), unset_policy: false}).await?
+                .into_inner();
+            out.print(
+                &json!({"user": user, "permission": permission, "allowed": reply.result}),
+                &["USER", "PERMISSION", "ALLOWED"],
+                vec![vec![user.clone(), permission.clone(), reply.result.to_string()]],
+            )?;
+        }
+        Command::Explain { user, permission } => {
+            let manager = cli.manager(cli.source).await?;
+            let explanation = manager.explain_perm(&guest_or(user.clone()), &permission.as_str().into_perm()).await;
+            let mut rows: Vec<Vec<String>> = explanation.steps.iter().map(|step| {
+                let source = match &step.source {
+                    ExplainSource::User(u) => format!("user {u}"),
+                    ExplainSource::Group(g) => format!("group {g}"),
+                };
+                vec![source, step.weight.to_string(), format_rule(step.rule)]
+            }).collect();
+            rows.push(vec!["result".to_string(), String::new(), format_rule(explanation.result)]);
+            out.print(&explanation, &["SOURCE", "WEIGHT", "RULE"], rows)?;
+        }
+        Command::Users(ListGet::List) => {
+            let snapshot = Snapshot::from_manager(&cli.manager(cli.source).await?).await;
+            let rows = snapshot.users.iter().map(|(uid, u)| vec![
+                uid.clone(),
+                u.groups.iter().cloned().collect::<Vec<_>>().join(","),
+                u.scoped_groups.values().map(|s| s.len()).sum::<usize>().to_string(),
+                u.rules.len().to_string(),
+            ]).collect();
+            out.print(&snapshot.users, &["USER", "GROUPS", "SCOPED", "RULES"], rows)?;
+        }
+        Command::Users(ListGet::Get { uid }) => {
+            let snapshot = Snapshot::from_manager(&cli.manager(cli.source).await?).await;
+            let user = snapshot.users.get(uid).ok_or_else(|| anyhow::anyhow!("User {uid} not found"))?;
+            let mut rows = vec![];
+            rows.extend(user.groups.iter().map(|g| vec!["group".to_string(), g.clone(), String::new()]));
+            for (g, scopes) in user.scoped_groups.iter() {
+                rows.extend(scopes.iter().map(|s| vec!["scoped".to_string(), g.clone(), s.clone()]));
+            }
+            rows.extend(user.rules.iter().map(|(r, e)| vec!["rule".to_string(), r.clone(), e.to_string()]));
+            out.print(user, &["KIND", "NAME", "VALUE"], rows)?;
+        }
+        Command::Groups(ListGet::List) => {
+            let snapshot = Snapshot::from_manager(&cli.manager(cli.source).await?).await;
+            let members = |g: &GroupUID| snapshot.users.values().filter(|u| u.groups.contains(g)).count();
+            let rows = snapshot.policy.groups.iter().map(|(name, g)| vec![
+                name.clone(),
+                g.weight.to_string(),
+                g.shards.to_string(),
+                g.inherits.iter().cloned().collect::<Vec<_>>().join(","),
+                g.rules.len().to_string(),
+                members(name).to_string(),
+            ]).collect();
+            out.print(&snapshot.policy, &["GROUP", "WEIGHT", "SHARDS", "INHERITS", "RULES", "MEMBERS"], rows)?;
+        }
+        Command::Groups(ListGet::Get { uid }) => {
+            let snapshot = Snapshot::from_manager(&cli.manager(cli.source).await?).await;
+            let group = snapshot.policy.groups.get(uid).ok_or_else(|| anyhow::anyhow!("Group {uid} not found"))?;
+            let members: Vec<&UserUID> = snapshot.users.iter().filter(|(_, u)| u.groups.contains(uid)).map(|(id, _)| id).collect();
+            let mut rows = vec![
+                vec!["weight".to_string(), group.weight.to_string(), String::new()],
+                vec!["shards".to_string(), group.shards.to_string(), String::new()],
+            ];
+            rows.extend(group.inherits.iter().map(|p| vec!["inherits".to_string(), p.clone(), String::new()]));
+            rows.extend(group.rules.iter().map(|(r, e)| vec!["rule".to_string(), r.clone(), e.to_string()]));
+            rows.extend(members.iter().map(|m| vec!["member".to_string(), m.to_string(), String::new()]));
+            out.print(&json!({"group": group, "members": members}), &["KIND", "NAME", "VALUE"], rows)?;
+        }
+        Command::Grant { target, permissions, deny } => {
+            let rules = permissions.iter().map(|p| (p.as_str().into_perm(), !deny)).collect();
+            let op = match (&target.user, &target.group) {
+                (Some(u), _) => RustpermsOperation::UserUpdatePerms(u.clone(), rules),
+                (_, Some(g)) => RustpermsOperation::GroupUpdatePerms(g.clone(), rules),
+                _ => unreachable!("clap requires a target"),
+            };
+            cli.write(vec![op]).await?;
+        }
+        Command::Revoke { target, permissions } => {
+            let paths = permissions.iter().map(|p| p.as_str().into_perm()).collect();
+            let op = match (&target.user, &target.group) {
+                (Some(u), _) => RustpermsOperation::UserRemovePerms(u.clone(), paths),
+                (_, Some(g)) => RustpermsOperation::GroupRemovePerms(g.clone(), paths),
+                _ => unreachable!("clap requires a target"),
+            };
+            cli.write(vec![op]).await?;
+        }
+        Command::Members(Members::Add { group, users, scope }) => {
+            let op = match scope {
+                Some(s) => RustpermsOperation::GroupAddScopedUsers(group.clone(), s.as_str().into_perm(), users.clone()),
+                None => RustpermsOperation::GroupAddUsers(group.clone(), users.clone()),
+            };
+            cli.write(vec![op]).await?;
+        }
+        Command::Members(Members::Remove { group, users, scope }) => {
+            let op = match scope {
+                Some(s) => RustpermsOperation::GroupRemoveScopedUsers(group.clone(), s.as_str().into_perm(), users.clone()),
+                None => RustpermsOperation::GroupRemoveUsers(group.clone(), users.clone()),
+            };
+            cli.write(vec![op]).await?;
+        }
+        Command::Snapshot(SnapshotCommand::Dump { file }) => {
+            let snapshot = Snapshot::from_manager(&cli.manager(cli.source).await?).await;
+            snapshot.to_file(file)?;
+            out.print(
+                &json!({"file": file, "groups": snapshot.policy.groups.len(), "users": snapshot.users.len()}),
+                &["FILE", "GROUPS", "USERS"],
+                vec![vec![file.clone(), snapshot.policy.groups.len().to_string(), snapshot.users.len().to_string()]],
+            )?;
+        }
+        Command::Snapshot(SnapshotCommand::Load { file, dry_run, prune }) => {
+            let desired = Snapshot::from_file(file)?;
+            // always diff against master, replicas may lag behind
+            let live = Snapshot::from_manager(&cli.manager(Source::Master).await?).await;
+            let (changes, ops) = desired.diff(&live, *prune);
+            let lines: Vec<String> = changes.iter().map(|c| c.to_string()).chain(ops.iter().map(|op| format!("{op:?}"))).collect();
+            let rows = lines.iter().map(|l| vec![l.clone()]).collect();
+            out.print(&json!({"changes": lines, "dry_run": dry_run}), &["CHANGE"], rows)?;
+            if !dry_run && !lines.is_empty() {
+                cli.write(desired.delta(&live, *prune).into_iter().collect()).await?;
+            }
+        }
+    }
+    Ok(())
+}
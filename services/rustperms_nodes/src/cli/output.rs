@@ -0,0 +1,46 @@
+use serde::Serialize;
+
+pub struct Output {
+    pub json: bool,
+}
+
+impl Output {
+    /// Prints `value` as JSON or rows as aligned table depending on `--json`
+    pub fn print<T: Serialize>(&self, value: &T, headers: &[&str], rows: Vec<Vec<String>>) -> anyhow::Result<()> {
+        if self.json {
+            println!("{}", serde_json::to_string_pretty(value)?);
+        } else {
+            print_table(headers, rows);
+        }
+        Ok(())
+    }
+}
+
+pub fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in rows.iter() {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+    let line = |cells: Vec<&str>| {
+        cells.iter().zip(widths.iter())
+            .map(|(c, w)| format!("{c:<w$}"))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    println!("{}", line(headers.to_vec()));
+    println!("{}", line(widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>().iter().map(|s| s.as_str()).collect()));
+    for row in rows.iter() {
+        println!("{}", line(row.iter().map(|s| s.as_str()).collect()));
+    }
+}
+
+pub fn format_rule(rule: Option<(bool, rustperms::prelude::MatchType)>) -> String {
+    match rule {
+        Some((enabled, m)) => format!("{enabled} ({m:?})"),
+        None => "-".to_string(),
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use rustperms::{api::policy::{changes_to_delta, Policy, PolicyChange}, prelude::*};
+use serde::{Deserialize, Serialize};
+
+// human readable dump of the whole state: group policy plus users with their rules and memberships
+// memberships in shards are folded into the base group, engine routes them back on load
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    #[serde(flatten)]
+    pub policy: Policy,
+    #[serde(default)]
+    pub users: BTreeMap<UserUID, UserSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct UserSnapshot {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rules: BTreeMap<String, bool>,
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub groups: BTreeSet<GroupUID>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub scoped_groups: BTreeMap<GroupUID, BTreeSet<String>>,
+}
+
+impl Snapshot {
+    pub async fn from_manager(manager: &AsyncManager) -> Self {
+        let policy = manager.export_policy().await;
+        let groups = manager.groups.read().await;
+        let base = |g: &GroupUID| groups.get(g).and_then(|g| g.get_shard_of()).unwrap_or(g).clone();
+        let users = manager.users.read().await.values()
+            .map(|u| (u.get_user_uid().clone(), UserSnapshot {
+                rules: u.get_perms().get_rules().into_iter().map(|(p, e)| (p.format(), e)).collect(),
+                groups: u.get_groups().iter().map(base).collect(),
+                scoped_groups: u.get_scoped_groups().iter()
+                    .map(|(g, scopes)| (g.clone(), scopes.iter().map(|s| s.format()).collect()))
+                    .collect(),
+            }))
+            .collect();
+        Self { policy, users }
+    }
+
+    pub fn from_file(path: &str) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        if path.ends_with(".json") {
+            Ok(serde_json::from_str(&content)?)
+        } else {
+            Ok(serde_yaml::from_str(&content)?)
+        }
+    }
+
+    pub fn to_file(&self, path: &str) -> anyhow::Result<()> {
+        let content = if path.ends_with(".json") {
+            serde_json::to_string_pretty(self)?
+        } else {
+            serde_yaml::to_string(self)?
+        };
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Group policy changes and user operations required to turn `live` into `self`
+    pub fn diff(&self, live: &Snapshot, prune: bool) -> (Vec<PolicyChange>, Vec<RustpermsOperation>) {
+        let changes = self.policy.diff(&live.policy, prune);
+        let empty = UserSnapshot::default();
+        let mut ops = vec![];
+        for (uid, desired) in self.users.iter() {
+            let current = live.users.get(uid).unwrap_or_else(|| {
+                ops.push(RustpermsOperation::UserCreate(uid.clone()));
+                &empty
+            });
+            let set: Vec<PermissionRule> = desired.rules.iter()
+                .filter(|(r, e)| current.rules.get(*r) != Some(e))
+                .map(|(r, e)| (r.as_str().into_perm(), *e))
+                .collect();
+            if !set.is_empty() {
+                ops.push(RustpermsOperation::UserUpdatePerms(uid.clone(), set));
+            }
+            let removed: Vec<PermissionPath> = current.rules.keys()
+                .filter(|r| !desired.rules.contains_key(*r))
+                .map(|r| r.as_str().into_perm())
+                .collect();
+            if !removed.is_empty() {
+                ops.push(RustpermsOperation::UserRemovePerms(uid.clone(), removed));
+            }
+            for g in desired.groups.difference(&current.groups) {
+                ops.push(RustpermsOperation::GroupAddUsers(g.clone(), vec![uid.clone()]));
+            }
+            for g in current.groups.difference(&desired.groups) {
+                ops.push(RustpermsOperation::GroupRemoveUsers(g.clone(), vec![uid.clone()]));
+            }
+            let none = BTreeSet::new();
+            for (g, scopes) in desired.scoped_groups.iter() {
+                for scope in scopes.difference(current.scoped_groups.get(g).unwrap_or(&none)) {
+                    ops.push(RustpermsOperation::GroupAddScopedUsers(g.clone(), scope.as_str().into_perm(), vec![uid.clone()]));
+                }
+            }
+            for (g, scopes) in current.scoped_groups.iter() {
+                for scope in scopes.difference(desired.scoped_groups.get(g).unwrap_or(&none)) {
+                    ops.push(RustpermsOperation::GroupRemoveScopedUsers(g.clone(), scope.as_str().into_perm(), vec![uid.clone()]));
+                }
+            }
+        }
+        if prune {
+            for uid in live.users.keys().filter(|u| !self.users.contains_key(*u)) {
+                ops.push(RustpermsOperation::UserRemove(uid.clone()));
+            }
+        }
+        (changes, ops)
+    }
+
+    pub fn delta(&self, live: &Snapshot, prune: bool) -> RustpermsDelta {
+        let (changes, ops) = self.diff(live, prune);
+        let mut delta = changes_to_delta(changes);
+        delta.push_many(ops);
+        delta
+    }
+}
+
@@ -227,6 +227,42 @@ where
                     .execute(e).await?;
                 Ok(())
             }
+            RustpermsOperation::GroupAddScopedUsers(g, scope, us) => {
+                sqlx::query(r#"
+                    INSERT INTO rustperms_user_scoped_groups (group_uid, scope, user_uid)
+                    SELECT $1, $2, users.user FROM
+                    UNNEST ($3::text[]) as users("user")
+                    ON CONFLICT (user_uid, group_uid, scope) DO nothing"#)
+                    .bind(g)
+                    .bind(scope.format())
+                    .bind(us)
+                    .execute(e).await?;
+                Ok(())
+            }
+            RustpermsOperation::GroupRemoveScopedUsers(g, scope, us) => {
+                sqlx::query(r#"
+                    DELETE FROM rustperms_user_scoped_groups
+                    USING UNNEST($3::text[]) as users("user")
+                    WHERE group_uid = $1 AND scope = $2 AND user_uid = users.user
+                "#)
+                    .bind(g)
+                    .bind(scope.format())
+                    .bind(us)
+                    .execute(e).await?;
+                Ok(())
+            }
+            RustpermsOperation::GroupSetShards { group_uid: g, shards } => {
+                sqlx::query("UPDATE rustperms_group SET shards = $2 WHERE group_uid = $1")
+                    .bind(g)
+                    .bind(shards as i32)
+                    .execute(e).await?;
+                Ok(())
+            }
+            RustpermsOperation::GroupReshard { group_uid: g, .. } => {
+                // never stored: routed into primitive operations before reaching storage
+                tracing::warn!("Unrouted reshard of {} reached storage, skipping", g);
+                Ok(())
+            }
         }
     }
 
@@ -273,18 +309,23 @@ where
         let s : Vec<GroupUserModel> = sqlx::query_as("select * from rustperms_user_groups").fetch_all(&self.conn).await?;
         dt.push_many(RustpermsOperation::from_batch(s));
 
+        // load scoped user <-> group relations
+        let s : Vec<GroupScopedUserModel> = sqlx::query_as("select * from rustperms_user_scoped_groups").fetch_all(&self.conn).await?;
+        dt.push_many(RustpermsOperation::from_batch(s));
+
         Ok(dt.into())
     }
 }
 
 #[tonic::async_trait]
 pub trait ReflectedApply<DB : SqlStore>{
-    async fn reflected_apply<'e>(&self, storage: &DB, actions: RustpermsDelta) -> Result<()>;
+    /// Applies changes locally and in storage, returns delta routed into primitive operations for replicas
+    async fn reflected_apply<'e>(&self, storage: &DB, actions: RustpermsDelta) -> Result<RustpermsDelta>;
 }
 
 #[tonic::async_trait]
 impl ReflectedApply<PostgreStorage> for AsyncManager {
-    async fn reflected_apply<'e>(&self, storage: &PostgreStorage, actions: RustpermsDelta) -> Result<()> 
+    async fn reflected_apply<'e>(&self, storage: &PostgreStorage, actions: RustpermsDelta) -> Result<RustpermsDelta> 
     where
         std::string::String: sqlx::Encode<'e, <PostgreStorage as SqlStore>::Database> + Type<<PostgreStorage as SqlStore>::Database>,
         i32: sqlx::Encode<'e, <PostgreStorage as SqlStore>::Database> + Type<<PostgreStorage as SqlStore>::Database>,
@@ -297,16 +338,21 @@ impl ReflectedApply<PostgreStorage> for AsyncManager {
         let mut tx = storage.begin_tx()
             .await
             .inspect_err(|e| error!("Can't begin transaction: {:?}", e))?; // todo: delay writes?
+        let mut routed = RustpermsDelta::new();
         for action in actions.into_iter() {
-            if AsyncManager::apply_action(&mut users, &mut groups, action.clone()) {
-                storage.sql_query(action, &mut *tx).await
-                    .inspect_err(|e| error!("Can't execute sql query for action: {:?}", e))
-                    .ok();
+            // routing depends on state, so each operation is routed right before it's applied
+            for action in AsyncManager::route_action(&groups, action) {
+                if AsyncManager::apply_action(&mut users, &mut groups, action.clone()) {
+                    storage.sql_query(action.clone(), &mut *tx).await
+                        .inspect_err(|e| error!("Can't execute sql query for action: {:?}", e))
+                        .ok();
+                }
+                routed.push(action);
             }
         }
         tx.commit().await
             .inspect_err(|e| error!("Can't commit changes to db: {:?}", e))
             .ok();
-        Ok(())
+        Ok(routed)
     }
 }
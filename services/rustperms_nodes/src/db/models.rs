@@ -27,12 +27,23 @@ impl From<UserModel> for RustpermsOperation {
 #[derive(FromRow, Debug)]
 pub struct GroupModel {
     group_uid: GroupUID,
-    weight: i32
+    weight: i32,
+    shards: i32
 }
 
-impl From<GroupModel> for RustpermsOperation {
-    fn from(value: GroupModel) -> Self {
-        RustpermsOperation::GroupCreate{group_uid: value.group_uid, weight: value.weight}
+impl FromBatch<GroupModel> for RustpermsOperation {
+    fn from_batch(batch: Vec<GroupModel>) -> Vec<RustpermsOperation> {
+        let mut ops = Vec::with_capacity(batch.len());
+        let mut sharded = vec![];
+        for model in batch {
+            if model.shards > 0 {
+                sharded.push(RustpermsOperation::GroupSetShards{group_uid: model.group_uid.clone(), shards: model.shards as u32});
+            }
+            ops.push(RustpermsOperation::GroupCreate{group_uid: model.group_uid, weight: model.weight});
+        }
+        // shards have to exist before base group points to them
+        ops.extend(sharded);
+        ops
     }
 }
 
@@ -120,5 +131,22 @@ impl FromBatch<GroupUserModel> for RustpermsOperation {
     }
 }
 
+#[derive(FromRow, Debug)]
+pub struct GroupScopedUserModel {
+    group_uid: GroupUID,
+    user_uid: UserUID,
+    scope: String
+}
 
-
+impl FromBatch<GroupScopedUserModel> for RustpermsOperation {
+    fn from_batch(batch: Vec<GroupScopedUserModel>) -> Vec<RustpermsOperation> {
+        let mut m : HashMap<(GroupUID, String), Vec<UserUID>> = HashMap::new();
+        for model in batch {
+            m
+                .entry((model.group_uid, model.scope))
+                .or_insert_with(|| Vec::with_capacity(1))
+                .push(model.user_uid);
+        }
+        m.into_iter().map(|((g, scope), v)|RustpermsOperation::GroupAddScopedUsers(g, PermissionPath::from_str(&scope), v)).collect()
+    }
+}
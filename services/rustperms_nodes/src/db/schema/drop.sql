@@ -1,6 +1,7 @@
 DROP TABLE rustperms_user CASCADE;
 DROP TABLE rustperms_user_permissions CASCADE;
 DROP TABLE rustperms_user_groups CASCADE;
+DROP TABLE rustperms_user_scoped_groups CASCADE;
 DROP TABLE rustperms_group CASCADE;
 DROP TABLE rustperms_group_permissions CASCADE;
 DROP TABLE rustperms_group_relations CASCADE;
@@ -1,7 +1,9 @@
 CREATE TABLE IF NOT EXISTS "rustperms_group" (
     group_uid TEXT PRIMARY KEY,
-    weight INTEGER NOT NULL
+    weight INTEGER NOT NULL,
+    shards INTEGER NOT NULL DEFAULT 0
 );
+ALTER TABLE "rustperms_group" ADD COLUMN IF NOT EXISTS shards INTEGER NOT NULL DEFAULT 0;
 CREATE INDEX IF NOT EXISTS "rustperms_group_group_uid_idx" ON "rustperms_group" (group_uid);
 
 CREATE TABLE IF NOT EXISTS "rustperms_group_permissions" (
@@ -27,4 +29,13 @@ CREATE TABLE IF NOT EXISTS "rustperms_user_groups" (
     PRIMARY KEY (user_uid, group_uid)
 );
 CREATE INDEX IF NOT EXISTS "rustperms_user_groups_user_uid_idx" ON "rustperms_user_groups" (user_uid);
-CREATE INDEX IF NOT EXISTS "rustperms_user_groups_group_uid_idx" ON "rustperms_user_groups" (group_uid);
\ No newline at end of file
+CREATE INDEX IF NOT EXISTS "rustperms_user_groups_group_uid_idx" ON "rustperms_user_groups" (group_uid);
+
+CREATE TABLE IF NOT EXISTS "rustperms_user_scoped_groups" (
+    user_uid TEXT NOT NULL REFERENCES "rustperms_user" (user_uid) ON DELETE CASCADE,
+    group_uid TEXT NOT NULL REFERENCES "rustperms_group" (group_uid) ON DELETE CASCADE,
+    scope TEXT NOT NULL,
+    PRIMARY KEY (user_uid, group_uid, scope)
+);
+CREATE INDEX IF NOT EXISTS "rustperms_user_scoped_groups_user_uid_idx" ON "rustperms_user_scoped_groups" (user_uid);
+CREATE INDEX IF NOT EXISTS "rustperms_user_scoped_groups_group_uid_idx" ON "rustperms_user_scoped_groups" (group_uid);
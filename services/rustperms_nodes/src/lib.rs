@@ -1,8 +1,10 @@
-use crate::proto::{rustperms_master_proto_client::RustpermsMasterProtoClient, rustperms_replica_proto_client::RustpermsReplicaProtoClient};
-
 pub mod service;
+pub mod auth;
 pub mod db;
 pub mod proto;
+pub mod metrics;
+
+pub use auth::{connect_master_at, connect_replica_at, MasterClient, ReplicaClient};
 
 
 shared::env_config!(
@@ -15,15 +17,22 @@ shared::env_config!(
         NATS_URL : String,
         NATS_PORT : String,
         DATABASE_URL : String,
+        RUSTPERMS_MASTER_METRICS_PORT : u16 = 9101,
+        RUSTPERMS_REPLICA_METRICS_PORT : u16 = 9102,
+        // log every n-th permission decision, 0 disables decision logs
+        RUSTPERMS_DECISION_LOG_EVERY : u64 = 0,
+        // seconds, service tokens are renewed after half of it
+        RUSTPERMS_SERVICE_TOKEN_LIFETIME : i64 = 10 * 60,
+        // services allowed to call WriteChanges, comma separated (calls writes room roles)
+        RUSTPERMS_WRITERS : String = "auth,init,calls,rustperms-cli".to_string(),
 });
 
 
-pub async fn connect_master() -> anyhow::Result<RustpermsMasterProtoClient<tonic::transport::Channel>> {
-    let addr = format!("http://{}:{}", ENV.RUSTPERMS_MASTER_ADDR, ENV.RUSTPERMS_MASTER_PORT);
-    Ok(RustpermsMasterProtoClient::connect(addr).await?)
+/// `service` becomes the subject of the service token
+pub async fn connect_master(service: &str) -> anyhow::Result<MasterClient> {
+    connect_master_at(format!("http://{}:{}", ENV.RUSTPERMS_MASTER_ADDR, ENV.RUSTPERMS_MASTER_PORT), service).await
 }
 
-pub async fn connect_replica() -> anyhow::Result<RustpermsReplicaProtoClient<tonic::transport::Channel>> {
-    let addr = format!("http://{}:{}", ENV.RUSTPERMS_REPLICA_ADDR, ENV.RUSTPERMS_REPLICA_PORT);
-    Ok(RustpermsReplicaProtoClient::connect(addr).await?)
-}
\ No newline at end of file
+pub async fn connect_replica(service: &str) -> anyhow::Result<ReplicaClient> {
+    connect_replica_at(format!("http://{}:{}", ENV.RUSTPERMS_REPLICA_ADDR, ENV.RUSTPERMS_REPLICA_PORT), service).await
+}
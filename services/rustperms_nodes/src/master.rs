@@ -1,17 +1,17 @@
 use std::sync::Arc;
 
 use async_nats::jetstream::Context;
-use ::shared::{env_config, utils::logger::init_logger};
+use ::shared::utils::request_id::grpc_span;
 use anyhow::Result;
 
-mod service;
-mod db;
-mod proto;
 
 use rustperms_nodes::ENV;
+use rustperms_nodes::auth::RequireService;
 
-use crate::service::master::*;
-use crate::{db::SqlStore, proto::rustperms_master_proto_server::RustpermsMasterProtoServer};
+use rustperms_nodes::db::{self, SqlStore};
+use rustperms_nodes::metrics::{serve_metrics, Metrics};
+use rustperms_nodes::proto::rustperms_master_proto_server::RustpermsMasterProtoServer;
+use rustperms_nodes::service::master::*;
 
 // env_config!(
 //     ".env" => ENV = Env {
@@ -35,7 +35,7 @@ pub async fn build_publisher() -> Result<Context> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_logger();
+    service::telemetry::init_telemetry();
     tracing::info!("Connecting to pg...");
 
     let addr = format!("[::1]:{}", ENV.RUSTPERMS_MASTER_PORT).parse()?;
@@ -49,10 +49,19 @@ async fn main() -> Result<()> {
     // fetch state
     let manager = storage.load_manager().await?;
 
+    let metrics = Metrics::new(ENV.RUSTPERMS_DECISION_LOG_EVERY);
+    let metrics_clone = metrics.clone();
+    tokio::spawn(async move {
+        if let Err(e) = serve_metrics(metrics_clone, ENV.RUSTPERMS_MASTER_METRICS_PORT).await {
+            tracing::error!("Metrics endpoint failed: {e}");
+        }
+    });
+
     tracing::info!("Starting master node!");
     // start grpc listener
     tonic::transport::Server::builder()
-        .add_service(RustpermsMasterProtoServer::new(MasterNode{manager, storage, nats_publisher, nats_event}))
+        .trace_fn(grpc_span)
+        .add_service(RustpermsMasterProtoServer::with_interceptor(MasterNode{manager, storage, nats_publisher, nats_event, metrics}, RequireService))
         .serve(addr)
         .await?;
     Ok(())
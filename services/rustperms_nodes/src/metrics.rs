@@ -0,0 +1,85 @@
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+
+use axum::{routing::get, Router};
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, Opts, Registry, TextEncoder};
+
+/// Prometheus metrics of a node, cheap to clone
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    /// checks by decision: allow / deny / unset
+    pub checks: IntCounterVec,
+    pub check_latency: Histogram,
+    pub check_depth: Histogram,
+    pub check_visited: Histogram,
+    /// writes by status: ok / error
+    pub writes: IntCounterVec,
+    pub write_ops: IntCounter,
+    pub write_latency: Histogram,
+    pub deltas_applied: IntCounter,
+    decisions: Arc<AtomicU64>,
+    log_every: u64,
+}
+
+impl Metrics {
+    pub fn new(log_every: u64) -> Self {
+        let registry = Registry::new_custom(Some("rustperms".to_string()), None).expect("Valid registry prefix");
+        let checks = IntCounterVec::new(Opts::new("checks_total", "Permission checks by decision"), &["decision"]).unwrap();
+        let check_latency = Histogram::with_opts(
+            HistogramOpts::new("check_duration_seconds", "Permission check latency")
+                .buckets(vec![0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.05])
+        ).unwrap();
+        let check_depth = Histogram::with_opts(
+            HistogramOpts::new("check_depth", "Deepest group level visited by a check")
+                .buckets(vec![0.0, 1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0])
+        ).unwrap();
+        let check_visited = Histogram::with_opts(
+            HistogramOpts::new("check_visited_groups", "Groups visited by a check")
+                .buckets(vec![0.0, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0])
+        ).unwrap();
+        let writes = IntCounterVec::new(Opts::new("writes_total", "Write requests by status"), &["status"]).unwrap();
+        let write_ops = IntCounter::new("write_operations_total", "Operations applied by writes").unwrap();
+        let write_latency = Histogram::with_opts(
+            HistogramOpts::new("write_duration_seconds", "Write request latency")
+        ).unwrap();
+        let deltas_applied = IntCounter::new("deltas_applied_total", "Deltas applied from the write stream").unwrap();
+
+        registry.register(Box::new(checks.clone())).unwrap();
+        registry.register(Box::new(check_latency.clone())).unwrap();
+        registry.register(Box::new(check_depth.clone())).unwrap();
+        registry.register(Box::new(check_visited.clone())).unwrap();
+        registry.register(Box::new(writes.clone())).unwrap();
+        registry.register(Box::new(write_ops.clone())).unwrap();
+        registry.register(Box::new(write_latency.clone())).unwrap();
+        registry.register(Box::new(deltas_applied.clone())).unwrap();
+
+        Self {
+            registry, checks, check_latency, check_depth, check_visited,
+            writes, write_ops, write_latency, deltas_applied,
+            decisions: Arc::new(AtomicU64::new(0)),
+            log_every,
+        }
+    }
+
+    /// True for every `log_every`-th decision
+    pub fn sample_decision(&self) -> bool {
+        self.log_every != 0 && self.decisions.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.log_every)
+    }
+
+    pub fn encode(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer).ok();
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+/// Serves `/metrics` in Prometheus text format
+pub async fn serve_metrics(metrics: Metrics, port: u16) -> anyhow::Result<()> {
+    let app = Router::new().route("/metrics", get(move || {
+        let metrics = metrics.clone();
+        async move { metrics.encode() }
+    }));
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    axum::serve(listener, app).await?;
+    Ok(())
+}
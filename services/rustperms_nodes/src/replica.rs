@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use rustperms::prelude::AsyncManager;
 use rustperms_nodes::proto::SnapshotResponse;
-use ::shared::{utils::logger::init_logger};
+use ::shared::utils::request_id::grpc_span;
 
 use anyhow::Result;
 
@@ -11,11 +11,14 @@ use rustperms_nodes::proto::rustperms_replica_proto_server::RustpermsReplicaProt
 use rustperms_nodes::db::SqlStore;
 use rustperms_nodes::service::replica::{start_nats_event_listener, ReplicaNode};
 
-use rustperms_nodes::{connect_master, connect_replica, ENV};
+use rustperms_nodes::metrics::{serve_metrics, Metrics};
+use rustperms_nodes::{auth::RequireService, connect_master, connect_replica, ENV};
+
+const SERVICE: &str = "rustperms-replica";
 
 async fn try_get_manager_from_replica() -> Result<AsyncManager> {
     tracing::info!("Trying to get manager from replica!");
-    let mut replica_conn = connect_replica().await
+    let mut replica_conn = connect_replica(SERVICE).await
         .inspect_err(|e|tracing::warn!("Can't establish connection with another replica, am i first?: {e}"))?;
     let SnapshotResponse{serialized_users, serialized_groups} = replica_conn
         .get_snapshot(()).await
@@ -27,7 +30,7 @@ async fn try_get_manager_from_replica() -> Result<AsyncManager> {
 
 async fn try_get_manager_from_master() -> Result<AsyncManager> {
     tracing::info!("Trying to get manager from master!");
-    let mut replica_conn = connect_master().await
+    let mut replica_conn = connect_master(SERVICE).await
         .inspect_err(|e|tracing::error!("Can't establish connection with master!: {e}"))?;
     let SnapshotResponse{serialized_users, serialized_groups} = replica_conn
         .get_snapshot(()).await
@@ -39,7 +42,7 @@ async fn try_get_manager_from_master() -> Result<AsyncManager> {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_logger();
+    service::telemetry::init_telemetry();
     let addr = format!("[::1]:{}", ENV.RUSTPERMS_REPLICA_PORT).parse()?;
 
     let manager = 'a: {
@@ -59,9 +62,18 @@ async fn main() -> Result<()> {
     let nats_url = format!("nats://{}:{}", ENV.NATS_URL, ENV.NATS_PORT);
     
 
+    let metrics = Metrics::new(ENV.RUSTPERMS_DECISION_LOG_EVERY);
+    let metrics_clone = metrics.clone();
+    tokio::spawn(async move {
+        if let Err(e) = serve_metrics(metrics_clone, ENV.RUSTPERMS_REPLICA_METRICS_PORT).await {
+            tracing::error!("Metrics endpoint failed: {e}");
+        }
+    });
+
     let manager_clone = manager.clone();
+    let metrics_clone = metrics.clone();
     tokio::spawn(async move {
-        let result = start_nats_event_listener(manager_clone, nats_url, ENV.PERM_WRITE_NATS_EVENT.clone()).await;
+        let result = start_nats_event_listener(manager_clone, metrics_clone, nats_url, ENV.PERM_WRITE_NATS_EVENT.clone()).await;
         if let Err(e) = result {
             tracing::error!("NATS consumer failed: {e}");
             std::process::exit(1);
@@ -70,7 +82,8 @@ async fn main() -> Result<()> {
 
     // start grpc listener
     tonic::transport::Server::builder()
-        .add_service(RustpermsReplicaProtoServer::new(ReplicaNode{manager}))
+        .trace_fn(grpc_span)
+        .add_service(RustpermsReplicaProtoServer::with_interceptor(ReplicaNode{manager, metrics}, RequireService))
         .serve(addr)
         .await?;
     Ok(())
@@ -1,36 +1,60 @@
 use std::fmt::Display;
 use std::sync::Arc;
+use std::time::Instant;
 
 use async_nats::jetstream::Context;
 use rustperms::prelude::{AsyncManager, RustpermsDelta};
 use tonic::{Request, Response, Status};
 use anyhow::Result;
 
+use ::shared::{tokens::jwt::ServiceTokenPayload, utils::request_id::{request_id_or_new, with_request_id, REQUEST_ID_HEADER}};
+use message_broker::headers::request_headers;
+use crate::auth::is_writer;
 use crate::db::{PostgreStorage, ReflectedApply, SqlStore};
+use crate::metrics::Metrics;
 use crate::proto::rustperms_master_proto_server::RustpermsMasterProto;
 use crate::proto::{SnapshotResponse, WriteRequest};
 
-#[derive(Debug)]
 pub struct MasterNode<T : SqlStore> {
     pub manager: AsyncManager,
     pub storage: T,
     pub nats_publisher: Arc<Context>,
-    pub nats_event: String
+    pub nats_event: String,
+    pub metrics: Metrics,
 }
 
 
+impl MasterNode<PostgreStorage> {
+    async fn write(&self, request: WriteRequest) -> Result<Response<()>, Status> {
+        let WriteRequest{serialized_delta} = request;
+        let delta = RustpermsDelta::deserialize_from_string(&serialized_delta).map_status(Status::internal(""))?;
+        let ops = delta.len();
+        let routed = self.manager.reflected_apply(&self.storage, delta).await.map_status(Status::internal(""))?;
+        let serialized_delta = routed.serialize_to_string().map_status(Status::internal("Can't encode routed delta"))?;
+        self.metrics.write_ops.inc_by(ops as u64);
+        // todo!: revert changes on error
+        self.nats_publisher.publish_with_headers(self.nats_event.clone(), request_headers(), serialized_delta.into()).await.map_status(Status::internal("Can't send nats event! The changes applied to db will not be reflected on replicas!"))?;
+        Ok(Response::new(()))
+    }
+}
+
 #[tonic::async_trait]
 impl RustpermsMasterProto for MasterNode<PostgreStorage> {
     async fn write_changes(
         &self,
         request: Request<WriteRequest>,
     ) -> Result<Response<()>, Status> {
-        let WriteRequest{serialized_delta} = request.into_inner();
-        let delta = RustpermsDelta::deserialize_from_string(&serialized_delta).map_status(Status::internal(""))?;
-        self.manager.reflected_apply(&self.storage, delta).await.map_status(Status::internal(""))?;
-        // todo!: revert changes on error
-        self.nats_publisher.publish(self.nats_event.clone(), serialized_delta.into()).await.map_status(Status::internal("Can't send nats event! The changes applied to db will not be reflected on replicas!"))?;
-        Ok(Response::new(()))
+        let writer = request.extensions().get::<ServiceTokenPayload>().ok_or_else(|| Status::unauthenticated("Service token is missing"))?;
+        if !is_writer(writer) {
+            tracing::warn!("Service {} tried to write changes", writer.sub);
+            return Err(Status::permission_denied(format!("{} is not allowed to write changes", writer.sub)));
+        }
+        let request_id = request_id_or_new(request.metadata().get(REQUEST_ID_HEADER).and_then(|v| v.to_str().ok()));
+        let start = Instant::now();
+        let result = with_request_id(request_id, self.write(request.into_inner())).await;
+        self.metrics.write_latency.observe(start.elapsed().as_secs_f64());
+        self.metrics.writes.with_label_values(&[if result.is_ok() {"ok"} else {"error"}]).inc();
+        result
     }
     async fn get_snapshot(
         &self,
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use async_nats::jetstream;
 use rustperms::prelude::AsyncManager;
@@ -8,22 +8,56 @@ use crate::proto::{rustperms_replica_proto_server::RustpermsReplicaProto};
 use crate::proto::SnapshotResponse;
 use crate::proto::CheckPermRequest;
 use crate::proto::CheckPermReply;
+use crate::proto::{CheckPermsRequest, CheckPermsReply};
+use crate::metrics::Metrics;
 use rustperms::prelude::*;
 
-#[derive(Debug)]
 pub struct ReplicaNode {
     pub manager: Arc<AsyncManager>,
+    pub metrics: Metrics,
+}
+
+impl ReplicaNode {
+    async fn decide(&self, user_uid: &UserUID, permission: &str, unset_policy: bool) -> bool {
+        let start = Instant::now();
+        let (mut visited, mut depth) = (0, 0);
+        let result = self.manager.check_perm_with(user_uid, &PermissionPath::from_str(permission), |step| {
+            if let ExplainSource::Group(_) = step.source {visited += 1}
+            depth = depth.max(step.depth);
+        }).await;
+        let elapsed = start.elapsed();
+        let decision = match result {
+            Some((true, _)) => "allow",
+            Some((false, _)) => "deny",
+            None => "unset",
+        };
+        self.metrics.checks.with_label_values(&[decision]).inc();
+        self.metrics.check_latency.observe(elapsed.as_secs_f64());
+        self.metrics.check_depth.observe(depth as f64);
+        self.metrics.check_visited.observe(visited as f64);
+        if self.metrics.sample_decision() {
+            tracing::info!(user = %user_uid, %permission, decision, visited, depth, latency_us = elapsed.as_micros() as u64, "Permission decision");
+        }
+        result.unwrap_or((unset_policy, MatchType::Exact)).0
+    }
 }
 
 #[tonic::async_trait]
 impl RustpermsReplicaProto for ReplicaNode {
     async fn check_perm(&self, request: Request<CheckPermRequest>) -> Result<Response<CheckPermReply>, Status> {
         let CheckPermRequest { user_uid, permission, unset_policy } = request.into_inner();
-        let result = self.manager.check_perm(&user_uid, &PermissionPath::from_str(&permission)).await;
         Ok(Response::new(CheckPermReply {
-            result: result.unwrap_or((unset_policy, MatchType::Exact)).0
+            result: self.decide(&user_uid, &permission, unset_policy).await
         }))
     }
+    async fn check_perms(&self, request: Request<CheckPermsRequest>) -> Result<Response<CheckPermsReply>, Status> {
+        let CheckPermsRequest { user_uid, permissions, unset_policy } = request.into_inner();
+        let mut results = Vec::with_capacity(permissions.len());
+        for permission in permissions {
+            results.push(self.decide(&user_uid, &permission, unset_policy).await);
+        }
+        Ok(Response::new(CheckPermsReply { results }))
+    }
     async fn get_snapshot(
         &self,
         _request: Request<()>,
@@ -39,8 +73,10 @@ impl RustpermsReplicaProto for ReplicaNode {
 use anyhow::{anyhow, Result};
 use futures::{StreamExt};
 use std::{str::from_utf8};
+use message_broker::headers::{continue_message_trace, message_request_id};
+use tracing::Instrument;
 
-pub async fn start_nats_event_listener(manager: Arc<AsyncManager>, nats_url: String, event: String) -> Result<(), async_nats::Error> {
+pub async fn start_nats_event_listener(manager: Arc<AsyncManager>, metrics: Metrics, nats_url: String, event: String) -> Result<(), async_nats::Error> {
     let client = async_nats::connect(nats_url).await?;
     let inbox = client.new_inbox();
     let jetstream = jetstream::new(client);
@@ -62,12 +98,19 @@ pub async fn start_nats_event_listener(manager: Arc<AsyncManager>, nats_url: Str
     let mut messages = consumer.messages().await?;
     while let Some(message) = messages.next().await {
         let message = message?;
+        let span = tracing::info_span!("delta", request_id = %message_request_id(&message));
+        continue_message_trace(&span, &message);
         let payload = from_utf8(&message.payload)?;
-        tracing::info!("New msg: {payload}");
-        match RustpermsDelta::deserialize_from_string(payload) {
-            Ok(actions) => manager.apply(actions).await,
-            Err(e) => tracing::error!("Can't deserialize delta from string: {}", e),
-        }
+        async {
+            tracing::info!("New msg: {payload}");
+            match RustpermsDelta::deserialize_from_string(payload) {
+                Ok(actions) => {
+                    manager.apply(actions).await;
+                    metrics.deltas_applied.inc();
+                },
+                Err(e) => tracing::error!("Can't deserialize delta from string: {}", e),
+            }
+        }.instrument(span).await;
         message.ack().await?;
     }
     Err(anyhow!("Nats event loop ended!").into())
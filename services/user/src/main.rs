@@ -1,11 +1,11 @@
 use std::{sync::Arc, time::Duration};
 
 use axum::{error_handling::HandleErrorLayer, middleware::from_extractor, routing::{get, put}, Router};
-use layers::{auth::AuthAccessLayer, rustperms::{ExtractPath, PermissionMiddlewareBuilder}};
+use layers::{auth::AuthAccessLayer, revocation::RevocationStore, rustperms::{ExtractPath, PermissionMiddlewareBuilder}};
 use minio::s3::{creds::StaticProvider, Client};
 use redis_utils::{redis::RedisConn, redis_cache::RedisCache};
 use reqwest::StatusCode;
-use shared::{router, utils::logger::init_logger};
+use shared::router;
 use tower::{timeout::TimeoutLayer, ServiceBuilder};
 use tower_governor::{governor::GovernorConfigBuilder, key_extractor::SmartIpKeyExtractor};
 use tower_http::{catch_panic::CatchPanicLayer, cors::{Any, CorsLayer}};
@@ -39,7 +39,6 @@ use crate::profile::set_profile_background;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    init_logger();
     let mut service = service::Service::begin();
     let client = Client::new(
         ENV.MINIO_URL.parse()?,
@@ -93,14 +92,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .allow_methods(Any)
         .allow_headers(Any)
         .max_age(Duration::from_secs(3600));
-    let replica = rustperms_nodes::connect_replica().await?;
-    let p = PermissionMiddlewareBuilder::new(replica);
+    let replica = rustperms_nodes::connect_replica("user").await?;
+    let p = PermissionMiddlewareBuilder::new(replica).with_cache().await?;
+    let revocation = RevocationStore::connect().await?;
 
 
     service.route(
         router!(
             p,
-            "/api/user/edit" : (AuthAccessLayer::only_authorized()) => {
+            "/api/user/edit" : (AuthAccessLayer::only_authorized().with_revocation(revocation.clone())) => {
                 put "/profile/bg" -> set_profile_background ("user.profile.edit.{from_access}.bg")
                 put "/profile/bg_url" -> set_profile_background_url ("user.profile.edit.{from_access}.bg")
                 put "/profile/theme" -> set_profile_theme ("user.profile.edit.{from_access}.theme")
@@ -113,13 +113,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 put "/miniprofile/bg_url" -> set_miniprofile_background_url ("user.miniprofile.edit.{from_access}.bg")
                 put "/miniprofile/theme" -> set_miniprofile_theme ("user.miniprofile.edit.{from_access}.theme")
             }
-            "/api/user" : (AuthAccessLayer::allow_guests()) => {
+            "/api/user" : (AuthAccessLayer::allow_guests().with_revocation(revocation.clone())) => {
                 get "/guids" -> get_all_users
                 get "/profile/{guid}" -> get_profile ("user.profile.view.{guid}")
                 get "/miniprofile/{guid}" -> get_miniprofile 
             }
         )
         .layer(from_extractor::<ExtractPath>())
+        .layer(p.handle_layer())
         .layer(cors)
         .layer(default_layer)
         .with_state(state)
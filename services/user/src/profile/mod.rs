@@ -1,11 +1,12 @@
 use anyhow::Result;
 use axum::{body::Body, extract::{Multipart, State}, response::{IntoResponse, Response}, Extension, Json};
-use layers::rustperms::{ExtractPath, ExtractedPathKV};
+use layers::rustperms::{filter::{FieldFilter, FieldPermissions}, ExtractedPathKV};
 use mime::Mime;
 use minio::s3::{response::PutObjectResponse, types::S3Api};
 use redis_utils::users::RedisUsers;
 use reqwest::StatusCode;
-use shared::{tokens::jwt::AccessTokenPayload, utils::app_err::AppErr, uuid};
+use perms::user::profile::profile_view_restricted_perm_postfix;
+use shared::{tokens::jwt::AccessTokenPayload, utils::IntoKey, uuid};
 use tonic::IntoStreamingRequest;
 use tracing::info;
 
@@ -84,12 +85,21 @@ make_upload_handlers! {
 pub async fn get_profile(
     State(state): State<AppState>,
     Extension(path): Extension<ExtractedPathKV>,
+    filter: FieldFilter,
 ) -> Result<impl IntoResponse, Response<Body>> {
     let Some(guid) = path.0.get("guid") else {
         return Ok(StatusCode::NOT_FOUND.into_response());
     };
     let user_guid = uuid::Uuid::parse_str(guid).map_err(|_| guid.clone());
-    let p = state.get_profile_by_user(user_guid).await?;
+    let (user_guid, p) = state.get_profile_by_user(user_guid).await?;
+    let mut fields = FieldPermissions::new();
+    if p.restrict_visibility {
+        let key = user_guid.into_key();
+        for field in ["status", "background", "encoded_theme"] {
+            fields = fields.field(field, profile_view_restricted_perm_postfix(&key, field));
+        }
+    }
+    let p = filter.apply(&p, fields).await.map_err(|s| s.into_response())?;
     Ok(Json(p).into_response())
 }
 pub async fn get_miniprofile(
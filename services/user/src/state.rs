@@ -1,4 +1,4 @@
-use axum::{body::Body, extract::Multipart, http::Response, response::IntoResponse};
+use axum::{body::Body, extract::Multipart, http::Response, response::{IntoResponse, Redirect}};
 use mime::Mime;
 use minio::s3::types::S3Api;
 use postgre_entities::user_data;
@@ -7,7 +7,7 @@ use postgre_entities::user_profile;
 use rand::rand_core::le;
 use redis_utils::redis::RedisConn;
 use redis_utils::redis_cache::RedisCache;
-use redis_utils::users::RedisUsers;
+use redis_utils::users::{RedisUsers, PROFILE_CACHE_PREFIX};
 use reqwest::StatusCode;
 use sea_orm::DatabaseConnection;
 use futures_util::{StreamExt, TryStreamExt};
@@ -111,7 +111,7 @@ pub fn validate_catbox_url(raw: &str) -> Option<()> {
 }
 
 
-const PROFILE_PREFIX : &str = "PROFILE:";
+const PROFILE_PREFIX : &str = PROFILE_CACHE_PREFIX;
 fn profile_key(user_guid: Uuid) -> String {
     format!("{}{}", PROFILE_PREFIX, user_guid.simple())
 }
@@ -127,6 +127,8 @@ pub struct Profile {
     pub background: Option<String>,
     pub status: Option<String>,
     pub avatar: Option<String>,
+    #[serde(default)]
+    pub restrict_visibility: bool,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -242,17 +244,33 @@ impl AppState {
     
 
 
-    pub async fn get_profile_by_user(&self, uid_or_guid: Result<Uuid, String>) -> Result<Profile, Response<Body>> {
-        match uid_or_guid {
+    /// Redirect from a recently changed uid to the current one, 404 otherwise.
+    /// The location is relative, so only the last path segment gets replaced.
+    async fn redirect_old_uid(&self, uid: &str) -> Response<Body> {
+        let current = match self.cache.get_uid_redirect(uid).await.trough_app_err() {
+            Ok(Some(guid)) => self.cache.get_user_uid(&guid).await.trough_app_err(),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        };
+        match current {
+            Ok(Some(current)) => Redirect::temporary(&current).into_response(),
+            Ok(None) => StatusCode::NOT_FOUND.into_response(),
+            Err(e) => e,
+        }
+    }
+
+    pub async fn get_profile_by_user(&self, uid_or_guid: Result<Uuid, String>) -> Result<(Uuid, Profile), Response<Body>> {
+        let guid = match uid_or_guid {
             Ok(guid) => {
                 if let None = self.cache.get_user_uid(&guid).await.trough_app_err()? {return Err(StatusCode::NOT_FOUND.into_response())};
-                self.get_profile(guid).await
+                guid
             }
             Err(uid) => {
-                let Some(guid) = self.cache.get_user_guid(&uid).await.trough_app_err()? else {return Err(StatusCode::NOT_FOUND.into_response())};
-                self.get_profile(guid).await
+                let Some(guid) = self.cache.get_user_guid(&uid).await.trough_app_err()? else {return Err(self.redirect_old_uid(&uid).await)};
+                guid
             }
-        }
+        };
+        Ok((guid, self.get_profile(guid).await?))
     }
 
     pub async fn get_profile(&self, user_guid: Uuid) -> Result<Profile, Response<Body>> {
@@ -273,6 +291,7 @@ impl AppState {
                 background: p.background,
                 status: p.status,
                 avatar: ud.avatar,
+                restrict_visibility: p.restrict_visibility.unwrap_or(false),
             };
             self.set_profile_cache(user_guid, profile.clone()).await?;
             Ok(profile)
@@ -290,7 +309,7 @@ impl AppState {
                 self.get_miniprofile(guid).await
             }
             Err(uid) => {
-                let Some(guid) = self.cache.get_user_guid(&uid).await.trough_app_err()? else {return Err(StatusCode::NOT_FOUND.into_response())};
+                let Some(guid) = self.cache.get_user_guid(&uid).await.trough_app_err()? else {return Err(self.redirect_old_uid(&uid).await)};
                 self.get_miniprofile(guid).await
             }
         }